@@ -0,0 +1,416 @@
+// ======================== cli.rs ========================
+// Command-line interface
+
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::PartialConfig;
+use crate::device_manager::DeviceSpec;
+use crate::disks::DiskFilter;
+use crate::flipper_manager::AdapterSelector;
+use crate::helpers::parse_seconds;
+use crate::memory::RamUsed;
+use crate::network::InterfaceFilter;
+use crate::processes::parse_process_count;
+use crate::protocol::MetricFields;
+use crate::sensors::SensorFilter;
+use crate::system_info::GpuSelector;
+use crate::wire::Encoding;
+use uuid::Uuid;
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Stream system stats to a Flipper Zero over Bluetooth LE"
+)]
+pub struct Cli {
+    /// Config file to use instead of the default one
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// The subcommand to run; no subcommand means `run` with defaults
+    pub fn command(self) -> Command {
+        self.command.unwrap_or(Command::Run(RunArgs::default()))
+    }
+}
+
+impl Command {
+    /// Settings given on the command line, applied over the config file and environment
+    pub fn overrides(&self) -> PartialConfig {
+        match self {
+            Command::Scan(args) => args.bluetooth.overrides(),
+            Command::Run(args) => args.overrides(),
+            Command::Dump(args) => args.overrides(),
+            Command::Demo(_) | Command::Once(_) => PartialConfig::default(),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// List Bluetooth LE devices in range with their signal strength and services
+    Scan(ScanArgs),
+    /// Connect to Flipper(s) and stream system stats (the default)
+    Run(RunArgs),
+    /// Print system stats to the console without a Flipper
+    Demo(DemoArgs),
+    /// Print one sample as the bytes that would be sent to a Flipper
+    Dump(DumpArgs),
+    /// Print one sample and exit
+    Once(OnceArgs),
+}
+
+/// Options shared by every command that talks to Bluetooth
+#[derive(Args, Debug, Default, Clone)]
+pub struct BluetoothArgs {
+    /// Bluetooth adapter to use (index or part of its name)
+    #[arg(long, value_name = "INDEX|NAME")]
+    pub adapter: Option<AdapterSelector>,
+
+    /// How long to scan for devices, in seconds
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub scan_duration: Option<Duration>,
+
+    /// Only consider devices whose name contains this; repeat for several
+    #[arg(long, value_name = "TEXT")]
+    pub name_pattern: Vec<String>,
+
+    /// Characteristic the Flipper app receives samples on
+    #[arg(long, value_name = "UUID")]
+    pub characteristic_uuid: Option<Uuid>,
+}
+
+impl BluetoothArgs {
+    pub fn overrides(&self) -> PartialConfig {
+        PartialConfig {
+            adapter: self.adapter.clone(),
+            scan_duration: self.scan_duration,
+            name_patterns: (!self.name_pattern.is_empty()).then(|| self.name_pattern.clone()),
+            characteristic_uuid: self.characteristic_uuid,
+            ..PartialConfig::default()
+        }
+    }
+}
+
+#[derive(Args, Debug, Default)]
+pub struct ScanArgs {
+    #[command(flatten)]
+    pub bluetooth: BluetoothArgs,
+
+    /// Also list devices that don't look like a Flipper
+    #[arg(long)]
+    pub all: bool,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub bluetooth: BluetoothArgs,
+
+    /// Connect to this device (peripheral id, address or part of its name);
+    /// repeat to drive several Flippers, e.g. `--device Alpha,interval=1,metrics=cpu+ram`
+    #[arg(
+        long,
+        value_name = "ID|ADDRESS|NAME[,interval=SECS][,metrics=LIST][,gpu=GPU][,sensors=LIST][,disks=LIST][,interfaces=LIST][,ram_used=DEF][,processes=N]"
+    )]
+    pub device: Vec<DeviceSpec>,
+
+    /// Connect to every Flipper found by the initial scan
+    #[arg(long, conflicts_with_all = ["device", "pick"])]
+    pub all: bool,
+
+    /// Choose the device interactively even if one is remembered
+    #[arg(long, conflicts_with = "device")]
+    pub pick: bool,
+
+    /// Forget the remembered device before scanning
+    #[arg(long)]
+    pub forget: bool,
+
+    /// File used to remember the chosen device
+    #[arg(long, value_name = "PATH")]
+    pub state_file: Option<PathBuf>,
+
+    /// Seconds between updates
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub interval: Option<Duration>,
+
+    /// Payload encoding: packed or json
+    #[arg(long)]
+    pub encoding: Option<Encoding>,
+
+    /// Metrics to send, e.g. "cpu+ram" or "all"
+    #[arg(long, value_name = "LIST")]
+    pub metrics: Option<MetricFields>,
+
+    /// Read GPU residency from a running powermetrics (macOS, needs root)
+    #[arg(long)]
+    pub powermetrics: bool,
+
+    /// What counts as used RAM: "available", "nocache" or "free"
+    #[arg(long, value_name = "DEF")]
+    pub ram_used: Option<RamUsed>,
+
+    /// GPU shown by single-GPU apps: "busiest", an index or part of its name
+    #[arg(long, value_name = "GPU")]
+    pub gpu: Option<GpuSelector>,
+
+    /// Sensors to show and send, by kind or part of the label, e.g. "cpu+fan" or "all"
+    #[arg(long, value_name = "LIST")]
+    pub sensors: Option<SensorFilter>,
+
+    /// Disks to show and send, by mount point or part of the device name, e.g. "/+nvme1n1"
+    #[arg(long, value_name = "LIST")]
+    pub disks: Option<DiskFilter>,
+
+    /// Network interfaces to show and send, e.g. "en*+wlp*" or "!docker*"; "all" includes loopback
+    #[arg(long, value_name = "LIST")]
+    pub interfaces: Option<InterfaceFilter>,
+
+    /// Processes to show and send in each top list (CPU and memory), 0 to 16
+    #[arg(long, value_name = "N", value_parser = parse_process_count)]
+    pub processes: Option<usize>,
+}
+
+impl RunArgs {
+    pub fn overrides(&self) -> PartialConfig {
+        PartialConfig {
+            interval: self.interval,
+            encoding: self.encoding,
+            metrics: self.metrics,
+            powermetrics: self.powermetrics.then_some(true),
+            ram_used: self.ram_used,
+            gpu: self.gpu.clone(),
+            sensors: self.sensors.clone(),
+            disks: self.disks.clone(),
+            interfaces: self.interfaces.clone(),
+            processes: self.processes,
+            ..self.bluetooth.overrides()
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct DemoArgs {
+    /// Number of readings
+    #[arg(long, short = 'n', default_value_t = 5)]
+    pub count: u32,
+
+    /// Seconds between readings
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds, default_value = "2")]
+    pub interval: Duration,
+}
+
+impl Default for DemoArgs {
+    fn default() -> Self {
+        DemoArgs {
+            count: 5,
+            interval: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Args, Debug, Default)]
+pub struct DumpArgs {
+    /// Payload encoding: packed or json
+    #[arg(long)]
+    pub encoding: Option<Encoding>,
+
+    /// Metrics to include, e.g. "cpu+ram" or "all"
+    #[arg(long, value_name = "LIST")]
+    pub metrics: Option<MetricFields>,
+
+    /// Wrap the payload in a protocol frame instead of the legacy bare struct
+    #[arg(long)]
+    pub framed: bool,
+
+    /// What counts as used RAM: "available", "nocache" or "free"
+    #[arg(long, value_name = "DEF")]
+    pub ram_used: Option<RamUsed>,
+
+    /// GPU in the single-GPU fields: "busiest", an index or part of its name
+    #[arg(long, value_name = "GPU")]
+    pub gpu: Option<GpuSelector>,
+
+    /// Sensors to include, by kind or part of the label, e.g. "cpu+fan" or "all"
+    #[arg(long, value_name = "LIST")]
+    pub sensors: Option<SensorFilter>,
+
+    /// Disks to include, by mount point or part of the device name, e.g. "/+nvme1n1" or "all"
+    #[arg(long, value_name = "LIST")]
+    pub disks: Option<DiskFilter>,
+
+    /// Network interfaces to include, e.g. "en*+wlp*" or "!docker*"; "all" includes loopback
+    #[arg(long, value_name = "LIST")]
+    pub interfaces: Option<InterfaceFilter>,
+
+    /// Processes to include in each top list (CPU and memory), 0 to 16
+    #[arg(long, value_name = "N", value_parser = parse_process_count)]
+    pub processes: Option<usize>,
+}
+
+impl DumpArgs {
+    pub fn overrides(&self) -> PartialConfig {
+        PartialConfig {
+            encoding: self.encoding,
+            metrics: self.metrics,
+            ram_used: self.ram_used,
+            gpu: self.gpu.clone(),
+            sensors: self.sensors.clone(),
+            disks: self.disks.clone(),
+            interfaces: self.interfaces.clone(),
+            processes: self.processes,
+            ..PartialConfig::default()
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct OnceArgs {
+    /// Print the sample as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Command {
+        Cli::try_parse_from(std::iter::once("flipper-monitor").chain(args.iter().copied()))
+            .unwrap()
+            .command()
+    }
+
+    #[test]
+    fn test_no_subcommand_runs_with_defaults() {
+        let Command::Run(run) = parse(&[]) else {
+            panic!("expected run");
+        };
+        assert!(run.device.is_empty());
+        assert_eq!(run.interval, None);
+        assert_eq!(Command::Run(run).overrides().powermetrics, None);
+    }
+
+    #[test]
+    fn test_run_flags() {
+        let Command::Run(run) = parse(&[
+            "run",
+            "--interval",
+            "0.5",
+            "--encoding",
+            "json",
+            "--metrics",
+            "cpu+gpu",
+            "--device",
+            "Alpha",
+            "--device",
+            "Bravo,interval=5",
+            "--adapter",
+            "1",
+            "--powermetrics",
+            "--gpu",
+            "RTX",
+            "--sensors",
+            "cpu+fan",
+            "--disks",
+            "/,sda",
+            "--interfaces",
+            "en*",
+            "--ram-used",
+            "free",
+            "--processes",
+            "8",
+        ]) else {
+            panic!("expected run");
+        };
+        assert_eq!(run.interval, Some(Duration::from_millis(500)));
+        assert_eq!(run.encoding, Some(Encoding::Json));
+        assert_eq!(
+            run.metrics,
+            Some(MetricFields::CPU.union(MetricFields::GPU))
+        );
+        assert_eq!(run.device.len(), 2);
+        assert_eq!(run.bluetooth.adapter, Some(AdapterSelector::Index(1)));
+
+        let overrides = Command::Run(run).overrides();
+        assert_eq!(overrides.interval, Some(Duration::from_millis(500)));
+        assert_eq!(overrides.adapter, Some(AdapterSelector::Index(1)));
+        assert_eq!(overrides.scan_duration, None);
+        assert_eq!(overrides.powermetrics, Some(true));
+        assert_eq!(overrides.gpu, Some(GpuSelector::Name("RTX".to_owned())));
+        assert_eq!(overrides.sensors, Some("cpu+fan".parse().unwrap()));
+        assert_eq!(overrides.disks, Some("/+sda".parse().unwrap()));
+        assert_eq!(overrides.interfaces, Some("en*".parse().unwrap()));
+        assert_eq!(overrides.ram_used, Some(RamUsed::Free));
+        assert_eq!(overrides.processes, Some(8));
+
+        let bad = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once("flipper-monitor").chain(args.iter().copied()))
+                .is_err()
+        };
+        assert!(bad(&["run", "--interval", "0"]));
+        assert!(bad(&["run", "--all", "--device", "Alpha"]));
+        assert!(bad(&["run", "--metrics", "disk"]));
+        assert!(bad(&["run", "--processes", "20"]));
+    }
+
+    #[test]
+    fn test_other_subcommands() {
+        assert!(matches!(
+            parse(&["demo", "-n", "3"]),
+            Command::Demo(DemoArgs { count: 3, .. })
+        ));
+        assert!(matches!(
+            parse(&["once", "--json"]),
+            Command::Once(OnceArgs { json: true })
+        ));
+        assert!(matches!(
+            parse(&["scan", "--all"]),
+            Command::Scan(ScanArgs { all: true, .. })
+        ));
+        let Command::Dump(dump) = parse(&["dump", "--framed"]) else {
+            panic!("expected dump");
+        };
+        assert!(dump.framed);
+        // Unset flags leave the config file and environment in charge
+        let overrides = Command::Dump(dump).overrides();
+        assert_eq!(overrides.encoding, None);
+        assert_eq!(overrides.metrics, None);
+        assert_eq!(overrides.gpu, None);
+        assert_eq!(overrides.interfaces, None);
+        assert_eq!(overrides.processes, None);
+
+        let Command::Dump(dump) = parse(&[
+            "dump",
+            "--encoding",
+            "json",
+            "--metrics",
+            "cpu+gpus",
+            "--ram-used",
+            "free",
+            "--sensors",
+            "cpu",
+            "--processes",
+            "3",
+        ]) else {
+            panic!("expected dump");
+        };
+        let overrides = Command::Dump(dump).overrides();
+        assert_eq!(overrides.encoding, Some(Encoding::Json));
+        assert_eq!(
+            overrides.metrics,
+            Some(MetricFields::CPU.union(MetricFields::GPUS))
+        );
+        assert_eq!(overrides.ram_used, Some(RamUsed::Free));
+        assert_eq!(overrides.sensors, Some("cpu".parse().unwrap()));
+        assert_eq!(overrides.processes, Some(3));
+        assert_eq!(overrides.interval, None);
+    }
+}
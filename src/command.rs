@@ -0,0 +1,361 @@
+// ======================== command.rs ========================
+// Running external tools behind a trait so their output can be faked in tests
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Timeout for quick queries like `sysctl` and `ioreg`
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Why an external tool gave us nothing usable
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    /// The program is not on this system
+    NotInstalled(String),
+    /// The program exists but needs more privileges (e.g. `powermetrics` without sudo)
+    PermissionDenied { program: String, message: String },
+    /// Still running after the timeout; it has been killed
+    TimedOut { program: String, after: Duration },
+    /// Exited with a failure status for any other reason
+    Failed { program: String, message: String },
+    /// Ran fine but printed something we can't make sense of
+    Unparseable { program: String, reason: String },
+}
+
+impl CommandError {
+    pub fn unparseable(program: &str, reason: impl Into<String>) -> Self {
+        CommandError::Unparseable {
+            program: program.to_owned(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::NotInstalled(program) => write!(f, "{} is not installed", program),
+            CommandError::PermissionDenied { program, message } => {
+                write!(f, "{} needs more privileges: {}", program, message)
+            }
+            CommandError::TimedOut { program, after } => {
+                write!(f, "{} timed out after {:.1}s", program, after.as_secs_f64())
+            }
+            CommandError::Failed { program, message } => {
+                write!(f, "{} failed: {}", program, message)
+            }
+            CommandError::Unparseable { program, reason } => {
+                write!(f, "unexpected output from {}: {}", program, reason)
+            }
+        }
+    }
+}
+
+impl Error for CommandError {}
+
+/// Runs a program and returns what it printed
+#[async_trait]
+pub trait CommandRunner: Send + Sync {
+    /// Run `program` with `args`, giving up after `timeout`; a non-zero exit is an error
+    async fn run(
+        &self,
+        program: &str,
+        args: &[&str],
+        timeout: Duration,
+    ) -> Result<String, CommandError>;
+}
+
+/// Messages tools print when they need root
+const PRIVILEGE_HINTS: [&str; 4] = [
+    "superuser",
+    "must be run as root",
+    "Permission denied",
+    "Operation not permitted",
+];
+
+/// Why `program` could not be started
+pub(crate) fn spawn_error(program: &str, e: io::Error) -> CommandError {
+    match e.kind() {
+        io::ErrorKind::NotFound => CommandError::NotInstalled(program.to_owned()),
+        io::ErrorKind::PermissionDenied => CommandError::PermissionDenied {
+            program: program.to_owned(),
+            message: e.to_string(),
+        },
+        _ => CommandError::Failed {
+            program: program.to_owned(),
+            message: e.to_string(),
+        },
+    }
+}
+
+/// Why `program` exited with a failure status, telling "needs root" apart
+pub(crate) fn exit_error(program: &str, status: ExitStatus, stderr: &[u8]) -> CommandError {
+    let stderr = String::from_utf8_lossy(stderr).trim().to_owned();
+    let message = format!("exited with {}: {}", status, stderr);
+    if PRIVILEGE_HINTS.iter().any(|hint| stderr.contains(hint)) {
+        CommandError::PermissionDenied {
+            program: program.to_owned(),
+            message,
+        }
+    } else {
+        CommandError::Failed {
+            program: program.to_owned(),
+            message,
+        }
+    }
+}
+
+/// Runs commands for real
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemRunner;
+
+#[async_trait]
+impl CommandRunner for SystemRunner {
+    async fn run(
+        &self,
+        program: &str,
+        args: &[&str],
+        timeout: Duration,
+    ) -> Result<String, CommandError> {
+        let child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| spawn_error(program, e))?;
+
+        // Dropping the child on timeout kills it
+        let output = tokio::time::timeout(timeout, child.wait_with_output())
+            .await
+            .map_err(|_| CommandError::TimedOut {
+                program: program.to_owned(),
+                after: timeout,
+            })?
+            .map_err(|e| CommandError::Failed {
+                program: program.to_owned(),
+                message: e.to_string(),
+            })?;
+
+        if !output.status.success() {
+            return Err(exit_error(program, output.status, &output.stderr));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Name of the index file in a fixture directory
+pub const FIXTURE_INDEX: &str = "commands.toml";
+
+/// Replays recorded output, keyed by the full argv.
+///
+/// Commands that were not recorded fail as if the tool were not installed.
+#[derive(Clone, Debug, Default)]
+pub struct FixtureRunner {
+    recorded: HashMap<Vec<String>, Result<String, CommandError>>,
+}
+
+#[derive(Deserialize)]
+struct FixtureIndex {
+    command: Vec<FixtureEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixtureEntry {
+    argv: Vec<String>,
+    /// File next to the index holding the command's stdout
+    stdout: Option<String>,
+    /// "not_installed", "permission_denied", "timed_out" or "failed"
+    error: Option<String>,
+    #[serde(default)]
+    message: String,
+}
+
+fn argv_key(program: &str, args: &[&str]) -> Vec<String> {
+    std::iter::once(program)
+        .chain(args.iter().copied())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn recorded_error(kind: &str, program: &str, message: String) -> Option<CommandError> {
+    let program = program.to_owned();
+    Some(match kind {
+        "not_installed" => CommandError::NotInstalled(program),
+        "permission_denied" => CommandError::PermissionDenied { program, message },
+        "timed_out" => CommandError::TimedOut {
+            program,
+            after: DEFAULT_TIMEOUT,
+        },
+        "failed" => CommandError::Failed { program, message },
+        _ => return None,
+    })
+}
+
+impl FixtureRunner {
+    pub fn new() -> Self {
+        FixtureRunner::default()
+    }
+
+    /// Answer `argv` with `stdout`
+    pub fn with_output(mut self, argv: &[&str], stdout: impl Into<String>) -> Self {
+        let (program, args) = argv.split_first().expect("argv must not be empty");
+        self.recorded
+            .insert(argv_key(program, args), Ok(stdout.into()));
+        self
+    }
+
+    /// Fail `argv` with `error`
+    pub fn with_error(mut self, argv: &[&str], error: CommandError) -> Self {
+        let (program, args) = argv.split_first().expect("argv must not be empty");
+        self.recorded.insert(argv_key(program, args), Err(error));
+        self
+    }
+
+    /// Load a directory recorded from one machine, described by its `commands.toml`:
+    ///
+    /// ```toml
+    /// [[command]]
+    /// argv = ["sysctl", "-n", "hw.memsize"]
+    /// stdout = "sysctl_memsize.txt"
+    ///
+    /// [[command]]
+    /// argv = ["powermetrics", "-n", "1"]
+    /// error = "permission_denied"
+    /// message = "powermetrics must be invoked as the superuser"
+    /// ```
+    pub fn from_dir(dir: &Path) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let index = dir.join(FIXTURE_INDEX);
+        let index: FixtureIndex = toml::from_str(&fs::read_to_string(&index)?)
+            .map_err(|e| invalid(format!("{}: {}", index.display(), e)))?;
+
+        let mut runner = FixtureRunner::new();
+        for entry in index.command {
+            let argv: Vec<&str> = entry.argv.iter().map(String::as_str).collect();
+            let program = argv.first().copied().unwrap_or_default();
+            runner = match (entry.stdout, entry.error) {
+                (Some(file), None) => {
+                    runner.with_output(&argv, fs::read_to_string(dir.join(file))?)
+                }
+                (None, Some(kind)) => {
+                    let error = recorded_error(&kind, program, entry.message)
+                        .ok_or_else(|| invalid(format!("unknown error kind '{}'", kind)))?;
+                    runner.with_error(&argv, error)
+                }
+                _ => {
+                    return Err(invalid(format!(
+                        "{:?} needs exactly one of stdout or error",
+                        entry.argv
+                    )))
+                }
+            };
+        }
+        Ok(runner)
+    }
+}
+
+/// The recorded commands of one Mac under `tests/fixtures/macos`, e.g. "m2"
+#[cfg(test)]
+pub fn macos_fixture(machine: &str) -> FixtureRunner {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/macos")
+        .join(machine);
+    FixtureRunner::from_dir(&dir).unwrap()
+}
+
+#[async_trait]
+impl CommandRunner for FixtureRunner {
+    async fn run(
+        &self,
+        program: &str,
+        args: &[&str],
+        _timeout: Duration,
+    ) -> Result<String, CommandError> {
+        self.recorded
+            .get(&argv_key(program, args))
+            .cloned()
+            .unwrap_or_else(|| Err(CommandError::NotInstalled(program.to_owned())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fixture_runner_matches_full_argv() {
+        let denied = CommandError::PermissionDenied {
+            program: "powermetrics".to_owned(),
+            message: "not root".to_owned(),
+        };
+        let runner = FixtureRunner::new()
+            .with_output(&["sysctl", "-n", "hw.memsize"], "17179869184\n")
+            .with_error(&["powermetrics"], denied.clone());
+
+        let run = |program, args| runner.run(program, args, DEFAULT_TIMEOUT);
+        assert_eq!(
+            run("sysctl", &["-n", "hw.memsize"]).await.unwrap(),
+            "17179869184\n"
+        );
+        assert_eq!(
+            run("sysctl", &["hw.memsize"]).await,
+            Err(CommandError::NotInstalled("sysctl".to_owned()))
+        );
+        assert_eq!(run("powermetrics", &[]).await, Err(denied));
+    }
+
+    #[test]
+    fn test_fixture_dirs_load() {
+        for machine in ["m1", "m2", "m3", "intel", "mac_pro"] {
+            macos_fixture(machine);
+        }
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/macos");
+        assert!(FixtureRunner::from_dir(&root).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_system_runner() {
+        let run = |args| SystemRunner.run("sh", args, DEFAULT_TIMEOUT);
+        assert_eq!(run(&["-c", "echo hi"]).await.unwrap(), "hi\n");
+        assert!(matches!(
+            run(&["-c", "exit 3"]).await,
+            Err(CommandError::Failed { .. })
+        ));
+        assert!(matches!(
+            run(&["-c", "echo 'must be run as root' >&2; exit 1"]).await,
+            Err(CommandError::PermissionDenied { .. })
+        ));
+        assert_eq!(
+            SystemRunner
+                .run("flipper-monitor-no-such-tool", &[], DEFAULT_TIMEOUT)
+                .await,
+            Err(CommandError::NotInstalled(
+                "flipper-monitor-no-such-tool".to_owned()
+            ))
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_system_runner_kills_on_timeout() {
+        let started = std::time::Instant::now();
+        let result = SystemRunner
+            .run("sleep", &["5"], Duration::from_millis(100))
+            .await;
+        assert!(matches!(result, Err(CommandError::TimedOut { .. })));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,536 @@
+// ======================== config.rs ========================
+// Settings from the config file, environment and command line
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::disks::DiskFilter;
+use crate::flipper_manager::{AdapterSelector, FlipperDiscovery};
+use crate::helpers::parse_seconds;
+use crate::memory::RamUsed;
+use crate::monitor::MonitorOptions;
+use crate::network::InterfaceFilter;
+use crate::processes::parse_process_count;
+use crate::protocol::MetricFields;
+use crate::sensors::SensorFilter;
+use crate::system_info::GpuSelector;
+use crate::wire::Encoding;
+
+const APP_DIR: &str = "flipper-monitor";
+const CONFIG_FILE: &str = "config.toml";
+
+/// Prefix of the environment variables that override config keys
+pub const ENV_PREFIX: &str = "FLIPPER_MONITOR_";
+
+/// Every key accepted in the config file and as `FLIPPER_MONITOR_<KEY>`
+pub const KEYS: [&str; 14] = [
+    "interval",
+    "encoding",
+    "metrics",
+    "scan_duration",
+    "name_patterns",
+    "characteristic_uuid",
+    "adapter",
+    "powermetrics",
+    "gpu",
+    "sensors",
+    "disks",
+    "interfaces",
+    "ram_used",
+    "processes",
+];
+
+/// `flipper-monitor/config.toml` in the platform config dir: `~/Library/Application Support`
+/// on macOS, `$XDG_CONFIG_HOME` (or `~/.config`) on Linux
+pub fn default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_DIR).join(CONFIG_FILE))
+}
+
+/// Where a setting came from, for error messages
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    File(PathBuf),
+    Env(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Env(var) => write!(f, "environment variable {}", var),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file exists but could not be read
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// The config file is not valid TOML
+    Syntax {
+        path: PathBuf,
+        message: String,
+    },
+    UnknownKey {
+        key: String,
+        source: Source,
+    },
+    Invalid {
+        key: String,
+        source: Source,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(f, "cannot read {}: {}", path.display(), source)
+            }
+            ConfigError::Syntax { path, message } => {
+                write!(
+                    f,
+                    "{} is not valid TOML: {}",
+                    path.display(),
+                    message.trim()
+                )
+            }
+            ConfigError::UnknownKey { key, source } => write!(
+                f,
+                "unknown setting '{}' in {} (expected one of: {})",
+                key,
+                source,
+                KEYS.join(", ")
+            ),
+            ConfigError::Invalid {
+                key,
+                source,
+                message,
+            } => write!(f, "invalid '{}' in {}: {}", key, source, message),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// One layer of settings; unset fields fall through to the layer below
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PartialConfig {
+    pub interval: Option<Duration>,
+    pub encoding: Option<Encoding>,
+    pub metrics: Option<MetricFields>,
+    pub scan_duration: Option<Duration>,
+    pub name_patterns: Option<Vec<String>>,
+    pub characteristic_uuid: Option<Uuid>,
+    pub adapter: Option<AdapterSelector>,
+    pub powermetrics: Option<bool>,
+    pub gpu: Option<GpuSelector>,
+    pub sensors: Option<SensorFilter>,
+    pub disks: Option<DiskFilter>,
+    pub interfaces: Option<InterfaceFilter>,
+    pub ram_used: Option<RamUsed>,
+    pub processes: Option<usize>,
+}
+
+impl PartialConfig {
+    /// Set `key` from its text form; `Ok(false)` if the key is unknown
+    fn set(&mut self, key: &str, value: &str) -> Result<bool, String> {
+        match key {
+            "interval" => self.interval = Some(parse_seconds(value)?),
+            "encoding" => self.encoding = Some(value.parse()?),
+            "metrics" => self.metrics = Some(value.parse()?),
+            "scan_duration" => self.scan_duration = Some(parse_seconds(value)?),
+            "name_patterns" => {
+                let patterns: Vec<String> = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(str::to_owned)
+                    .collect();
+                self.name_patterns = Some(patterns);
+            }
+            "characteristic_uuid" => {
+                let uuid = Uuid::parse_str(value.trim())
+                    .map_err(|e| format!("'{}' is not a UUID: {}", value, e))?;
+                self.characteristic_uuid = Some(uuid);
+            }
+            "adapter" => self.adapter = Some(value.parse()?),
+            "powermetrics" => {
+                let enabled = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("expected true or false, got '{}'", value))?;
+                self.powermetrics = Some(enabled);
+            }
+            "gpu" => self.gpu = Some(value.parse()?),
+            "sensors" => self.sensors = Some(value.parse()?),
+            "disks" => self.disks = Some(value.parse()?),
+            "interfaces" => self.interfaces = Some(value.parse()?),
+            "ram_used" => self.ram_used = Some(value.parse()?),
+            "processes" => self.processes = Some(parse_process_count(value)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn set_from(&mut self, key: &str, value: &str, source: Source) -> Result<(), ConfigError> {
+        match self.set(key, value) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(ConfigError::UnknownKey {
+                key: key.to_owned(),
+                source,
+            }),
+            Err(message) => Err(ConfigError::Invalid {
+                key: key.to_owned(),
+                source,
+                message,
+            }),
+        }
+    }
+
+    /// Parse a config file's contents
+    pub fn from_toml(text: &str, path: &Path) -> Result<Self, ConfigError> {
+        let table: toml::Table =
+            text.parse()
+                .map_err(|e: toml::de::Error| ConfigError::Syntax {
+                    path: path.to_owned(),
+                    message: e.to_string(),
+                })?;
+
+        let mut layer = PartialConfig::default();
+        for (key, value) in &table {
+            let source = Source::File(path.to_owned());
+            let text = toml_to_text(value).map_err(|message| ConfigError::Invalid {
+                key: key.clone(),
+                source: source.clone(),
+                message,
+            })?;
+            layer.set_from(key, &text, source)?;
+        }
+        Ok(layer)
+    }
+
+    /// Read the config file; a missing file is an empty layer
+    pub fn load_file(path: &Path) -> Result<Self, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(text) => PartialConfig::from_toml(&text, path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(PartialConfig::default()),
+            Err(source) => Err(ConfigError::Io {
+                path: path.to_owned(),
+                source,
+            }),
+        }
+    }
+
+    /// Pick up `FLIPPER_MONITOR_<KEY>` variables; others are ignored
+    pub fn from_env<I>(vars: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut layer = PartialConfig::default();
+        for (var, value) in vars {
+            let Some(key) = var.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let key = key.to_ascii_lowercase();
+            layer.set_from(&key, &value, Source::Env(var.clone()))?;
+        }
+        Ok(layer)
+    }
+
+    /// Settings from `self`, with anything set in `over` taking precedence
+    pub fn overridden_by(self, over: PartialConfig) -> PartialConfig {
+        PartialConfig {
+            interval: over.interval.or(self.interval),
+            encoding: over.encoding.or(self.encoding),
+            metrics: over.metrics.or(self.metrics),
+            scan_duration: over.scan_duration.or(self.scan_duration),
+            name_patterns: over.name_patterns.or(self.name_patterns),
+            characteristic_uuid: over.characteristic_uuid.or(self.characteristic_uuid),
+            adapter: over.adapter.or(self.adapter),
+            powermetrics: over.powermetrics.or(self.powermetrics),
+            gpu: over.gpu.or(self.gpu),
+            sensors: over.sensors.or(self.sensors),
+            disks: over.disks.or(self.disks),
+            interfaces: over.interfaces.or(self.interfaces),
+            ram_used: over.ram_used.or(self.ram_used),
+            processes: over.processes.or(self.processes),
+        }
+    }
+}
+
+/// TOML scalars and string arrays in the same text form the environment uses
+fn toml_to_text(value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(n) => Ok(n.to_string()),
+        toml::Value::Float(n) => Ok(n.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        toml::Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                toml::Value::String(s) if !s.contains(',') => Ok(s.clone()),
+                toml::Value::String(s) => Err(format!("'{}' must not contain a comma", s)),
+                other => Err(format!("expected a string, got {}", other.type_str())),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|items| items.join(",")),
+        other => Err(format!(
+            "expected a string, number or list, got {}",
+            other.type_str()
+        )),
+    }
+}
+
+/// Fully resolved settings
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub interval: Duration,
+    pub encoding: Encoding,
+    pub metrics: MetricFields,
+    pub scan_duration: Duration,
+    /// Empty matches any advertised name
+    pub name_patterns: Vec<String>,
+    pub characteristic_uuid: Uuid,
+    pub adapter: AdapterSelector,
+    /// Keep a `powermetrics` process running for GPU residency (macOS, needs root)
+    pub powermetrics: bool,
+    /// GPU reported in the single-GPU fields
+    pub gpu: GpuSelector,
+    /// Sensors shown and sent; empty means all
+    pub sensors: SensorFilter,
+    /// Disks shown and sent; empty means all
+    pub disks: DiskFilter,
+    /// Network interfaces shown and sent; the default leaves out loopback and bridges
+    pub interfaces: InterfaceFilter,
+    /// What counts as used in the RAM percentage
+    pub ram_used: RamUsed,
+    /// Processes in each top list
+    pub processes: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let monitor = MonitorOptions::default();
+        let discovery = FlipperDiscovery::default();
+        Config {
+            interval: monitor.interval,
+            encoding: monitor.encoding,
+            metrics: monitor.capabilities.fields,
+            scan_duration: discovery.scan_duration,
+            name_patterns: discovery.name_patterns,
+            characteristic_uuid: discovery.characteristic_uuid,
+            adapter: discovery.adapter,
+            powermetrics: false,
+            gpu: monitor.gpu,
+            sensors: monitor.sensors,
+            disks: monitor.disks,
+            interfaces: monitor.interfaces,
+            ram_used: monitor.ram_used,
+            processes: monitor.processes,
+        }
+    }
+}
+
+impl Config {
+    /// Fill in anything the layer leaves unset with the defaults
+    pub fn resolve(layer: PartialConfig) -> Config {
+        let defaults = Config::default();
+        Config {
+            interval: layer.interval.unwrap_or(defaults.interval),
+            encoding: layer.encoding.unwrap_or(defaults.encoding),
+            metrics: layer.metrics.unwrap_or(defaults.metrics),
+            scan_duration: layer.scan_duration.unwrap_or(defaults.scan_duration),
+            name_patterns: layer.name_patterns.unwrap_or(defaults.name_patterns),
+            characteristic_uuid: layer
+                .characteristic_uuid
+                .unwrap_or(defaults.characteristic_uuid),
+            adapter: layer.adapter.unwrap_or(defaults.adapter),
+            powermetrics: layer.powermetrics.unwrap_or(defaults.powermetrics),
+            gpu: layer.gpu.unwrap_or(defaults.gpu),
+            sensors: layer.sensors.unwrap_or(defaults.sensors),
+            disks: layer.disks.unwrap_or(defaults.disks),
+            interfaces: layer.interfaces.unwrap_or(defaults.interfaces),
+            ram_used: layer.ram_used.unwrap_or(defaults.ram_used),
+            processes: layer.processes.unwrap_or(defaults.processes),
+        }
+    }
+
+    /// Load the config file (the given one, or the default if it exists),
+    /// then apply environment variables, then `cli`.
+    pub fn load(path: Option<&Path>, cli: PartialConfig) -> Result<Config, ConfigError> {
+        let file = match path {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(text) => PartialConfig::from_toml(&text, path)?,
+                Err(source) => {
+                    return Err(ConfigError::Io {
+                        path: path.to_owned(),
+                        source,
+                    })
+                }
+            },
+            None => match default_config_path() {
+                Some(path) => PartialConfig::load_file(&path)?,
+                None => PartialConfig::default(),
+            },
+        };
+        let env = PartialConfig::from_env(std::env::vars())?;
+
+        Ok(Config::resolve(file.overridden_by(env).overridden_by(cli)))
+    }
+
+    pub fn discovery(&self) -> FlipperDiscovery {
+        FlipperDiscovery::default()
+            .with_name_patterns(self.name_patterns.clone())
+            .with_adapter(self.adapter.clone())
+            .with_scan_duration(self.scan_duration)
+            .with_characteristic_uuid(self.characteristic_uuid)
+    }
+
+    pub fn monitor_options(&self) -> MonitorOptions {
+        let mut options = MonitorOptions {
+            interval: self.interval,
+            encoding: self.encoding,
+            gpu: self.gpu.clone(),
+            sensors: self.sensors.clone(),
+            disks: self.disks.clone(),
+            interfaces: self.interfaces.clone(),
+            ram_used: self.ram_used,
+            processes: self.processes,
+            ..MonitorOptions::default()
+        };
+        options.capabilities.fields = self.metrics;
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path() -> PathBuf {
+        PathBuf::from("/etc/flipper-monitor/config.toml")
+    }
+
+    #[test]
+    fn test_file_values() {
+        let text = r#"
+            interval = 0.5
+            scan_duration = 10
+            encoding = "json"
+            metrics = "cpu+ram"
+            name_patterns = ["PC Mon", "Desk"]
+            characteristic_uuid = "19ed82ae-ed21-4c9d-4145-228e61fe0000"
+            adapter = 1
+            powermetrics = true
+            gpu = "radeon"
+            sensors = ["cpu", "Composite"]
+            disks = ["/", "nvme1n1"]
+            interfaces = ["en*", "!en5"]
+            ram_used = "nocache"
+            processes = 3
+        "#;
+        let config = Config::resolve(PartialConfig::from_toml(text, &path()).unwrap());
+        assert_eq!(config.interval, Duration::from_millis(500));
+        assert_eq!(config.scan_duration, Duration::from_secs(10));
+        assert_eq!(config.encoding, Encoding::Json);
+        assert_eq!(config.metrics, MetricFields::CPU.union(MetricFields::RAM));
+        assert_eq!(config.name_patterns, vec!["PC Mon", "Desk"]);
+        assert_eq!(
+            config.characteristic_uuid,
+            Uuid::from_u128(0x19ed82ae_ed21_4c9d_4145_228e61fe0000)
+        );
+        assert_eq!(config.adapter, AdapterSelector::Index(1));
+        assert!(config.powermetrics);
+        assert_eq!(config.gpu, GpuSelector::Name("radeon".to_owned()));
+        assert_eq!(
+            config.sensors,
+            SensorFilter(vec!["cpu".to_owned(), "Composite".to_owned()])
+        );
+        assert_eq!(config.disks.to_string(), "/+nvme1n1");
+        assert_eq!(config.interfaces.to_string(), "en*+!en5");
+        assert_eq!(config.monitor_options().ram_used, RamUsed::NoCache);
+        assert_eq!(config.monitor_options().processes, 3);
+
+        assert_eq!(
+            Config::resolve(PartialConfig::from_toml("", &path()).unwrap()),
+            Config::default()
+        );
+    }
+
+    #[test]
+    fn test_layers_override_in_order() {
+        let file = PartialConfig::from_toml("interval = 5\nencoding = \"json\"", &path()).unwrap();
+        let env = PartialConfig::from_env([
+            ("FLIPPER_MONITOR_INTERVAL".to_owned(), "3".to_owned()),
+            ("FLIPPER_MONITOR_METRICS".to_owned(), "cpu".to_owned()),
+            ("FLIPPER_MONITOR_GPU".to_owned(), "1".to_owned()),
+            ("HOME".to_owned(), "/root".to_owned()),
+        ])
+        .unwrap();
+        let cli = PartialConfig {
+            interval: Some(Duration::from_secs(1)),
+            ..PartialConfig::default()
+        };
+
+        let config = Config::resolve(file.overridden_by(env).overridden_by(cli));
+        assert_eq!(config.interval, Duration::from_secs(1));
+        assert_eq!(config.metrics, MetricFields::CPU);
+        assert_eq!(config.encoding, Encoding::Json);
+        assert_eq!(
+            config.monitor_options().capabilities.fields,
+            MetricFields::CPU
+        );
+        assert_eq!(config.monitor_options().gpu, GpuSelector::Index(1));
+    }
+
+    #[test]
+    fn test_errors_name_key_and_source() {
+        let err = PartialConfig::from_toml("interval = -1", &path()).unwrap_err();
+        assert!(matches!(&err, ConfigError::Invalid { key, .. } if key == "interval"));
+        assert!(err.to_string().contains("/etc/flipper-monitor/config.toml"));
+
+        let err = PartialConfig::from_toml("intervall = 2", &path()).unwrap_err();
+        assert!(matches!(err, ConfigError::UnknownKey { .. }));
+
+        assert!(matches!(
+            PartialConfig::from_toml("interval = ", &path()),
+            Err(ConfigError::Syntax { .. })
+        ));
+        assert!(PartialConfig::from_toml("name_patterns = [1]", &path()).is_err());
+        assert!(PartialConfig::from_toml("characteristic_uuid = \"nope\"", &path()).is_err());
+        assert!(PartialConfig::from_toml("powermetrics = \"sometimes\"", &path()).is_err());
+
+        let err =
+            PartialConfig::from_env([("FLIPPER_MONITOR_ENCODING".to_owned(), "xml".to_owned())])
+                .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("environment variable FLIPPER_MONITOR_ENCODING"));
+    }
+
+    #[test]
+    fn test_explicit_path_must_exist() {
+        let missing = std::env::temp_dir().join("flipper-monitor-missing.toml");
+        assert!(matches!(
+            Config::load(Some(&missing), PartialConfig::default()),
+            Err(ConfigError::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,156 @@
+// ======================== cpu_topology.rs ========================
+// Which logical CPUs are performance cores and which are efficiency cores
+
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use crate::command::{CommandRunner, DEFAULT_TIMEOUT};
+
+/// Kind of core on hybrid CPUs (Apple Silicon, Intel Alder Lake and later)
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum CoreKind {
+    Performance,
+    Efficiency,
+}
+
+/// Parse a kernel CPU list such as "0-3,8,10-11"
+pub fn parse_cpu_list(text: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in text.trim().split(',').filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last): (usize, usize) = (first.parse().ok()?, last.parse().ok()?);
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(part.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+/// Kind of each of `count` logical CPUs from the hybrid PMU devices in sysfs.
+///
+/// Intel hybrid CPUs list their P-cores in `devices/cpu_core/cpus` and their
+/// E-cores in `devices/cpu_atom/cpus`; other CPUs have neither.
+pub fn linux_core_kinds(sysfs_root: &Path, count: usize) -> Option<Vec<CoreKind>> {
+    let read = |pmu: &str| {
+        fs::read_to_string(sysfs_root.join("devices").join(pmu).join("cpus"))
+            .ok()
+            .and_then(|text| parse_cpu_list(&text))
+    };
+    let atom = read("cpu_atom").filter(|cpus| !cpus.is_empty())?;
+    read("cpu_core")?;
+
+    let mut kinds = vec![CoreKind::Performance; count];
+    for cpu in atom {
+        if let Some(kind) = kinds.get_mut(cpu) {
+            *kind = CoreKind::Efficiency;
+        }
+    }
+    Some(kinds)
+}
+
+/// Kind of each logical CPU from `sysctl -n hw.perflevelN.name hw.perflevelN.logicalcpu ...`.
+///
+/// Levels are listed fastest first, while macOS numbers the efficiency cores first.
+pub fn kinds_from_perflevels(output: &str) -> Option<Vec<CoreKind>> {
+    let lines: Vec<&str> = output.lines().map(str::trim).collect();
+    let mut levels = Vec::new();
+    for (level, pair) in lines.chunks(2).enumerate() {
+        let [name, logical] = pair else {
+            return None;
+        };
+        let kind = match *name {
+            "Performance" => CoreKind::Performance,
+            "Efficiency" => CoreKind::Efficiency,
+            _ if level == 0 => CoreKind::Performance,
+            _ => CoreKind::Efficiency,
+        };
+        levels.push((kind, logical.parse::<usize>().ok()?));
+    }
+    if levels.len() < 2 {
+        return None;
+    }
+
+    Some(
+        levels
+            .into_iter()
+            .rev()
+            .flat_map(|(kind, count)| std::iter::repeat_n(kind, count))
+            .collect(),
+    )
+}
+
+/// Kind of each logical CPU on Apple Silicon; `None` on Intel Macs
+pub async fn macos_core_kinds(runner: &dyn CommandRunner) -> Option<Vec<CoreKind>> {
+    let levels: usize = runner
+        .run("sysctl", &["-n", "hw.nperflevels"], DEFAULT_TIMEOUT)
+        .await
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    if levels < 2 {
+        return None;
+    }
+
+    let keys: Vec<String> = (0..levels)
+        .flat_map(|level| {
+            [
+                format!("hw.perflevel{}.name", level),
+                format!("hw.perflevel{}.logicalcpu", level),
+            ]
+        })
+        .collect();
+    let args: Vec<&str> = std::iter::once("-n")
+        .chain(keys.iter().map(String::as_str))
+        .collect();
+    let output = runner.run("sysctl", &args, DEFAULT_TIMEOUT).await.ok()?;
+    kinds_from_perflevels(&output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::macos_fixture;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11\n"),
+            Some(vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(parse_cpu_list(""), Some(vec![]));
+        assert_eq!(parse_cpu_list("0-x"), None);
+    }
+
+    #[test]
+    fn test_linux_hybrid_intel() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/linux/sys");
+        let kinds = linux_core_kinds(&root, 20).unwrap();
+        assert!(kinds[..12].iter().all(|&k| k == CoreKind::Performance));
+        assert!(kinds[12..].iter().all(|&k| k == CoreKind::Efficiency));
+
+        let missing = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/linux/proc");
+        assert_eq!(linux_core_kinds(&missing, 20), None);
+    }
+
+    #[tokio::test]
+    async fn test_macos_perflevels() {
+        // M2 Pro: 8 performance cores, 4 efficiency cores numbered first
+        let kinds = macos_core_kinds(&macos_fixture("m2")).await.unwrap();
+        assert_eq!(kinds.len(), 12);
+        assert!(kinds[..4].iter().all(|&k| k == CoreKind::Efficiency));
+        assert!(kinds[4..].iter().all(|&k| k == CoreKind::Performance));
+
+        assert_eq!(
+            macos_core_kinds(&macos_fixture("m1")).await.unwrap().len(),
+            8
+        );
+        // Intel Macs have no perflevels
+        assert_eq!(macos_core_kinds(&macos_fixture("intel")).await, None);
+        assert_eq!(kinds_from_perflevels("Performance\n"), None);
+    }
+}
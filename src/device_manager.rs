@@ -0,0 +1,305 @@
+// ======================== device_manager.rs ========================
+// Several Flippers streaming from one host process
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+use crate::disks::DiskFilter;
+use crate::helpers::parse_seconds;
+use crate::memory::RamUsed;
+use crate::monitor::MonitorOptions;
+use crate::network::InterfaceFilter;
+use crate::processes::parse_process_count;
+use crate::protocol::MetricFields;
+use crate::sampler::SampleReceiver;
+use crate::sensors::SensorFilter;
+use crate::supervisor::{supervise, ConnectError, Connector, SupervisorOptions};
+use crate::system_info::GpuSelector;
+
+/// One device to drive and its per-device overrides.
+///
+/// Written as `QUERY[,interval=SECS][,metrics=cpu+ram][,gpu=GPU][,sensors=cpu+fan][,disks=/+sda]
+/// [,interfaces=en*+!en5][,ram_used=nocache][,processes=3]`; an empty query means "the remembered or only Flipper".
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceSpec {
+    pub query: Option<String>,
+    pub interval: Option<Duration>,
+    pub fields: Option<MetricFields>,
+    pub gpu: Option<GpuSelector>,
+    pub sensors: Option<SensorFilter>,
+    pub disks: Option<DiskFilter>,
+    pub interfaces: Option<InterfaceFilter>,
+    pub ram_used: Option<RamUsed>,
+    pub processes: Option<usize>,
+}
+
+impl DeviceSpec {
+    /// Monitor settings for this device, starting from the shared defaults
+    pub fn apply(&self, base: &MonitorOptions) -> MonitorOptions {
+        let mut options = base.clone();
+        if let Some(interval) = self.interval {
+            options.interval = interval;
+        }
+        if let Some(fields) = self.fields {
+            options.capabilities.fields = fields;
+        }
+        if let Some(gpu) = &self.gpu {
+            options.gpu = gpu.clone();
+        }
+        if let Some(sensors) = &self.sensors {
+            options.sensors = sensors.clone();
+        }
+        if let Some(disks) = &self.disks {
+            options.disks = disks.clone();
+        }
+        if let Some(interfaces) = &self.interfaces {
+            options.interfaces = interfaces.clone();
+        }
+        if let Some(ram_used) = self.ram_used {
+            options.ram_used = ram_used;
+        }
+        if let Some(processes) = self.processes {
+            options.processes = processes;
+        }
+        if let Some(query) = &self.query {
+            options.label = Some(query.clone());
+        }
+        options
+    }
+}
+
+impl FromStr for DeviceSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',').map(str::trim);
+        let query = parts.next().filter(|q| !q.is_empty()).map(str::to_owned);
+        let mut spec = DeviceSpec {
+            query,
+            ..DeviceSpec::default()
+        };
+
+        for part in parts {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{}'", part))?;
+            match key.trim() {
+                "interval" => spec.interval = Some(parse_seconds(value)?),
+                "metrics" => spec.fields = Some(value.parse()?),
+                "gpu" => spec.gpu = Some(value.parse()?),
+                "sensors" => spec.sensors = Some(value.parse()?),
+                "disks" => spec.disks = Some(value.parse()?),
+                "interfaces" => spec.interfaces = Some(value.parse()?),
+                "ram_used" => spec.ram_used = Some(value.parse()?),
+                "processes" => spec.processes = Some(parse_process_count(value)?),
+                other => return Err(format!("unknown device option '{}'", other)),
+            }
+        }
+        Ok(spec)
+    }
+}
+
+impl fmt::Display for DeviceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.query.as_deref().unwrap_or("auto"))
+    }
+}
+
+/// Runs one supervised connection per device, all fed by the same sampler
+pub struct DeviceManager {
+    samples: SampleReceiver,
+    tasks: JoinSet<(String, Result<(), ConnectError>)>,
+}
+
+impl DeviceManager {
+    pub fn new(samples: SampleReceiver) -> Self {
+        DeviceManager {
+            samples,
+            tasks: JoinSet::new(),
+        }
+    }
+
+    /// Start driving a device; it reconnects independently of the others
+    pub fn add<C>(
+        &mut self,
+        name: impl Into<String>,
+        mut connector: C,
+        monitor: MonitorOptions,
+        supervisor: SupervisorOptions,
+    ) where
+        C: Connector + 'static,
+    {
+        let name = name.into();
+        let mut samples = self.samples.clone();
+        self.tasks.spawn(async move {
+            let result = supervise(&mut connector, &mut samples, &monitor, &supervisor).await;
+            (name, result)
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Wait for every device to finish, reporting how each one ended
+    pub async fn run(mut self) -> Vec<(String, Result<(), ConnectError>)> {
+        // Only the devices keep the sampler alive from here on
+        drop(self.samples);
+
+        let mut results = Vec::with_capacity(self.tasks.len());
+        while let Some(joined) = self.tasks.join_next().await {
+            match joined {
+                Ok((name, result)) => {
+                    if let Err(e) = &result {
+                        println!("⚠️  [{}] Gave up: {}", name, e);
+                    }
+                    results.push((name, result));
+                }
+                Err(e) => println!("⚠️  Device task failed: {}", e),
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fragment::Reassembler;
+    use crate::protocol::{decode_frame, encode_frame, Capabilities, Encodings, MessageType};
+    use crate::sampler::spawn_sampler;
+    use crate::supervisor::Backoff;
+    use crate::transport::{ChannelTransport, Transport};
+    use crate::wire::{self, Encoding};
+    use async_trait::async_trait;
+
+    struct OnceConnector(Option<ChannelTransport>);
+
+    #[async_trait]
+    impl Connector for OnceConnector {
+        type Transport = ChannelTransport;
+
+        async fn connect(&mut self) -> Result<ChannelTransport, ConnectError> {
+            let mut transport = self.0.take().ok_or("out of range")?;
+            transport.connect().await?;
+            Ok(transport)
+        }
+    }
+
+    #[test]
+    fn test_device_spec_parsing() {
+        let spec: DeviceSpec =
+            "Flipper Alpha,interval=0.5,metrics=cpu+ram,gpu=1,sensors=cpu+fan,disks=/+/home"
+                .parse()
+                .unwrap();
+        assert_eq!(spec.query.as_deref(), Some("Flipper Alpha"));
+        assert_eq!(spec.interval, Some(Duration::from_millis(500)));
+        assert_eq!(
+            spec.fields,
+            Some(MetricFields::CPU.union(MetricFields::RAM))
+        );
+
+        let options = spec.apply(&MonitorOptions::default());
+        assert_eq!(options.interval, Duration::from_millis(500));
+        assert_eq!(options.label.as_deref(), Some("Flipper Alpha"));
+        assert_eq!(options.gpu, GpuSelector::Index(1));
+        assert_eq!(options.sensors.to_string(), "cpu+fan");
+        assert_eq!(options.disks.to_string(), "/+/home");
+        assert_eq!(options.interfaces, InterfaceFilter::default());
+
+        let spec: DeviceSpec = "Bravo,interfaces=wlp*,ram_used=nocache,processes=3"
+            .parse()
+            .unwrap();
+        assert_eq!(spec.interfaces, Some("wlp*".parse().unwrap()));
+        let options = spec.apply(&MonitorOptions::default());
+        assert_eq!(options.ram_used, RamUsed::NoCache);
+        assert_eq!(options.processes, 3);
+        assert!("Bravo,processes=99".parse::<DeviceSpec>().is_err());
+
+        assert_eq!("AA:BB".parse::<DeviceSpec>().unwrap().interval, None);
+        assert_eq!("".parse::<DeviceSpec>().unwrap(), DeviceSpec::default());
+        assert!("x,interval=0".parse::<DeviceSpec>().is_err());
+        assert!("x,metrics=disk".parse::<DeviceSpec>().is_err());
+        assert!("x,speed=3".parse::<DeviceSpec>().is_err());
+        assert!("x,interval".parse::<DeviceSpec>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_devices_share_one_sampler() {
+        let (legacy, mut legacy_peer) = ChannelTransport::pair(64);
+        let (framed, mut framed_peer) = ChannelTransport::pair(64);
+        let (samples, sampler) = spawn_sampler(Duration::from_millis(10));
+
+        let base = MonitorOptions {
+            interval: Duration::from_millis(10),
+            handshake_timeout: Duration::from_millis(50),
+            ..MonitorOptions::default()
+        };
+        let supervisor = SupervisorOptions {
+            backoff: Backoff::new(Duration::from_millis(1), Duration::from_millis(1)),
+            max_attempts: Some(1),
+        };
+        let cpu_only = DeviceSpec {
+            fields: Some(MetricFields::CPU),
+            ..DeviceSpec::default()
+        };
+
+        let mut manager = DeviceManager::new(samples);
+        manager.add(
+            "legacy",
+            OnceConnector(Some(legacy)),
+            cpu_only.apply(&base),
+            supervisor.clone(),
+        );
+        manager.add(
+            "framed",
+            OnceConnector(Some(framed)),
+            base.clone(),
+            supervisor,
+        );
+        assert_eq!(manager.len(), 2);
+        let run = tokio::spawn(manager.run());
+
+        // Upgraded app: answers the Hello and gets framed metrics
+        assert!(framed_peer.recv().await.is_some());
+        let caps = Capabilities {
+            encodings: Encodings::PACKED,
+            ..Capabilities::host()
+        };
+        framed_peer.send(
+            &encode_frame(MessageType::Capabilities, Encoding::Packed, &caps.encode()).unwrap(),
+        );
+        let mut reassembler = Reassembler::new(caps.max_payload as usize);
+        let message = loop {
+            if let Some(message) = reassembler
+                .push(&framed_peer.recv().await.unwrap())
+                .unwrap()
+            {
+                break message;
+            }
+        };
+        let frame = decode_frame(&message).unwrap();
+        assert_eq!(frame.msg_type, MessageType::Metrics);
+
+        // Stock app: ignores the Hello and gets CPU only
+        assert!(legacy_peer.recv().await.is_some());
+        let info = wire::decode_packed(&legacy_peer.recv().await.unwrap()).unwrap();
+        assert_eq!(info.ram_max, 0);
+
+        // Both walk away; neither can reconnect
+        legacy_peer.set_link(false);
+        framed_peer.set_link(false);
+        let results = run.await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(_, result)| result.is_err()));
+
+        // No device left to feed
+        sampler.await.unwrap();
+    }
+}
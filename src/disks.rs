@@ -0,0 +1,336 @@
+// ======================== disks.rs ========================
+// Per-mount capacity from sysinfo and I/O rates from /proc/diskstats
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use sysinfo::Disks;
+
+use crate::helpers::{format_patterns, format_rate, parse_patterns};
+use crate::system_info::SystemInfo;
+
+pub const DEFAULT_DISKSTATS_PATH: &str = "/proc/diskstats";
+
+/// `/proc/diskstats` counts in 512-byte sectors regardless of the device
+const SECTOR_SIZE: u64 = 512;
+
+/// Throughput over the last sampling interval
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskIo {
+    pub read_bytes_per_sec: u64,
+    pub write_bytes_per_sec: u64,
+    pub read_iops: u32,
+    pub write_iops: u32,
+}
+
+/// One mounted filesystem
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskStats {
+    /// e.g. "/" or "/Volumes/Data"
+    pub mount: String,
+    /// Block device without "/dev/", e.g. "nvme0n1p2" or "disk3s1"
+    pub device: String,
+    /// Capacity, scaled like the RAM fields
+    pub size: u16,
+    pub unit: [u8; 4],
+    /// Percent of the capacity in use
+    pub usage: u8,
+    /// `None` where the OS gives no per-device counters, and on the first sample
+    pub io: Option<DiskIo>,
+}
+
+impl DiskStats {
+    pub fn new(
+        mount: impl Into<String>,
+        device: impl Into<String>,
+        total: u64,
+        available: u64,
+    ) -> Self {
+        let (size, unit) = SystemInfo::scale(total);
+        let usage = if total > 0 {
+            ((total.saturating_sub(available) as f64 / total as f64) * 100.0) as u8
+        } else {
+            0
+        };
+        DiskStats {
+            mount: mount.into(),
+            device: device.into(),
+            size,
+            unit,
+            usage,
+            io: None,
+        }
+    }
+}
+
+impl fmt::Display for DiskStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mount)?;
+        if !self.device.is_empty() {
+            write!(f, " ({})", self.device)?;
+        }
+        write!(
+            f,
+            ": {} {} ({}% used)",
+            self.size,
+            String::from_utf8_lossy(&self.unit).trim_end_matches('\0'),
+            self.usage
+        )?;
+        if let Some(io) = self.io {
+            write!(
+                f,
+                ", read {} ({} IOPS), write {} ({} IOPS)",
+                format_rate(io.read_bytes_per_sec),
+                io.read_iops,
+                format_rate(io.write_bytes_per_sec),
+                io.write_iops
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Which disks to report: mount points (exact) or parts of device names; empty means all
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiskFilter(pub Vec<String>);
+
+impl DiskFilter {
+    pub fn allows(&self, disk: &DiskStats) -> bool {
+        self.0.is_empty()
+            || self
+                .0
+                .iter()
+                .any(|pattern| disk.mount == *pattern || disk.device.contains(pattern.as_str()))
+    }
+
+    /// The disks this filter allows, in their original order
+    pub fn apply(&self, disks: &[DiskStats]) -> Vec<DiskStats> {
+        disks
+            .iter()
+            .filter(|disk| self.allows(disk))
+            .cloned()
+            .collect()
+    }
+}
+
+impl FromStr for DiskFilter {
+    type Err = String;
+
+    /// Parse a list like "/+/home" or "nvme0n1,sda"; "all" selects everything
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_patterns(s, "disk").map(DiskFilter)
+    }
+}
+
+impl fmt::Display for DiskFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_patterns(&self.0))
+    }
+}
+
+/// Cumulative counters for one block device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoCounters {
+    pub reads: u64,
+    pub sectors_read: u64,
+    pub writes: u64,
+    pub sectors_written: u64,
+}
+
+/// Parse `/proc/diskstats`, keyed by device name
+pub fn parse_diskstats(text: &str) -> HashMap<String, IoCounters> {
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let number = |i: usize| fields.get(i)?.parse::<u64>().ok();
+            let counters = IoCounters {
+                reads: number(3)?,
+                sectors_read: number(5)?,
+                writes: number(7)?,
+                sectors_written: number(9)?,
+            };
+            Some((fields[2].to_owned(), counters))
+        })
+        .collect()
+}
+
+/// Per-second rates between two snapshots; devices missing from either are left out
+pub fn io_rates(
+    previous: &HashMap<String, IoCounters>,
+    current: &HashMap<String, IoCounters>,
+    elapsed: Duration,
+) -> HashMap<String, DiskIo> {
+    let secs = elapsed.as_secs_f64();
+    if secs <= 0.0 {
+        return HashMap::new();
+    }
+    // Counters wrap or reset when a device is re-added; treat that as idle
+    let rate = |now: u64, before: u64| (now.saturating_sub(before) as f64 / secs).round() as u64;
+
+    current
+        .iter()
+        .filter_map(|(name, now)| {
+            let before = previous.get(name)?;
+            Some((
+                name.clone(),
+                DiskIo {
+                    read_bytes_per_sec: rate(now.sectors_read, before.sectors_read) * SECTOR_SIZE,
+                    write_bytes_per_sec: rate(now.sectors_written, before.sectors_written)
+                        * SECTOR_SIZE,
+                    read_iops: rate(now.reads, before.reads).min(u32::MAX as u64) as u32,
+                    write_iops: rate(now.writes, before.writes).min(u32::MAX as u64) as u32,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Reads `/proc/diskstats`; keeps the previous snapshot to turn counters into rates
+#[derive(Debug)]
+pub struct DiskIoProbe {
+    path: PathBuf,
+    previous: HashMap<String, IoCounters>,
+    previous_at: Option<Instant>,
+}
+
+impl Default for DiskIoProbe {
+    fn default() -> Self {
+        DiskIoProbe::new(DEFAULT_DISKSTATS_PATH)
+    }
+}
+
+impl DiskIoProbe {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        DiskIoProbe {
+            path: path.into(),
+            previous: HashMap::new(),
+            previous_at: None,
+        }
+    }
+
+    /// Rates since the previous call, keyed by device name; empty on the first call
+    pub fn sample(&mut self) -> HashMap<String, DiskIo> {
+        let now = Instant::now();
+        let current = fs::read_to_string(&self.path)
+            .map(|text| parse_diskstats(&text))
+            .unwrap_or_default();
+        let rates = match self.previous_at {
+            Some(at) => io_rates(&self.previous, &current, now - at),
+            None => HashMap::new(),
+        };
+        self.previous = current;
+        self.previous_at = Some(now);
+        rates
+    }
+}
+
+/// The device name diskstats uses: "/dev/mapper/root" -> "dm-0", "/dev/sda1" -> "sda1"
+fn device_name(name: &str) -> String {
+    let resolved = fs::canonicalize(name).unwrap_or_else(|_| PathBuf::from(name));
+    let resolved = resolved.to_string_lossy();
+    resolved
+        .strip_prefix("/dev/")
+        .unwrap_or(&resolved)
+        .to_owned()
+}
+
+/// Every mounted filesystem sysinfo lists, in its order
+pub fn from_disks(disks: &Disks) -> Vec<DiskStats> {
+    disks
+        .iter()
+        .map(|disk| {
+            DiskStats::new(
+                disk.mount_point().to_string_lossy(),
+                device_name(&disk.name().to_string_lossy()),
+                disk.total_space(),
+                disk.available_space(),
+            )
+        })
+        .collect()
+}
+
+/// Attach I/O rates to the disks whose device has them
+pub fn attach_io(disks: &mut [DiskStats], rates: &HashMap<String, DiskIo>) {
+    for disk in disks {
+        disk.io = rates.get(&disk.device).copied();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEFORE: &str = include_str!("../tests/fixtures/linux/diskstats/before");
+    const AFTER: &str = include_str!("../tests/fixtures/linux/diskstats/after");
+
+    #[test]
+    fn test_parse_diskstats() {
+        let stats = parse_diskstats(BEFORE);
+        assert_eq!(stats.len(), 6);
+        assert_eq!(
+            stats["nvme0n1p2"],
+            IoCounters {
+                reads: 201_833,
+                sectors_read: 12_476_898,
+                writes: 388_101,
+                sectors_written: 31_457_280,
+            }
+        );
+    }
+
+    #[test]
+    fn test_io_rates() {
+        let rates = io_rates(
+            &parse_diskstats(BEFORE),
+            &parse_diskstats(AFTER),
+            Duration::from_secs(2),
+        );
+        assert_eq!(
+            rates["nvme0n1p2"],
+            DiskIo {
+                read_bytes_per_sec: 4 * 1024 * 1024,
+                write_bytes_per_sec: 1024 * 1024,
+                read_iops: 150,
+                write_iops: 40,
+            }
+        );
+        // sdb was unplugged in between; dm-0 appeared
+        assert!(!rates.contains_key("sdb"));
+        assert!(!rates.contains_key("dm-0"));
+        assert!(io_rates(&HashMap::new(), &parse_diskstats(AFTER), Duration::ZERO).is_empty());
+    }
+
+    #[test]
+    fn test_probe_and_filter() {
+        let path =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/linux/diskstats/before");
+        let mut probe = DiskIoProbe::new(path);
+        assert!(probe.sample().is_empty());
+        assert_eq!(probe.sample()["sda"], DiskIo::default());
+
+        let mut disks = vec![
+            DiskStats::new("/", "nvme0n1p2", 500 << 30, 125 << 30),
+            DiskStats::new("/home", "sdc1", 2 << 40, 2 << 40),
+        ];
+        attach_io(&mut disks, &probe.sample());
+        assert_eq!((disks[0].size, disks[0].usage), (500, 75));
+        assert_eq!(&disks[1].unit, b"TB\0\0");
+        assert_eq!(disks[0].io, Some(DiskIo::default()));
+        assert_eq!(
+            disks[0].to_string(),
+            "/ (nvme0n1p2): 500 GB (75% used), read 0 KB/s (0 IOPS), write 0 KB/s (0 IOPS)"
+        );
+        assert_eq!(disks[1].to_string(), "/home (sdc1): 2 TB (0% used)");
+
+        let filter: DiskFilter = "/+sdc".parse().unwrap();
+        assert_eq!(filter.apply(&disks).len(), 2);
+        let filter: DiskFilter = "/home".parse().unwrap();
+        assert_eq!(filter.apply(&disks)[0].device, "sdc1");
+        assert!("nvme".parse::<DiskFilter>().unwrap().allows(&disks[0]));
+        assert_eq!("all".parse(), Ok(DiskFilter::default()));
+    }
+}
@@ -1,40 +1,531 @@
 // ======================== flipper_manager.rs ========================
 
-use btleplug::api::{Central, Manager as _, Peripheral as _};
+use async_trait::async_trait;
+use btleplug::api::{BDAddr, Central, Manager as _, Peripheral as _, ScanFilter};
 use btleplug::platform::{Adapter, Manager, Peripheral, PeripheralId};
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::Mutex;
 use uuid::Uuid;
 
+use crate::pairing::{self, DeviceSelector, PairedDevice, Pick, State};
+use crate::supervisor::{ConnectError, Connector};
+use crate::transport::{BleTransport, Transport};
+
 pub const FLIPPER_CHARACTERISTIC_UUID: Uuid =
     Uuid::from_u128(0x19ed82ae_ed21_4c9d_4145_228e62fe0000);
 
-pub async fn get_central(manager: &Manager) -> Adapter {
-    manager
-        .adapters()
-        .await
-        .unwrap()
-        .into_iter()
-        .nth(0)
-        .unwrap()
-}
-
-pub async fn get_flipper(central: &Adapter, id: &PeripheralId) -> Option<Peripheral> {
-    for p in central
-        .peripherals()
-        .await
-        .unwrap()
-        .iter()
-        .filter(|p| p.id() == *id)
-    {
-        if p.properties()
-            .await
-            .unwrap()
-            .unwrap()
-            .local_name
-            .iter()
-            .any(|name| name.contains("PC Mon"))
-        {
-            return Some(p.clone());
-        }
-    }
-    None
+/// Substrings of the advertised name that identify a Flipper running PC Monitor
+pub const FLIPPER_NAME_PATTERNS: [&str; 2] = ["PC Mon", "Flipper"];
+
+pub const DEFAULT_SCAN_DURATION: Duration = Duration::from_secs(5);
+
+/// One scan at a time per process, so reconnecting devices don't stop each other's scans
+static SCAN_LOCK: Mutex<()> = Mutex::const_new(());
+
+#[derive(Debug)]
+pub enum DiscoveryError {
+    /// The Bluetooth stack reported an error
+    Ble(btleplug::Error),
+    /// The system has no Bluetooth adapter
+    NoAdapters,
+    /// No adapter matches the requested index or name
+    AdapterNotFound(AdapterSelector),
+    /// The scan finished without seeing any matching device
+    NoMatchingDevice,
+    /// Several devices matched and none could be chosen automatically
+    Ambiguous(usize),
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiscoveryError::Ble(e) => write!(f, "bluetooth error: {}", e),
+            DiscoveryError::NoAdapters => write!(f, "no Bluetooth adapter found"),
+            DiscoveryError::AdapterNotFound(selector) => {
+                write!(f, "no Bluetooth adapter matches {}", selector)
+            }
+            DiscoveryError::NoMatchingDevice => write!(f, "no matching Flipper Zero found"),
+            DiscoveryError::Ambiguous(count) => write!(
+                f,
+                "{} Flipper Zero devices found; choose one with --device or --pick",
+                count
+            ),
+        }
+    }
+}
+
+impl Error for DiscoveryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DiscoveryError::Ble(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<btleplug::Error> for DiscoveryError {
+    fn from(e: btleplug::Error) -> Self {
+        DiscoveryError::Ble(e)
+    }
+}
+
+/// Which Bluetooth adapter to scan with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdapterSelector {
+    /// Position in the list returned by the OS
+    Index(usize),
+    /// Substring of the adapter's description (e.g. "hci1")
+    Name(String),
+}
+
+impl Default for AdapterSelector {
+    fn default() -> Self {
+        AdapterSelector::Index(0)
+    }
+}
+
+impl FromStr for AdapterSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("adapter selector must not be empty".to_owned());
+        }
+        Ok(match s.parse::<usize>() {
+            Ok(index) => AdapterSelector::Index(index),
+            Err(_) => AdapterSelector::Name(s.to_owned()),
+        })
+    }
+}
+
+impl fmt::Display for AdapterSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdapterSelector::Index(index) => write!(f, "index {}", index),
+            AdapterSelector::Name(name) => write!(f, "name '{}'", name),
+        }
+    }
+}
+
+/// A device seen during a scan
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub peripheral: Peripheral,
+    pub id: PeripheralId,
+    pub name: Option<String>,
+    pub address: BDAddr,
+    pub rssi: Option<i16>,
+    pub services: Vec<Uuid>,
+}
+
+impl Candidate {
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("Unknown")
+    }
+
+    /// Identity to remember in the state file
+    pub fn key(&self) -> PairedDevice {
+        PairedDevice {
+            id: self.id.to_string(),
+            address: self.address.to_string(),
+            name: self.name.clone(),
+        }
+    }
+
+    async fn from_peripheral(peripheral: Peripheral) -> Option<Candidate> {
+        // Devices can vanish between listing and querying
+        let properties = peripheral.properties().await.ok()??;
+        Some(Candidate {
+            id: peripheral.id(),
+            name: properties.local_name,
+            address: properties.address,
+            rssi: properties.rssi,
+            services: properties.services,
+            peripheral,
+        })
+    }
+}
+
+/// Strongest signal first; devices without an RSSI reading go last
+pub fn compare_rssi(a: Option<i16>, b: Option<i16>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => b.cmp(&a),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// How to find a Flipper: adapter, scan time and what a match looks like
+#[derive(Clone, Debug)]
+pub struct FlipperDiscovery {
+    /// Case-insensitive substrings of the advertised name; empty matches any name
+    pub name_patterns: Vec<String>,
+    /// Advertised services, at least one of which must be present; empty matches any
+    pub service_uuids: Vec<Uuid>,
+    pub adapter: AdapterSelector,
+    pub scan_duration: Duration,
+    /// Characteristic the PC Monitor app receives samples on
+    pub characteristic_uuid: Uuid,
+}
+
+impl Default for FlipperDiscovery {
+    fn default() -> Self {
+        FlipperDiscovery {
+            name_patterns: FLIPPER_NAME_PATTERNS
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            service_uuids: Vec::new(),
+            adapter: AdapterSelector::default(),
+            scan_duration: DEFAULT_SCAN_DURATION,
+            characteristic_uuid: FLIPPER_CHARACTERISTIC_UUID,
+        }
+    }
+}
+
+impl FlipperDiscovery {
+    pub fn with_name_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.name_patterns = patterns;
+        self
+    }
+
+    pub fn with_service_uuids(mut self, uuids: Vec<Uuid>) -> Self {
+        self.service_uuids = uuids;
+        self
+    }
+
+    pub fn with_adapter(mut self, adapter: AdapterSelector) -> Self {
+        self.adapter = adapter;
+        self
+    }
+
+    pub fn with_scan_duration(mut self, scan_duration: Duration) -> Self {
+        self.scan_duration = scan_duration;
+        self
+    }
+
+    pub fn with_characteristic_uuid(mut self, uuid: Uuid) -> Self {
+        self.characteristic_uuid = uuid;
+        self
+    }
+
+    /// Whether an advertisement satisfies the name and service filters
+    pub fn matches(&self, name: Option<&str>, services: &[Uuid]) -> bool {
+        let name_ok = self.name_patterns.is_empty()
+            || name.is_some_and(|name| {
+                let name = name.to_lowercase();
+                self.name_patterns
+                    .iter()
+                    .any(|p| name.contains(&p.to_lowercase()))
+            });
+        let services_ok = self.service_uuids.is_empty()
+            || self
+                .service_uuids
+                .iter()
+                .any(|uuid| services.contains(uuid));
+        name_ok && services_ok
+    }
+
+    /// Pick the configured adapter
+    pub async fn adapter(&self, manager: &Manager) -> Result<Adapter, DiscoveryError> {
+        let adapters = manager.adapters().await?;
+        if adapters.is_empty() {
+            return Err(DiscoveryError::NoAdapters);
+        }
+
+        match &self.adapter {
+            AdapterSelector::Index(index) => adapters.into_iter().nth(*index),
+            AdapterSelector::Name(name) => {
+                let mut found = None;
+                for adapter in adapters {
+                    if adapter.adapter_info().await?.contains(name.as_str()) {
+                        found = Some(adapter);
+                        break;
+                    }
+                }
+                found
+            }
+        }
+        .ok_or_else(|| DiscoveryError::AdapterNotFound(self.adapter.clone()))
+    }
+
+    /// Scan and return every device seen, strongest signal first
+    pub async fn scan(&self, central: &Adapter) -> Result<Vec<Candidate>, DiscoveryError> {
+        let _scan = SCAN_LOCK.lock().await;
+        central
+            .start_scan(ScanFilter {
+                services: self.service_uuids.clone(),
+            })
+            .await?;
+
+        // Wait a bit for devices to be discovered
+        tokio::time::sleep(self.scan_duration).await;
+
+        let peripherals = central.peripherals().await?;
+        let _ = central.stop_scan().await;
+
+        let mut candidates = Vec::with_capacity(peripherals.len());
+        for peripheral in peripherals {
+            if let Some(candidate) = Candidate::from_peripheral(peripheral).await {
+                candidates.push(candidate);
+            }
+        }
+        candidates.sort_by(|a, b| compare_rssi(a.rssi, b.rssi));
+        Ok(candidates)
+    }
+
+    /// Scan and keep only matching devices, strongest signal first
+    pub async fn candidates(&self, central: &Adapter) -> Result<Vec<Candidate>, DiscoveryError> {
+        let mut candidates = self.scan(central).await?;
+        candidates.retain(|c| self.matches(c.name.as_deref(), &c.services));
+        Ok(candidates)
+    }
+
+    /// Scan until a remembered device shows up, without waiting out the full scan
+    pub async fn find_known(
+        &self,
+        central: &Adapter,
+        known: &PairedDevice,
+    ) -> Result<Option<Candidate>, DiscoveryError> {
+        let _scan = SCAN_LOCK.lock().await;
+        central
+            .start_scan(ScanFilter {
+                services: self.service_uuids.clone(),
+            })
+            .await?;
+
+        let deadline = tokio::time::Instant::now() + self.scan_duration;
+        let mut found = None;
+        while found.is_none() && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            for peripheral in central.peripherals().await? {
+                if let Some(candidate) = Candidate::from_peripheral(peripheral).await {
+                    if candidate.key().is_same_device(known) {
+                        found = Some(candidate);
+                        break;
+                    }
+                }
+            }
+        }
+
+        let _ = central.stop_scan().await;
+        Ok(found)
+    }
+
+    /// Look up an already-known device, checking that it still matches the filters
+    pub async fn get_flipper(
+        &self,
+        central: &Adapter,
+        id: &PeripheralId,
+    ) -> Result<Option<Peripheral>, DiscoveryError> {
+        for p in central.peripherals().await? {
+            if p.id() != *id {
+                continue;
+            }
+            let Some(properties) = p.properties().await? else {
+                continue;
+            };
+            if self.matches(properties.local_name.as_deref(), &properties.services) {
+                return Ok(Some(p));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// First Bluetooth adapter on the system
+pub async fn get_central(manager: &Manager) -> Result<Adapter, DiscoveryError> {
+    FlipperDiscovery::default().adapter(manager).await
+}
+
+/// A known peripheral, if it is still present and looks like a Flipper
+pub async fn get_flipper(
+    central: &Adapter,
+    id: &PeripheralId,
+) -> Result<Option<Peripheral>, DiscoveryError> {
+    FlipperDiscovery::default().get_flipper(central, id).await
+}
+
+/// Connector that reconnects to the last Flipper directly and rescans when that fails
+pub struct BleConnector {
+    central: Adapter,
+    discovery: FlipperDiscovery,
+    selector: DeviceSelector,
+    state_path: Option<PathBuf>,
+    last: Option<PeripheralId>,
+}
+
+impl BleConnector {
+    pub fn new(central: Adapter, discovery: FlipperDiscovery) -> Self {
+        BleConnector {
+            central,
+            discovery,
+            selector: DeviceSelector::Auto,
+            state_path: None,
+            last: None,
+        }
+    }
+
+    pub fn with_selector(mut self, selector: DeviceSelector) -> Self {
+        self.selector = selector;
+        self
+    }
+
+    /// Remember the chosen device in this file and prefer it on later runs
+    pub fn with_state_file(mut self, path: Option<PathBuf>) -> Self {
+        self.state_path = path;
+        self
+    }
+
+    fn load_paired(&self) -> Option<PairedDevice> {
+        let path = self.state_path.as_ref()?;
+        match State::load(path) {
+            Ok(state) => state.paired,
+            Err(e) => {
+                println!(
+                    "⚠️  Ignoring unreadable state file {}: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    fn save_paired(&self, device: PairedDevice) {
+        let Some(path) = self.state_path.as_ref() else {
+            return;
+        };
+        let mut state = State::load(path).unwrap_or_default();
+        if state.paired.as_ref() == Some(&device) {
+            return;
+        }
+        state.paired = Some(device);
+        if let Err(e) = state.save(path) {
+            println!("⚠️  Could not save {}: {}", path.display(), e);
+        }
+    }
+
+    /// Find the device to use according to the selector and the remembered pairing
+    async fn select(&self) -> Result<Candidate, ConnectError> {
+        let paired = self.load_paired();
+
+        if let (DeviceSelector::Auto, Some(known)) = (&self.selector, &paired) {
+            println!("🔍 Looking for {}...", known.display_name());
+            if let Some(candidate) = self.discovery.find_known(&self.central, known).await? {
+                return Ok(candidate);
+            }
+            println!("   Not in range, scanning for other devices\n");
+        }
+
+        println!("🔍 Scanning for Flipper Zero devices...");
+        println!(
+            "   (Looking for devices matching {:?})\n",
+            self.discovery.name_patterns
+        );
+
+        let mut candidates = self.discovery.candidates(&self.central).await?;
+        for candidate in &candidates {
+            println!(
+                "   - {} ({} dBm)",
+                candidate.display_name(),
+                candidate.rssi.map_or("?".to_owned(), |r| r.to_string())
+            );
+        }
+        let keys: Vec<PairedDevice> = candidates.iter().map(Candidate::key).collect();
+
+        let index = match pairing::pick(&keys, paired.as_ref(), &self.selector) {
+            Pick::Chosen(index) => index,
+            Pick::NotFound => return Err(DiscoveryError::NoMatchingDevice.into()),
+            Pick::Ambiguous => {
+                let choices: Vec<_> = candidates.iter().map(|c| (c.key(), c.rssi)).collect();
+                tokio::task::spawn_blocking(move || pairing::prompt_choice(&choices))
+                    .await?
+                    .ok_or(DiscoveryError::Ambiguous(candidates.len()))?
+            }
+        };
+        Ok(candidates.swap_remove(index))
+    }
+}
+
+#[async_trait]
+impl Connector for BleConnector {
+    type Transport = BleTransport;
+
+    async fn connect(&mut self) -> Result<BleTransport, ConnectError> {
+        if let Some(id) = self.last.take() {
+            match self.discovery.get_flipper(&self.central, &id).await {
+                Ok(Some(peripheral)) => {
+                    println!("🔗 Reconnecting to Flipper Zero...");
+                    let mut transport = BleTransport::new(peripheral)
+                        .with_characteristic(self.discovery.characteristic_uuid);
+                    if transport.connect().await.is_ok() {
+                        self.last = Some(id);
+                        return Ok(transport);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => println!("   ⚠️  Lookup failed: {}", e),
+            }
+            println!("   Device not reachable, scanning again");
+        }
+
+        let candidate = self.select().await?;
+        println!("     ✓ Using Flipper Zero: {}", candidate.display_name());
+
+        // Connect to Flipper and discover its services
+        println!("\n🔗 Connecting to Flipper Zero...");
+        let mut transport = BleTransport::new(candidate.peripheral.clone())
+            .with_characteristic(self.discovery.characteristic_uuid);
+        transport.connect().await?;
+        self.save_paired(candidate.key());
+        self.last = Some(candidate.id);
+        Ok(transport)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_name_patterns() {
+        let discovery = FlipperDiscovery::default();
+        assert!(discovery.matches(Some("PC Monitor"), &[]));
+        assert!(discovery.matches(Some("Flipper Zero"), &[]));
+        assert!(discovery.matches(Some("flipper Ab1c"), &[]));
+        assert!(!discovery.matches(Some("AirPods"), &[]));
+        assert!(!discovery.matches(None, &[]));
+    }
+
+    #[test]
+    fn test_service_filter() {
+        let service = Uuid::from_u128(0x8fe5b3d5_2e7f_4a98_2a48_7acc60fe0000);
+        let discovery = FlipperDiscovery::default()
+            .with_name_patterns(Vec::new())
+            .with_service_uuids(vec![service]);
+        assert!(discovery.matches(None, &[service]));
+        assert!(!discovery.matches(Some("Flipper"), &[]));
+    }
+
+    #[test]
+    fn test_adapter_selector_from_str() {
+        assert_eq!("1".parse(), Ok(AdapterSelector::Index(1)));
+        assert_eq!("hci1".parse(), Ok(AdapterSelector::Name("hci1".to_owned())));
+        assert!(" ".parse::<AdapterSelector>().is_err());
+    }
+
+    #[test]
+    fn test_rssi_ordering() {
+        let mut rssi = vec![None, Some(-80), Some(-40), None, Some(-60)];
+        rssi.sort_by(|a, b| compare_rssi(*a, *b));
+        assert_eq!(rssi, vec![Some(-40), Some(-60), Some(-80), None, None]);
+    }
 }
@@ -0,0 +1,257 @@
+// ======================== fragment.rs ========================
+// Splitting framed messages into MTU-sized writes and putting them back together
+//
+// Each write carries a 3 byte header followed by a slice of the message:
+//
+//   seq u8 | index u8 | count u8 | chunk
+//
+// `seq` identifies the message (wrapping), `index` counts from 0 to `count - 1`.
+// Write-without-response can drop packets but never reorders them, so the
+// reassembler only has to detect gaps and start over on the next message.
+
+use std::error::Error;
+use std::fmt;
+
+pub const FRAGMENT_HEADER_LEN: usize = 3;
+
+/// Most fragments a single message can be split into
+pub const MAX_FRAGMENTS: usize = u8::MAX as usize;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FragmentError {
+    /// The MTU leaves no room for data after the header
+    MtuTooSmall(usize),
+    /// The message needs more than `MAX_FRAGMENTS` writes at this MTU
+    MessageTooLarge { size: usize, max: usize },
+    /// A write is shorter than the header or has an impossible index/count
+    Malformed,
+    /// A fragment went missing; the partial message was discarded
+    OutOfOrder { expected: u8, got: u8 },
+}
+
+impl fmt::Display for FragmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FragmentError::MtuTooSmall(mtu) => write!(f, "MTU {} is too small to fragment", mtu),
+            FragmentError::MessageTooLarge { size, max } => {
+                write!(
+                    f,
+                    "message of {} bytes exceeds the {} byte limit",
+                    size, max
+                )
+            }
+            FragmentError::Malformed => write!(f, "malformed fragment"),
+            FragmentError::OutOfOrder { expected, got } => {
+                write!(f, "expected fragment {}, got {}", expected, got)
+            }
+        }
+    }
+}
+
+impl Error for FragmentError {}
+
+/// Host side: splits messages and numbers them
+#[derive(Debug, Default)]
+pub struct Fragmenter {
+    next_seq: u8,
+}
+
+impl Fragmenter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Largest message that fits in `MAX_FRAGMENTS` writes of `mtu` bytes
+    pub fn max_message_len(mtu: usize) -> usize {
+        mtu.saturating_sub(FRAGMENT_HEADER_LEN) * MAX_FRAGMENTS
+    }
+
+    /// Split `message` into writes of at most `mtu` bytes
+    pub fn split(&mut self, message: &[u8], mtu: usize) -> Result<Vec<Vec<u8>>, FragmentError> {
+        if mtu <= FRAGMENT_HEADER_LEN {
+            return Err(FragmentError::MtuTooSmall(mtu));
+        }
+        let max = Self::max_message_len(mtu);
+        if message.len() > max {
+            return Err(FragmentError::MessageTooLarge {
+                size: message.len(),
+                max,
+            });
+        }
+
+        let chunk_len = mtu - FRAGMENT_HEADER_LEN;
+        let count = message.len().div_ceil(chunk_len).max(1);
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+
+        let fragments = (0..count)
+            .map(|index| {
+                let start = index * chunk_len;
+                let end = (start + chunk_len).min(message.len());
+                let mut fragment = Vec::with_capacity(FRAGMENT_HEADER_LEN + end - start);
+                fragment.extend_from_slice(&[seq, index as u8, count as u8]);
+                fragment.extend_from_slice(&message[start..end]);
+                fragment
+            })
+            .collect();
+        Ok(fragments)
+    }
+}
+
+struct Partial {
+    seq: u8,
+    count: u8,
+    next_index: u8,
+    data: Vec<u8>,
+}
+
+/// Device side (and tests): rebuilds messages from fragments
+pub struct Reassembler {
+    max_message: usize,
+    partial: Option<Partial>,
+}
+
+impl Reassembler {
+    /// `max_message` bounds the buffer, matching the device's advertised `max_payload`
+    pub fn new(max_message: usize) -> Self {
+        Reassembler {
+            max_message,
+            partial: None,
+        }
+    }
+
+    /// Feed one write; returns the message once its last fragment arrives
+    pub fn push(&mut self, fragment: &[u8]) -> Result<Option<Vec<u8>>, FragmentError> {
+        if fragment.len() < FRAGMENT_HEADER_LEN {
+            return Err(FragmentError::Malformed);
+        }
+        let (seq, index, count) = (fragment[0], fragment[1], fragment[2]);
+        let chunk = &fragment[FRAGMENT_HEADER_LEN..];
+        if count == 0 || index >= count {
+            self.partial = None;
+            return Err(FragmentError::Malformed);
+        }
+
+        if index == 0 {
+            // A new message always restarts, abandoning any incomplete one
+            self.partial = Some(Partial {
+                seq,
+                count,
+                next_index: 0,
+                data: Vec::new(),
+            });
+        }
+
+        let partial = match self.partial.as_mut() {
+            Some(p) if p.seq == seq && p.count == count && p.next_index == index => p,
+            Some(p) => {
+                let expected = p.next_index;
+                self.partial = None;
+                return Err(FragmentError::OutOfOrder {
+                    expected,
+                    got: index,
+                });
+            }
+            None => {
+                return Err(FragmentError::OutOfOrder {
+                    expected: 0,
+                    got: index,
+                })
+            }
+        };
+
+        if partial.data.len() + chunk.len() > self.max_message {
+            let size = partial.data.len() + chunk.len();
+            self.partial = None;
+            return Err(FragmentError::MessageTooLarge {
+                size,
+                max: self.max_message,
+            });
+        }
+        partial.data.extend_from_slice(chunk);
+        partial.next_index += 1;
+
+        if partial.next_index == partial.count {
+            Ok(self.partial.take().map(|p| p.data))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reassemble(fragments: &[Vec<u8>]) -> Option<Vec<u8>> {
+        let mut reassembler = Reassembler::new(4096);
+        let mut out = None;
+        for fragment in fragments {
+            out = reassembler.push(fragment).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let message: Vec<u8> = (0..100).collect();
+        let mut fragmenter = Fragmenter::new();
+        let fragments = fragmenter.split(&message, 20).unwrap();
+
+        assert_eq!(fragments.len(), 6);
+        assert!(fragments.iter().all(|f| f.len() <= 20));
+        assert_eq!(reassemble(&fragments).unwrap(), message);
+
+        // Sequence numbers advance per message
+        let next = fragmenter.split(b"x", 20).unwrap();
+        assert_eq!(next, vec![vec![1, 0, 1, b'x']]);
+    }
+
+    #[test]
+    fn test_empty_message_is_one_fragment() {
+        let fragments = Fragmenter::new().split(&[], 20).unwrap();
+        assert_eq!(fragments, vec![vec![0, 0, 1]]);
+        assert_eq!(reassemble(&fragments).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut fragmenter = Fragmenter::new();
+        assert_eq!(
+            fragmenter.split(b"abc", 3),
+            Err(FragmentError::MtuTooSmall(3))
+        );
+        let too_big = vec![0; Fragmenter::max_message_len(20) + 1];
+        assert!(matches!(
+            fragmenter.split(&too_big, 20),
+            Err(FragmentError::MessageTooLarge { .. })
+        ));
+
+        let fragments = fragmenter.split(&[0; 40], 20).unwrap();
+        let mut small = Reassembler::new(30);
+        assert_eq!(small.push(&fragments[0]), Ok(None));
+        assert!(matches!(
+            small.push(&fragments[1]),
+            Err(FragmentError::MessageTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_lost_fragment_is_detected_and_recovered() {
+        let mut fragmenter = Fragmenter::new();
+        let first = fragmenter.split(&[1; 40], 20).unwrap();
+        let second = fragmenter.split(&[2; 10], 20).unwrap();
+
+        let mut reassembler = Reassembler::new(4096);
+        assert_eq!(reassembler.push(&first[0]), Ok(None));
+        assert_eq!(
+            reassembler.push(&first[2]),
+            Err(FragmentError::OutOfOrder {
+                expected: 1,
+                got: 2
+            })
+        );
+        assert_eq!(reassembler.push(&second[0]), Ok(Some(vec![2; 10])));
+        assert_eq!(reassembler.push(&[0, 0]), Err(FragmentError::Malformed));
+    }
+}
@@ -2,214 +2,167 @@
 // macOS-specific GPU information retrieval
 
 use serde::Serialize;
-use std::process::Command;
 
-#[derive(Serialize, Debug, Clone)]
+use std::time::Duration;
+
+use crate::command::{CommandRunner, DEFAULT_TIMEOUT};
+use crate::ioreg::{self, IoRegEntry};
+use crate::system_profiler;
+
+/// system_profiler can be slow on machines with many displays
+const SYSTEM_PROFILER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct GpuInfo {
+    /// e.g. "Apple M2 Pro" or "AMD Radeon Pro 5500M"
+    pub model: String,
     pub gpu_usage: u64,
     pub vram_max: u64,
     pub vram_used: u64,
 }
 
 impl GpuInfo {
-    /// Get GPU information on macOS
+    /// Get information for every GPU on macOS
     /// Uses different methods depending on the GPU type (Apple Silicon vs Intel/AMD)
-    pub async fn get_gpu_info() -> Option<Self> {
-        // Try to detect if we're on Apple Silicon
-        if Self::is_apple_silicon() {
-            Self::get_apple_silicon_gpu_info().await
-        } else {
-            Self::get_intel_amd_gpu_info().await
+    pub async fn get_all_gpu_info(runner: &dyn CommandRunner) -> Vec<Self> {
+        // Try to detect if we're on Apple Silicon (M1/M2/M3/etc)
+        match Self::cpu_brand(runner).await {
+            Some(brand) if brand.contains("Apple") => {
+                Self::get_apple_silicon_gpu_info(runner, brand.trim())
+                    .await
+                    .into_iter()
+                    .collect()
+            }
+            _ => Self::get_intel_amd_gpu_info(runner).await,
         }
     }
 
-    /// Check if running on Apple Silicon (M1/M2/M3/etc)
-    fn is_apple_silicon() -> bool {
-        if let Ok(output) = Command::new("sysctl")
-            .arg("-n")
-            .arg("machdep.cpu.brand_string")
-            .output()
-        {
-            let cpu_info = String::from_utf8_lossy(&output.stdout);
-            cpu_info.contains("Apple")
-        } else {
-            false
-        }
+    /// The GPU to report when only one fits: the busiest, then the one with the most VRAM
+    pub async fn get_gpu_info(runner: &dyn CommandRunner) -> Option<Self> {
+        Self::get_all_gpu_info(runner)
+            .await
+            .into_iter()
+            .rev() // first listed wins a tie
+            .max_by_key(|gpu| (gpu.gpu_usage, gpu.vram_max))
     }
 
-    /// Get GPU info for Apple Silicon Macs
-    async fn get_apple_silicon_gpu_info() -> Option<Self> {
-        // Method 1: Try using ioreg to get GPU info
-        if let Some(info) = Self::parse_ioreg_gpu() {
-            return Some(info);
-        }
+    async fn cpu_brand(runner: &dyn CommandRunner) -> Option<String> {
+        runner
+            .run(
+                "sysctl",
+                &["-n", "machdep.cpu.brand_string"],
+                DEFAULT_TIMEOUT,
+            )
+            .await
+            .ok()
+    }
 
-        // Method 2: Try using powermetrics (requires sudo, may not work)
-        if let Some(info) = Self::parse_powermetrics_gpu() {
+    /// Get GPU info for Apple Silicon Macs.
+    ///
+    /// Usage comes from the IORegistry; a running powermetrics (see `Collector::with_power`)
+    /// overrides it later.
+    async fn get_apple_silicon_gpu_info(runner: &dyn CommandRunner, chip: &str) -> Option<Self> {
+        if let Some(mut info) = Self::parse_ioreg_gpu(runner).await {
+            if info.model.is_empty() {
+                info.model = chip.to_owned();
+            }
+            if info.vram_max == 0 {
+                // Unified memory: the GPU can use all of RAM
+                info.vram_max = Self::get_total_vram_apple_silicon(runner)
+                    .await
+                    .unwrap_or(0);
+            }
             return Some(info);
         }
 
         // Fallback: Return default values
         Some(GpuInfo {
+            model: chip.to_owned(),
             gpu_usage: 0,
-            vram_max: Self::get_total_vram_apple_silicon().unwrap_or(0),
+            vram_max: Self::get_total_vram_apple_silicon(runner)
+                .await
+                .unwrap_or(0),
             vram_used: 0,
         })
     }
 
-    /// Get GPU info for Intel/AMD GPUs on older Macs
-    async fn get_intel_amd_gpu_info() -> Option<Self> {
+    /// Get GPU info for Intel/AMD GPUs on older Macs, one entry per GPU
+    async fn get_intel_amd_gpu_info(runner: &dyn CommandRunner) -> Vec<Self> {
         // Use system_profiler to get GPU information
-        if let Some(info) = Self::parse_system_profiler_gpu() {
-            return Some(info);
+        match Self::parse_system_profiler_gpu(runner).await {
+            Some(gpus) if !gpus.is_empty() => gpus,
+            // Fallback
+            _ => vec![GpuInfo {
+                model: String::new(),
+                gpu_usage: 0,
+                vram_max: 0,
+                vram_used: 0,
+            }],
         }
-
-        // Fallback
-        Some(GpuInfo {
-            gpu_usage: 0,
-            vram_max: 0,
-            vram_used: 0,
-        })
     }
 
-    /// Parse GPU info from ioreg command
-    fn parse_ioreg_gpu() -> Option<GpuInfo> {
-        // Get GPU memory info from IORegistry
-        let output = Command::new("ioreg")
-            .arg("-r")
-            .arg("-d")
-            .arg("1")
-            .arg("-w")
-            .arg("0")
-            .arg("-c")
-            .arg("IOAccelerator")
-            .output()
+    /// Parse GPU info from the accelerators' `PerformanceStatistics` in the IORegistry
+    async fn parse_ioreg_gpu(runner: &dyn CommandRunner) -> Option<GpuInfo> {
+        let output_str = runner
+            .run("ioreg", &ioreg::ACCELERATOR_ARGS, DEFAULT_TIMEOUT)
+            .await
             .ok()?;
+        let entries = ioreg::parse(&output_str).ok()?;
 
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        
-        // Parse VRAM size (this is a simplified parser)
-        // Real implementation would need more robust parsing
-        let vram_max = Self::extract_vram_from_ioreg(&output_str);
+        // Older drivers publish no utilization; let the next method try
+        entries.iter().flat_map(IoRegEntry::walk).find_map(|entry| {
+            let stats = entry.performance_statistics()?;
+            let vram_used = stats.in_use_system_memory.or(stats.vram_used);
+            let vram_max = entry.vram_total().or_else(|| {
+                stats
+                    .vram_used
+                    .zip(stats.vram_free)
+                    .map(|(used, free)| used + free)
+            });
 
-        Some(GpuInfo {
-            gpu_usage: 0, // ioreg doesn't provide usage directly
-            vram_max: vram_max.unwrap_or(0),
-            vram_used: 0,
+            Some(GpuInfo {
+                model: entry.string("model").unwrap_or_default().to_owned(),
+                gpu_usage: stats.device_utilization?,
+                vram_max: vram_max.unwrap_or(0),
+                vram_used: vram_used.unwrap_or(0),
+            })
         })
     }
 
-    /// Extract VRAM size from ioreg output
-    fn extract_vram_from_ioreg(output: &str) -> Option<u64> {
-        // Look for "VRAM,totalMB" or similar fields
-        for line in output.lines() {
-            if line.contains("VRAM") || line.contains("vram") {
-                // Parse the value - this is simplified
-                // Real implementation needs proper parsing
-                if let Some(value_str) = line.split('=').nth(1) {
-                    let cleaned = value_str.trim().trim_matches(|c| c == '"' || c == ',');
-                    if let Ok(value) = cleaned.parse::<u64>() {
-                        return Some(value * 1024 * 1024); // Convert MB to bytes
-                    }
-                }
-            }
-        }
-        None
-    }
-
-    /// Parse GPU info from powermetrics (requires elevated privileges)
-    fn parse_powermetrics_gpu() -> Option<GpuInfo> {
-        // powermetrics requires sudo, so this might not work in all cases
-        let output = Command::new("powermetrics")
-            .arg("--samplers")
-            .arg("gpu_power")
-            .arg("-i")
-            .arg("1000")
-            .arg("-n")
-            .arg("1")
-            .output()
+    /// Parse one record per GPU from system_profiler's JSON report
+    async fn parse_system_profiler_gpu(runner: &dyn CommandRunner) -> Option<Vec<GpuInfo>> {
+        let output_str = runner
+            .run(
+                "system_profiler",
+                &system_profiler::DISPLAYS_ARGS,
+                SYSTEM_PROFILER_TIMEOUT,
+            )
+            .await
             .ok()?;
+        let records = system_profiler::parse_displays(&output_str).ok()?;
 
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        
-        // Parse GPU usage percentage
-        let gpu_usage = Self::extract_gpu_usage_from_powermetrics(&output_str);
-
-        Some(GpuInfo {
-            gpu_usage: gpu_usage.unwrap_or(0),
-            vram_max: 0,
-            vram_used: 0,
-        })
-    }
-
-    /// Extract GPU usage from powermetrics output
-    fn extract_gpu_usage_from_powermetrics(output: &str) -> Option<u64> {
-        for line in output.lines() {
-            if line.contains("GPU active residency") || line.contains("GPU Active") {
-                // Parse percentage value
-                if let Some(percent_str) = line.split(':').nth(1) {
-                    let cleaned = percent_str.trim().trim_end_matches('%');
-                    if let Ok(value) = cleaned.parse::<f64>() {
-                        return Some(value as u64);
-                    }
-                }
-            }
-        }
-        None
-    }
-
-    /// Parse GPU info from system_profiler
-    fn parse_system_profiler_gpu() -> Option<GpuInfo> {
-        let output = Command::new("system_profiler")
-            .arg("SPDisplaysDataType")
-            .output()
-            .ok()?;
-
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        
-        // Parse VRAM size
-        let vram_max = Self::extract_vram_from_system_profiler(&output_str);
-
-        Some(GpuInfo {
-            gpu_usage: 0,
-            vram_max: vram_max.unwrap_or(0),
-            vram_used: 0,
-        })
-    }
-
-    /// Extract VRAM from system_profiler output
-    fn extract_vram_from_system_profiler(output: &str) -> Option<u64> {
-        for line in output.lines() {
-            if line.contains("VRAM") || line.contains("Memory") {
-                // Look for patterns like "8 GB" or "8192 MB"
-                let words: Vec<&str> = line.split_whitespace().collect();
-                for (i, word) in words.iter().enumerate() {
-                    if word.contains("GB") && i > 0 {
-                        if let Ok(value) = words[i - 1].parse::<u64>() {
-                            return Some(value * 1024 * 1024 * 1024); // GB to bytes
-                        }
-                    } else if word.contains("MB") && i > 0 {
-                        if let Ok(value) = words[i - 1].parse::<u64>() {
-                            return Some(value * 1024 * 1024); // MB to bytes
-                        }
-                    }
-                }
-            }
-        }
-        None
+        // system_profiler has no live usage figures
+        Some(
+            records
+                .into_iter()
+                .map(|gpu| GpuInfo {
+                    model: gpu.model,
+                    gpu_usage: 0,
+                    vram_max: gpu.vram.unwrap_or(0),
+                    vram_used: 0,
+                })
+                .collect(),
+        )
     }
 
     /// Get total VRAM for Apple Silicon (unified memory)
-    fn get_total_vram_apple_silicon() -> Option<u64> {
+    async fn get_total_vram_apple_silicon(runner: &dyn CommandRunner) -> Option<u64> {
         // On Apple Silicon, GPU uses unified memory
         // We can get total system memory as an approximation
-        let output = Command::new("sysctl")
-            .arg("-n")
-            .arg("hw.memsize")
-            .output()
+        let mem_str = runner
+            .run("sysctl", &["-n", "hw.memsize"], DEFAULT_TIMEOUT)
+            .await
             .ok()?;
-
-        let mem_str = String::from_utf8_lossy(&output.stdout);
         mem_str.trim().parse::<u64>().ok()
     }
 }
@@ -217,11 +170,98 @@ impl GpuInfo {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::command::{macos_fixture, CommandError, FixtureRunner};
+
+    const GIB: u64 = 1024 * 1024 * 1024;
 
+    #[cfg(target_os = "macos")]
     #[tokio::test]
     async fn test_gpu_info() {
-        let info = GpuInfo::get_gpu_info().await;
+        let info = GpuInfo::get_gpu_info(&crate::command::SystemRunner).await;
         assert!(info.is_some());
         println!("GPU Info: {:?}", info);
     }
+
+    /// As if the machine's IORegistry published no GPU statistics
+    fn without_ioreg(runner: FixtureRunner) -> FixtureRunner {
+        let argv: Vec<&str> = std::iter::once("ioreg")
+            .chain(ioreg::ACCELERATOR_ARGS)
+            .collect();
+        runner.with_error(
+            &argv,
+            CommandError::unparseable("ioreg", "no PerformanceStatistics"),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_apple_silicon_reads_performance_statistics() {
+        let m2 = GpuInfo::get_gpu_info(&macos_fixture("m2")).await.unwrap();
+        assert_eq!(
+            m2,
+            GpuInfo {
+                model: "Apple M2 Pro".to_owned(),
+                gpu_usage: 23,
+                vram_max: 32 * GIB,
+                vram_used: 2 * GIB,
+            }
+        );
+
+        let m3 = GpuInfo::get_gpu_info(&macos_fixture("m3")).await.unwrap();
+        assert_eq!(m3.gpu_usage, 41);
+        assert_eq!(m3.vram_used, 5 * GIB);
+    }
+
+    #[tokio::test]
+    async fn test_apple_silicon_without_statistics_falls_back_to_memsize() {
+        let m2 = GpuInfo::get_gpu_info(&without_ioreg(macos_fixture("m2")))
+            .await
+            .unwrap();
+        assert_eq!(
+            m2,
+            GpuInfo {
+                model: "Apple M2 Pro".to_owned(),
+                gpu_usage: 0,
+                vram_max: 32 * GIB,
+                vram_used: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_intel_uses_system_profiler() {
+        let all = GpuInfo::get_all_gpu_info(&macos_fixture("intel")).await;
+        assert_eq!(all.len(), 2);
+        // The first GPU listed is the integrated one
+        assert_eq!(all[0].model, "Intel UHD Graphics 630");
+        assert_eq!(all[0].vram_max, 1536 * 1024 * 1024);
+
+        // With no usage figures, the discrete GPU is reported
+        let intel = GpuInfo::get_gpu_info(&macos_fixture("intel"))
+            .await
+            .unwrap();
+        assert_eq!(intel.model, "AMD Radeon Pro 5500M");
+        assert_eq!(intel.vram_max, 8 * GIB);
+        assert_eq!(intel.gpu_usage, 0);
+    }
+
+    #[tokio::test]
+    async fn test_intel_without_system_profiler_reports_zeros() {
+        let all = GpuInfo::get_all_gpu_info(&FixtureRunner::new()).await;
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].gpu_usage, 0);
+        assert_eq!(all[0].vram_max, 0);
+    }
+
+    #[tokio::test]
+    async fn test_mac_pro_lists_every_gpu() {
+        let all = GpuInfo::get_all_gpu_info(&macos_fixture("mac_pro")).await;
+        assert_eq!(all.len(), 3);
+        assert!(all.iter().all(|gpu| gpu.vram_max == 16 * GIB));
+        assert_eq!(all[2].model, "AMD Radeon RX 6800 XT");
+
+        let mac_pro = GpuInfo::get_gpu_info(&macos_fixture("mac_pro"))
+            .await
+            .unwrap();
+        assert_eq!(mac_pro, all[0]);
+    }
 }
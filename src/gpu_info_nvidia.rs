@@ -0,0 +1,223 @@
+// ======================== gpu_info_nvidia.rs ========================
+// NVIDIA GPU information from nvidia-smi
+
+use std::time::Duration;
+
+use crate::command::{CommandError, CommandRunner};
+use crate::helpers::nvd_r2u64;
+
+/// nvidia-smi can stall for seconds while the driver wakes a GPU up
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Columns requested from nvidia-smi, in output order
+pub const QUERY_FIELDS: [&str; 8] = [
+    "index",
+    "name",
+    "utilization.gpu",
+    "memory.total",
+    "memory.used",
+    "temperature.gpu",
+    "power.draw",
+    "fan.speed",
+];
+
+/// One GPU as reported by nvidia-smi; fields it shows as "[N/A]" are `None`
+#[derive(Clone, Debug, PartialEq)]
+pub struct NvidiaGpu {
+    pub index: u32,
+    pub name: String,
+    /// Percent
+    pub utilization: Option<u64>,
+    /// Bytes
+    pub memory_total: Option<u64>,
+    pub memory_used: Option<u64>,
+    /// Degrees Celsius
+    pub temperature: Option<u64>,
+    /// Watts
+    pub power_draw: Option<f64>,
+    /// Percent of maximum fan speed
+    pub fan_speed: Option<u64>,
+}
+
+/// Arguments for `nvidia-smi` that produce the CSV `parse_csv` reads
+pub fn query_args() -> Vec<String> {
+    vec![
+        format!("--query-gpu={}", QUERY_FIELDS.join(",")),
+        "--format=csv,noheader,nounits".to_owned(),
+    ]
+}
+
+/// Parse `nvidia-smi --query-gpu` output, one GPU per line.
+///
+/// Lines with the wrong number of columns are skipped.
+pub fn parse_csv(output: &str) -> Vec<NvidiaGpu> {
+    const MIB: u64 = 1024 * 1024;
+
+    output
+        .lines()
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+            if columns.len() != QUERY_FIELDS.len() {
+                return None;
+            }
+            let int = |i: usize| nvd_r2u64(columns[i].to_owned());
+            let float = |i: usize| columns[i].parse::<f64>().ok();
+
+            Some(NvidiaGpu {
+                index: int(0)? as u32,
+                name: columns[1].to_owned(),
+                utilization: int(2),
+                memory_total: int(3).map(|mib| mib * MIB),
+                memory_used: int(4).map(|mib| mib * MIB),
+                temperature: int(5),
+                power_draw: float(6),
+                fan_speed: int(7),
+            })
+        })
+        .collect()
+}
+
+/// Polls nvidia-smi, going quiet for good if it isn't installed
+pub struct NvidiaProbe {
+    runner: Box<dyn CommandRunner>,
+    available: bool,
+    latest: Vec<NvidiaGpu>,
+}
+
+impl NvidiaProbe {
+    pub fn new(runner: Box<dyn CommandRunner>) -> Self {
+        NvidiaProbe {
+            runner,
+            available: true,
+            latest: Vec::new(),
+        }
+    }
+
+    /// Every NVIDIA GPU, or none if nvidia-smi is missing or fails
+    pub async fn sample(&mut self) -> Vec<NvidiaGpu> {
+        self.latest = self.query().await.unwrap_or_default();
+        self.latest.clone()
+    }
+
+    /// What the last `sample` returned, so one nvidia-smi run serves GPUs and sensors
+    pub fn latest(&self) -> &[NvidiaGpu] {
+        &self.latest
+    }
+
+    /// Like `sample`, but says why nothing came back
+    pub async fn query(&mut self) -> Result<Vec<NvidiaGpu>, CommandError> {
+        if !self.available {
+            return Err(CommandError::NotInstalled("nvidia-smi".to_owned()));
+        }
+
+        let args = query_args();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let output = self
+            .runner
+            .run("nvidia-smi", &args, TIMEOUT)
+            .await
+            .inspect_err(|e| {
+                if matches!(e, CommandError::NotInstalled(_)) {
+                    self.available = false;
+                }
+            })?;
+
+        let gpus = parse_csv(&output);
+        if gpus.is_empty() && !output.trim().is_empty() {
+            return Err(CommandError::unparseable(
+                "nvidia-smi",
+                output.lines().next().unwrap_or_default(),
+            ));
+        }
+        Ok(gpus)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const MULTI_GPU: &str = include_str!("../tests/fixtures/nvidia/multi_gpu.csv");
+    const NA_FIELDS: &str = include_str!("../tests/fixtures/nvidia/na_fields.csv");
+
+    /// Replays one output, or fails as if nvidia-smi were not installed
+    struct Canned(Option<&'static str>, Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl CommandRunner for Canned {
+        async fn run(
+            &self,
+            program: &str,
+            args: &[&str],
+            _timeout: Duration,
+        ) -> Result<String, CommandError> {
+            assert_eq!(program, "nvidia-smi");
+            assert_eq!(args[1], "--format=csv,noheader,nounits");
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0
+                .map(str::to_owned)
+                .ok_or_else(|| CommandError::NotInstalled(program.to_owned()))
+        }
+    }
+
+    #[test]
+    fn test_parse_multi_gpu() {
+        let gpus = parse_csv(MULTI_GPU);
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].name, "NVIDIA GeForce RTX 3090");
+        assert_eq!(gpus[0].utilization, Some(45));
+        assert_eq!(gpus[0].memory_total, Some(24576 * 1024 * 1024));
+        assert_eq!(gpus[0].memory_used, Some(1837 * 1024 * 1024));
+        assert_eq!(gpus[0].temperature, Some(52));
+        assert_eq!(gpus[0].power_draw, Some(125.43));
+        assert_eq!(gpus[0].fan_speed, Some(30));
+        assert_eq!(gpus[1].index, 1);
+        assert_eq!(gpus[1].utilization, Some(97));
+    }
+
+    #[test]
+    fn test_parse_not_available_fields() {
+        let gpus = parse_csv(NA_FIELDS);
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].utilization, None);
+        assert_eq!(gpus[0].memory_total, Some(4096 * 1024 * 1024));
+        assert_eq!(gpus[0].power_draw, None);
+        assert_eq!(gpus[0].fan_speed, None);
+        assert_eq!(gpus[1].name, "Tesla T4");
+        assert_eq!(gpus[1].fan_speed, None);
+        assert_eq!(gpus[1].power_draw, Some(27.81));
+
+        assert!(parse_csv("NVIDIA-SMI has failed\n").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_probe_stops_when_not_installed() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut probe = NvidiaProbe::new(Box::new(Canned(Some(MULTI_GPU), calls.clone())));
+        assert_eq!(probe.sample().await.len(), 2);
+        assert_eq!(probe.latest().len(), 2);
+
+        let mut missing = NvidiaProbe::new(Box::new(Canned(None, calls.clone())));
+        assert!(missing.sample().await.is_empty());
+        assert!(missing.sample().await.is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_probe_reports_unparseable_output() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut probe = NvidiaProbe::new(Box::new(Canned(Some("NVIDIA-SMI has failed\n"), calls)));
+        assert_eq!(
+            probe.query().await,
+            Err(CommandError::unparseable(
+                "nvidia-smi",
+                "NVIDIA-SMI has failed"
+            ))
+        );
+        // A broken driver may recover, so keep polling
+        assert!(probe.available);
+    }
+}
@@ -1,5 +1,7 @@
 // ======================== helpers.rs ========================
 
+use std::time::Duration;
+
 pub fn avg_vecu32(v: Vec<u32>) -> u32 {
     v.iter().sum::<u32>() / v.len() as u32
 }
@@ -8,16 +10,99 @@ pub fn pop_4u8(barry: &[u8]) -> [u8; 4] {
     [barry, &[0, 0, 0, 0]].concat()[0..4].try_into().unwrap()
 }
 
+/// Parse an nvidia-smi/NVML value such as `42`, `8192 MiB`, `[8192 MiB]` or `[N/A]`
 pub fn nvd_r2u64(res: String) -> Option<u64> {
-    let mut chars = res.chars();
-    chars.next();
-    chars.next_back();
+    res.trim()
+        .trim_start_matches(['[', '"'])
+        .trim_end_matches([']', '"'])
+        .split(' ')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
 
-    match chars.as_str().split(" ").collect::<Vec<&str>>()[0]
+/// Parse a positive number of seconds such as "2" or "0.5"
+pub fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s
         .trim()
         .parse()
-    {
-        Ok(v) => Some(v),
-        _ => None,
+        .map_err(|_| format!("invalid number of seconds '{}'", s))?;
+    if !(secs.is_finite() && secs > 0.0) {
+        return Err(format!(
+            "expected a positive number of seconds, got '{}'",
+            s
+        ));
+    }
+    Ok(Duration::from_secs_f64(secs))
+}
+
+/// The items of a list like "cpu+fan" or "Tctl,Composite", trimmed, without empty ones
+pub fn split_list(s: &str) -> Vec<&str> {
+    s.split(['+', ','])
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Join items back into a list `split_list` reads
+pub fn join_list<S: AsRef<str>>(items: &[S]) -> String {
+    items
+        .iter()
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+/// Parse a list of name patterns, one of them at least; "all" gives an empty list,
+/// which selects everything. `what` names one item for the error message.
+pub fn parse_patterns(s: &str, what: &str) -> Result<Vec<String>, String> {
+    let patterns = split_list(s);
+    if patterns.is_empty() {
+        return Err(format!("at least one {} must be selected", what));
+    }
+    if patterns.iter().any(|p| p.eq_ignore_ascii_case("all")) {
+        return Ok(Vec::new());
+    }
+    Ok(patterns.into_iter().map(str::to_owned).collect())
+}
+
+/// Patterns as `parse_patterns` reads them; an empty list is "all"
+pub fn format_patterns(patterns: &[String]) -> String {
+    if patterns.is_empty() {
+        "all".to_owned()
+    } else {
+        join_list(patterns)
+    }
+}
+
+/// The longest prefix of `s` that fits in `max` bytes without splitting a character
+pub fn truncate_utf8(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// A transfer rate for the console, e.g. "812 KB/s" or "4.0 MB/s"
+pub fn format_rate(bytes_per_sec: u64) -> String {
+    if bytes_per_sec < 1024 * 1024 {
+        format!("{} KB/s", bytes_per_sec / 1024)
+    } else {
+        format!("{:.1} MB/s", bytes_per_sec as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/// A size for the console, e.g. "500 MB" or "14.5 GB"
+pub fn format_bytes(bytes: u64) -> String {
+    const MIB: f64 = 1024.0 * 1024.0;
+    if bytes < 1 << 30 {
+        format!("{} MB", (bytes as f64 / MIB).round())
+    } else {
+        format!("{:.1} GB", bytes as f64 / (MIB * 1024.0))
     }
 }
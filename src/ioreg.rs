@@ -0,0 +1,217 @@
+// ======================== ioreg.rs ========================
+// IORegistry entries from `ioreg -a` (XML plist) output
+
+use plist::{Dictionary, Value};
+
+use crate::command::CommandError;
+
+/// `ioreg` arguments that list every GPU accelerator as a plist array
+pub const ACCELERATOR_ARGS: [&str; 8] = ["-a", "-r", "-d", "1", "-w", "0", "-c", "IOAccelerator"];
+
+/// `ioreg` arguments for the internal battery's gauge readings
+pub const SMART_BATTERY_ARGS: [&str; 8] =
+    ["-a", "-r", "-d", "1", "-w", "0", "-c", "AppleSmartBattery"];
+
+const CHILDREN_KEY: &str = "IORegistryEntryChildren";
+
+/// One registry entry: its properties plus the entries below it
+#[derive(Clone, Debug, PartialEq)]
+pub struct IoRegEntry {
+    pub properties: Dictionary,
+    pub children: Vec<IoRegEntry>,
+}
+
+/// The `PerformanceStatistics` dictionary GPU drivers publish
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PerformanceStatistics {
+    /// Percent, all GPU work
+    pub device_utilization: Option<u64>,
+    pub renderer_utilization: Option<u64>,
+    pub tiler_utilization: Option<u64>,
+    /// Bytes of unified memory the GPU is using (Apple Silicon)
+    pub in_use_system_memory: Option<u64>,
+    /// Bytes of unified memory reserved for the GPU (Apple Silicon)
+    pub alloc_system_memory: Option<u64>,
+    /// Dedicated memory in bytes (AMD)
+    pub vram_used: Option<u64>,
+    pub vram_free: Option<u64>,
+}
+
+impl IoRegEntry {
+    fn from_dictionary(mut properties: Dictionary) -> Self {
+        let children = match properties.remove(CHILDREN_KEY) {
+            Some(Value::Array(children)) => children
+                .into_iter()
+                .filter_map(Value::into_dictionary)
+                .map(IoRegEntry::from_dictionary)
+                .collect(),
+            _ => Vec::new(),
+        };
+        IoRegEntry {
+            properties,
+            children,
+        }
+    }
+
+    /// The driver class, e.g. "AGXAcceleratorG13G"
+    pub fn class(&self) -> Option<&str> {
+        self.string("IOClass")
+            .or_else(|| self.string("IOObjectClass"))
+    }
+
+    pub fn string(&self, key: &str) -> Option<&str> {
+        self.properties.get(key)?.as_string()
+    }
+
+    pub fn integer(&self, key: &str) -> Option<u64> {
+        integer(self.properties.get(key)?)
+    }
+
+    /// Signed readings like battery current, which ioreg writes as unsigned two's complement
+    pub fn signed(&self, key: &str) -> Option<i64> {
+        let value = self.properties.get(key)?;
+        value
+            .as_signed_integer()
+            .or_else(|| value.as_unsigned_integer().map(|v| v as i64))
+    }
+
+    /// GPU usage counters, if this entry is an accelerator that publishes them
+    pub fn performance_statistics(&self) -> Option<PerformanceStatistics> {
+        let stats = self
+            .properties
+            .get("PerformanceStatistics")?
+            .as_dictionary()?;
+        let get = |key: &str| stats.get(key).and_then(integer);
+
+        Some(PerformanceStatistics {
+            device_utilization: get("Device Utilization %").or_else(|| get("GPU Activity(%)")),
+            renderer_utilization: get("Renderer Utilization %"),
+            tiler_utilization: get("Tiler Utilization %"),
+            in_use_system_memory: get("In use system memory"),
+            alloc_system_memory: get("Alloc system memory"),
+            vram_used: get("vramUsedBytes"),
+            vram_free: get("vramFreeBytes"),
+        })
+    }
+
+    /// Dedicated VRAM in bytes, from `VRAM,totalMB` (discrete GPUs only)
+    pub fn vram_total(&self) -> Option<u64> {
+        self.integer("VRAM,totalMB").map(|mib| mib * 1024 * 1024)
+    }
+
+    /// This entry and everything below it, depth first
+    pub fn walk(&self) -> Vec<&IoRegEntry> {
+        let mut entries = vec![self];
+        for child in &self.children {
+            entries.extend(child.walk());
+        }
+        entries
+    }
+}
+
+/// Plists store counters as signed or unsigned integers depending on the driver
+fn integer(value: &Value) -> Option<u64> {
+    value.as_unsigned_integer().or_else(|| {
+        value
+            .as_signed_integer()
+            .and_then(|v| u64::try_from(v).ok())
+    })
+}
+
+/// Parse `ioreg -a` output: an array of entries with `-r`, a single root entry without
+pub fn parse(output: &str) -> Result<Vec<IoRegEntry>, CommandError> {
+    let value = Value::from_reader_xml(output.as_bytes())
+        .map_err(|e| CommandError::unparseable("ioreg", e.to_string()))?;
+
+    match value {
+        Value::Array(entries) => Ok(entries
+            .into_iter()
+            .filter_map(Value::into_dictionary)
+            .map(IoRegEntry::from_dictionary)
+            .collect()),
+        Value::Dictionary(root) => Ok(vec![IoRegEntry::from_dictionary(root)]),
+        _ => Err(CommandError::unparseable(
+            "ioreg",
+            "expected an array or dictionary",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const M1: &str = include_str!("../tests/fixtures/macos/m1/ioreg_accelerator.plist");
+
+    /// An AMD card on an Intel Mac, as `ioreg -a -l` nests it under its PCI device
+    const NESTED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+	<key>IOObjectClass</key>
+	<string>IOPCIDevice</string>
+	<key>VRAM,totalMB</key>
+	<integer>8192</integer>
+	<key>model</key>
+	<data>UmFkZW9uIFBybyA1NTAwTQA=</data>
+	<key>IORegistryEntryChildren</key>
+	<array>
+		<dict>
+			<key>IOClass</key>
+			<string>AMDRadeonX6000_AMDNavi14GraphicsAccelerator</string>
+			<key>PerformanceStatistics</key>
+			<dict>
+				<key>Device Utilization %</key>
+				<integer>64</integer>
+				<key>vramUsedBytes</key>
+				<integer>1073741824</integer>
+				<key>vramFreeBytes</key>
+				<integer>7516192768</integer>
+			</dict>
+		</dict>
+	</array>
+</dict>
+</plist>"#;
+
+    #[test]
+    fn test_parse_apple_silicon_accelerator() {
+        let entries = parse(M1).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].class(), Some("AGXAcceleratorG13G"));
+        assert_eq!(entries[0].string("model"), Some("Apple M1"));
+        assert_eq!(entries[0].integer("gpu-core-count"), Some(8));
+        assert_eq!(entries[0].vram_total(), None);
+
+        let stats = entries[0].performance_statistics().unwrap();
+        assert_eq!(stats.device_utilization, Some(7));
+        assert_eq!(stats.renderer_utilization, Some(6));
+        assert_eq!(stats.tiler_utilization, Some(5));
+        assert_eq!(stats.in_use_system_memory, Some(390348800));
+        assert_eq!(stats.alloc_system_memory, Some(1234567168));
+        assert_eq!(stats.vram_used, None);
+    }
+
+    #[test]
+    fn test_parse_nested_entries() {
+        let root = parse(NESTED).unwrap().remove(0);
+        assert_eq!(root.class(), Some("IOPCIDevice"));
+        // `model` is raw data on PCI devices, not a string
+        assert_eq!(root.string("model"), None);
+        assert_eq!(root.vram_total(), Some(8192 * 1024 * 1024));
+        assert!(root.performance_statistics().is_none());
+
+        let stats = root
+            .walk()
+            .into_iter()
+            .find_map(IoRegEntry::performance_statistics)
+            .unwrap();
+        assert_eq!(stats.device_utilization, Some(64));
+        assert_eq!(stats.vram_used, Some(1073741824));
+        assert_eq!(stats.vram_free, Some(7516192768));
+    }
+
+    #[test]
+    fn test_parse_rejects_text_output() {
+        let text = "+-o AGXAcceleratorG13G  <class AGXAcceleratorG13G>\n";
+        assert!(matches!(parse(text), Err(CommandError::Unparseable { .. })));
+    }
+}
@@ -0,0 +1,31 @@
+// ======================== lib.rs ========================
+
+pub mod cli;
+pub mod command;
+pub mod config;
+pub mod cpu_topology;
+pub mod device_manager;
+pub mod disks;
+pub mod flipper_manager;
+pub mod fragment;
+pub mod gpu_info_linux;
+pub mod gpu_info_macos;
+pub mod gpu_info_nvidia;
+pub mod helpers;
+pub mod ioreg;
+pub mod memory;
+pub mod monitor;
+pub mod network;
+pub mod pairing;
+pub mod power;
+pub mod powermetrics;
+pub mod processes;
+pub mod protocol;
+pub mod sampler;
+pub mod sensors;
+pub mod supervisor;
+pub mod system_info;
+pub mod system_profiler;
+pub mod transport;
+pub mod wire;
+
@@ -1,197 +1,282 @@
 // ======================== main.rs ========================
 
-use btleplug::api::{
-    Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, WriteType,
-};
-use btleplug::platform::{Manager, Peripheral};
+use btleplug::platform::{Adapter, Manager};
+use clap::Parser;
 use std::error::Error;
 use std::time::Duration;
-use sysinfo::System;
-
-mod flipper_manager;
-mod helpers;
-mod system_info;
 
+use flipper_monitor_macos::cli::{Cli, Command, DemoArgs, DumpArgs, OnceArgs, RunArgs, ScanArgs};
+use flipper_monitor_macos::config::Config;
+use flipper_monitor_macos::device_manager::{DeviceManager, DeviceSpec};
+use flipper_monitor_macos::flipper_manager::{BleConnector, DiscoveryError, FlipperDiscovery};
+use flipper_monitor_macos::monitor::{print_system_info, MonitorOptions};
+use flipper_monitor_macos::pairing::{default_state_path, DeviceSelector, State};
 #[cfg(target_os = "macos")]
-mod gpu_info_macos;
-
-use flipper_manager::{get_central, FLIPPER_CHARACTERISTIC_UUID};
-use system_info::SystemInfo;
+use flipper_monitor_macos::powermetrics::spawn_powermetrics;
+use flipper_monitor_macos::protocol::{Session, PROTOCOL_VERSION};
+use flipper_monitor_macos::sampler::spawn_sampler_with;
+use flipper_monitor_macos::supervisor::SupervisorOptions;
+use flipper_monitor_macos::system_info::{Collector, SystemInfo};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    let config_path = cli.config.clone();
+    let command = cli.command();
+    let config = match Config::load(config_path.as_deref(), command.overrides()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("❌ Configuration error: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    match command {
+        Command::Scan(args) => scan(args, &config).await,
+        Command::Run(args) => run(args, &config).await,
+        Command::Demo(args) => {
+            show_system_info_demo(&args).await;
+            Ok(())
+        }
+        Command::Dump(args) => dump(&args, &config).await,
+        Command::Once(args) => once(&args).await,
+    }
+}
+
+fn print_banner() {
     println!("╔═══════════════════════════════════════════╗");
     println!("║   Flipper Monitor - macOS Version        ║");
     println!("║   System Monitor via Bluetooth LE        ║");
     println!("╚═══════════════════════════════════════════╝\n");
+}
 
-    // Initialize Bluetooth manager
-    println!("🔧 Initializing Bluetooth...");
-    let manager = Manager::new().await?;
-    let central = get_central(&manager).await;
+/// List devices in range, strongest signal first
+async fn scan(args: ScanArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let discovery = config.discovery();
+    let central = open_adapter(&discovery).await?;
+
+    println!(
+        "🔍 Scanning for {:.1}s...\n",
+        discovery.scan_duration.as_secs_f64()
+    );
+    let candidates = if args.all {
+        discovery.scan(&central).await?
+    } else {
+        discovery.candidates(&central).await?
+    };
+    if candidates.is_empty() {
+        println!("No devices found");
+        return Ok(());
+    }
 
-    println!("✓ Bluetooth adapter ready\n");
+    for candidate in &candidates {
+        println!(
+            "📱 {} ({}, {} dBm)",
+            candidate.display_name(),
+            candidate.address,
+            candidate
+                .rssi
+                .map_or("?".to_owned(), |rssi| rssi.to_string())
+        );
+        println!("   id: {}", candidate.id);
+        for service in &candidate.services {
+            println!("   service: {}", service);
+        }
+    }
+    Ok(())
+}
 
-    // Start scanning for devices
-    println!("🔍 Scanning for Flipper Zero devices...");
-    println!("   (Looking for devices with 'PC Mon' in name)\n");
+/// Connect to Flipper(s) and stream until interrupted
+async fn run(args: RunArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    print_banner();
 
-    central.start_scan(ScanFilter::default()).await?;
+    // Initialize Bluetooth manager
+    println!("🔧 Initializing Bluetooth...");
+    let discovery = config.discovery();
+    let central = match open_adapter(&discovery).await {
+        Ok(central) => central,
+        Err(e) => {
+            println!("⚠️  Bluetooth unavailable: {}\n", e);
+
+            // Still show system info even without Flipper
+            show_system_info_demo(&DemoArgs::default()).await;
+            return Ok(());
+        }
+    };
 
-    // Wait a bit for devices to be discovered
-    tokio::time::sleep(Duration::from_secs(5)).await;
+    println!("✓ Bluetooth adapter ready\n");
 
-    // Get list of discovered peripherals
-    let peripherals = central.peripherals().await?;
+    let state_path = args.state_file.or_else(default_state_path);
+    if args.forget {
+        if let Some(path) = &state_path {
+            State::default().save(path)?;
+            println!("🗑  Forgot remembered Flipper Zero\n");
+        }
+    }
 
-    if peripherals.is_empty() {
-        println!("⚠️  No Bluetooth devices found.");
-        println!("   Make sure your Flipper Zero is:");
-        println!("   1. Powered on");
-        println!("   2. Running the PC Monitor app");
-        println!("   3. In Bluetooth range\n");
-        
-        // Still show system info even without Flipper
-        show_system_info_demo().await;
-        return Ok(());
+    let base = config.monitor_options();
+    let supervisor = SupervisorOptions::default();
+    let mut devices = Vec::new();
+    if args.all {
+        println!("🔍 Scanning for Flipper Zero devices...");
+        for candidate in discovery.candidates(&central).await? {
+            let key = candidate.key();
+            println!("   • {} ({})", key.display_name(), key.address);
+            let monitor = MonitorOptions {
+                label: Some(key.display_name().to_owned()),
+                ..base.clone()
+            };
+            let connector = BleConnector::new(central.clone(), discovery.clone())
+                .with_selector(DeviceSelector::Query(key.id.clone()));
+            devices.push((key.display_name().to_owned(), connector, monitor));
+        }
+        if devices.is_empty() {
+            return Err(DiscoveryError::NoMatchingDevice.into());
+        }
+        println!();
+    } else if args.device.len() > 1 {
+        // The state file remembers a single device, so it only applies to one
+        for spec in &args.device {
+            let selector = spec
+                .query
+                .clone()
+                .map_or(DeviceSelector::Auto, DeviceSelector::Query);
+            let connector =
+                BleConnector::new(central.clone(), discovery.clone()).with_selector(selector);
+            devices.push((spec.to_string(), connector, spec.apply(&base)));
+        }
+    } else {
+        let spec: DeviceSpec = args.device.into_iter().next().unwrap_or_default();
+        let selector = match (&spec.query, args.pick) {
+            (Some(query), _) => DeviceSelector::Query(query.clone()),
+            (None, true) => DeviceSelector::Interactive,
+            (None, false) => DeviceSelector::Auto,
+        };
+        let connector = BleConnector::new(central.clone(), discovery.clone())
+            .with_selector(selector)
+            .with_state_file(state_path);
+        devices.push((spec.to_string(), connector, spec.apply(&base)));
     }
 
-    println!("📱 Found {} Bluetooth device(s)", peripherals.len());
+    // Sample once for everyone, as often as the most eager device wants
+    let interval = devices
+        .iter()
+        .map(|(_, _, monitor)| monitor.interval)
+        .min()
+        .unwrap_or(base.interval);
+    let (samples, _sampler) = spawn_sampler_with(collector(config, interval), interval);
+
+    // Keep streaming across disconnects until interrupted
+    let mut manager = DeviceManager::new(samples);
+    for (name, connector, monitor) in devices {
+        manager.add(name, connector, monitor, supervisor.clone());
+    }
+    tokio::select! {
+        results = manager.run() => {
+            if let Some((_, Err(e))) = results.into_iter().find(|(_, r)| r.is_err()) {
+                return Err(e as Box<dyn Error>);
+            }
+        }
+        _ = tokio::signal::ctrl_c() => println!("\n👋 Stopping Flipper Monitor"),
+    }
 
-    // Look for Flipper Zero
-    let mut flipper: Option<Peripheral> = None;
-    for peripheral in peripherals {
-        let properties = peripheral.properties().await?;
-        let local_name = properties
-            .as_ref()
-            .and_then(|p| p.local_name.as_ref())
-            .map(|n| n.as_str())
-            .unwrap_or("Unknown");
+    Ok(())
+}
 
-        println!("   - {}", local_name);
+/// The collector for `run`, fed by powermetrics when enabled
+fn collector(config: &Config, interval: Duration) -> Collector {
+    let collector = Collector::new();
+    if !config.powermetrics {
+        return collector;
+    }
 
-        if local_name.contains("PC Mon") || local_name.contains("Flipper") {
-            println!("     ✓ Found Flipper Zero!");
-            flipper = Some(peripheral);
-            break;
-        }
+    #[cfg(target_os = "macos")]
+    {
+        let (power, task) = spawn_powermetrics(interval);
+        tokio::spawn(async move {
+            if let Ok(Err(e)) = task.await {
+                println!("⚠️  powermetrics stopped: {}", e);
+            }
+        });
+        println!("⚡ Reading GPU residency, power and thermal pressure from powermetrics\n");
+        collector.with_power(power)
     }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = interval;
+        println!("⚠️  powermetrics is only available on macOS; ignoring\n");
+        collector
+    }
+}
 
-    if let Some(flipper_device) = flipper {
-        // Connect to Flipper
-        println!("\n🔗 Connecting to Flipper Zero...");
-        flipper_device.connect().await?;
-        println!("✓ Connected!\n");
-
-        // Discover services and characteristics
-        println!("🔎 Discovering services...");
-        flipper_device.discover_services().await?;
-        
-        let characteristics = flipper_device.characteristics();
-        let flipper_char = characteristics
-            .iter()
-            .find(|c| c.uuid == FLIPPER_CHARACTERISTIC_UUID);
-
-        if let Some(characteristic) = flipper_char {
-            println!("✓ Found Flipper characteristic\n");
-            
-            // Start monitoring loop
-            monitor_and_send_loop(&flipper_device, characteristic).await?;
-        } else {
-            println!("⚠️  Could not find Flipper characteristic UUID");
-            println!("   Expected: {}\n", FLIPPER_CHARACTERISTIC_UUID);
-            show_system_info_demo().await;
-        }
+/// Take a single sample
+async fn sample_once() -> SystemInfo {
+    let mut collector = Collector::new();
+    SystemInfo::get_system_info(&mut collector).await
+}
 
-        // Disconnect
-        flipper_device.disconnect().await?;
-        println!("\n👋 Disconnected from Flipper Zero");
+/// Print one sample, as JSON for scripts or in the console format
+async fn once(args: &OnceArgs) -> Result<(), Box<dyn Error>> {
+    let info = sample_once().await;
+    if args.json {
+        println!("{}", serde_json::to_string(&info)?);
     } else {
-        println!("\n⚠️  No Flipper Zero device found with 'PC Mon' in name\n");
-        show_system_info_demo().await;
+        print_system_info(&info);
     }
-
     Ok(())
 }
 
-/// Main monitoring loop - reads system info and sends to Flipper
-async fn monitor_and_send_loop(
-    peripheral: &Peripheral,
-    characteristic: &Characteristic,
-) -> Result<(), Box<dyn Error>> {
-    println!("╔═══════════════════════════════════════════╗");
-    println!("║   Starting System Monitor                ║");
-    println!("║   Press Ctrl+C to stop                   ║");
-    println!("╚═══════════════════════════════════════════╝\n");
-
-    let mut sys = System::new_all();
-    let mut iteration = 0;
-
-    loop {
-        iteration += 1;
-        
-        // Get current system information
-        let info = SystemInfo::get_system_info(&mut sys).await;
-
-        // Display to console
-        println!("📊 Update #{}", iteration);
-        println!("   CPU:  {}%", info.cpu_usage);
-        println!("   RAM:  {} {} ({}% used)", 
-            info.ram_max, 
-            String::from_utf8_lossy(&info.ram_unit),
-            info.ram_usage
-        );
-        println!("   GPU:  {}%", info.gpu_usage);
-        println!("   VRAM: {} {} ({}% used)", 
-            info.vram_max,
-            String::from_utf8_lossy(&info.vram_unit),
-            info.vram_usage
-        );
-
-        // Serialize to JSON and send to Flipper
-        match serde_json::to_vec(&info) {
-            Ok(data) => {
-                match peripheral.write(characteristic, &data, WriteType::WithoutResponse).await {
-                    Ok(_) => println!("   ✓ Sent to Flipper Zero\n"),
-                    Err(e) => println!("   ⚠️  Failed to send: {}\n", e),
-                }
-            }
-            Err(e) => println!("   ⚠️  Failed to serialize: {}\n", e),
+/// Print one sample exactly as it would go over the air, in hex
+async fn dump(args: &DumpArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let options = config.monitor_options();
+    let fields = options.capabilities.fields;
+    let session = if args.framed {
+        Session::Framed {
+            version: PROTOCOL_VERSION,
+            fields,
+            encoding: options.encoding,
+            max_payload: u16::MAX,
         }
+    } else {
+        Session::Legacy {
+            encoding: options.encoding,
+            fields,
+        }
+    };
 
-        // Wait before next update (adjust as needed)
-        tokio::time::sleep(Duration::from_secs(2)).await;
+    let info = options.apply(sample_once().await);
+    // One line per message
+    for message in session.encode_messages(&info)? {
+        let hex: Vec<String> = message.iter().map(|b| format!("{:02x}", b)).collect();
+        println!("{}", hex.join(" "));
     }
+    Ok(())
+}
+
+/// Initialize the Bluetooth manager and pick the configured adapter
+async fn open_adapter(discovery: &FlipperDiscovery) -> Result<Adapter, DiscoveryError> {
+    let manager = Manager::new().await?;
+    discovery.adapter(&manager).await
 }
 
 /// Show system info demo without Flipper connection
-async fn show_system_info_demo() {
+async fn show_system_info_demo(args: &DemoArgs) {
     println!("╔═══════════════════════════════════════════╗");
     println!("║   System Information Demo                ║");
     println!("║   (Running without Flipper connection)   ║");
     println!("╚═══════════════════════════════════════════╝\n");
 
-    let mut sys = System::new_all();
+    let mut collector = Collector::new();
 
-    for i in 1..=5 {
-        let info = SystemInfo::get_system_info(&mut sys).await;
+    for i in 1..=args.count {
+        let info = SystemInfo::get_system_info(&mut collector).await;
 
         println!("📊 Reading #{}", i);
-        println!("   CPU:  {}%", info.cpu_usage);
-        println!("   RAM:  {} {} ({}% used)", 
-            info.ram_max, 
-            String::from_utf8_lossy(&info.ram_unit),
-            info.ram_usage
-        );
-        println!("   GPU:  {}%", info.gpu_usage);
-        println!("   VRAM: {} {} ({}% used)\n", 
-            info.vram_max,
-            String::from_utf8_lossy(&info.vram_unit),
-            info.vram_usage
-        );
+        print_system_info(&info);
+        println!();
 
-        if i < 5 {
-            tokio::time::sleep(Duration::from_secs(2)).await;
+        if i < args.count {
+            tokio::time::sleep(args.interval).await;
         }
     }
 
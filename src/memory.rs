@@ -0,0 +1,355 @@
+// ======================== memory.rs ========================
+// Memory breakdown, swap and pressure from /proc/meminfo, vm_stat and sysinfo
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use sysinfo::System;
+
+use crate::command::{CommandRunner, DEFAULT_TIMEOUT};
+
+pub const DEFAULT_MEMINFO_PATH: &str = "/proc/meminfo";
+
+/// How hard the system is working to find free memory (macOS only)
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryPressure {
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl MemoryPressure {
+    pub fn name(self) -> &'static str {
+        match self {
+            MemoryPressure::Normal => "normal",
+            MemoryPressure::Warning => "warning",
+            MemoryPressure::Critical => "critical",
+        }
+    }
+
+    /// 0 is reserved for "unknown" on the wire
+    pub fn to_u8(self) -> u8 {
+        match self {
+            MemoryPressure::Normal => 1,
+            MemoryPressure::Warning => 2,
+            MemoryPressure::Critical => 3,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(MemoryPressure::Normal),
+            2 => Some(MemoryPressure::Warning),
+            3 => Some(MemoryPressure::Critical),
+            _ => None,
+        }
+    }
+
+    /// `kern.memorystatus_vm_pressure_level`: 1 normal, 2 warning, 4 critical
+    pub fn from_sysctl(text: &str) -> Option<Self> {
+        match text.trim() {
+            "1" => Some(MemoryPressure::Normal),
+            "2" => Some(MemoryPressure::Warning),
+            "4" => Some(MemoryPressure::Critical),
+            _ => None,
+        }
+    }
+}
+
+/// Memory and swap, in bytes. Fields the platform doesn't expose are `None`.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub total: u64,
+    /// By the chosen `RamUsed` definition
+    pub used: u64,
+    /// What can be handed to applications without swapping
+    pub available: u64,
+    /// Not used for anything, not even caches
+    pub free: u64,
+    /// Page cache and reclaimable kernel caches (Linux), file-backed and purgeable pages (macOS)
+    pub cached: Option<u64>,
+    /// Block device buffers (Linux)
+    pub buffers: Option<u64>,
+    /// Memory holding compressed pages: zswap (Linux) or the compressor (macOS)
+    pub compressed: Option<u64>,
+    /// Pages the kernel can't page out (macOS)
+    pub wired: Option<u64>,
+    pub swap_total: u64,
+    pub swap_used: u64,
+    pub pressure: Option<MemoryPressure>,
+}
+
+impl MemoryStats {
+    /// What sysinfo knows on every platform
+    pub fn from_system(system: &System) -> Self {
+        MemoryStats {
+            total: system.total_memory(),
+            available: system.available_memory(),
+            free: system.free_memory(),
+            swap_total: system.total_swap(),
+            swap_used: system.used_swap(),
+            ..MemoryStats::default()
+        }
+        .with_used(RamUsed::default())
+    }
+
+    /// Recompute `used` by `definition`
+    pub fn with_used(mut self, definition: RamUsed) -> Self {
+        self.used = definition.used(&self);
+        self
+    }
+
+    /// Replace the sysinfo estimate with vm_stat's breakdown, the way Activity Monitor
+    /// counts it: file-backed and purgeable pages are cache, and are available.
+    pub fn with_vm_stat(mut self, vm: &VmStat) -> Self {
+        let page = |key: &str| vm.bytes(key).unwrap_or(0);
+        let free = page("Pages free") + page("Pages speculative");
+        let cached = page("File-backed pages") + page("Pages purgeable");
+
+        self.free = free.min(self.total);
+        self.available = (free + cached).min(self.total);
+        self.cached = Some(cached);
+        self.compressed = vm.bytes("Pages occupied by compressor");
+        self.wired = vm.bytes("Pages wired down");
+        self
+    }
+
+    /// `used` as a percentage of `total`
+    pub fn usage(&self) -> u8 {
+        if self.total > 0 {
+            ((self.used as f64 / self.total as f64) * 100.0) as u8
+        } else {
+            0
+        }
+    }
+}
+
+/// What counts as "used" RAM in the percentage sent to the Flipper
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RamUsed {
+    /// Everything that isn't available: caches the kernel can drop don't count
+    #[default]
+    Available,
+    /// Everything but free memory, caches and buffers; on macOS this matches
+    /// Activity Monitor's "Memory Used" (app, wired and compressed)
+    NoCache,
+    /// Everything that isn't free, caches included
+    Free,
+}
+
+impl RamUsed {
+    pub fn used(self, memory: &MemoryStats) -> u64 {
+        let total = memory.total;
+        match self {
+            RamUsed::Available => total.saturating_sub(memory.available),
+            RamUsed::NoCache => total
+                .saturating_sub(memory.free)
+                .saturating_sub(memory.cached.unwrap_or(0))
+                .saturating_sub(memory.buffers.unwrap_or(0)),
+            RamUsed::Free => total.saturating_sub(memory.free),
+        }
+    }
+}
+
+impl FromStr for RamUsed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "available" => Ok(RamUsed::Available),
+            "nocache" => Ok(RamUsed::NoCache),
+            "free" => Ok(RamUsed::Free),
+            other => Err(format!(
+                "unknown RAM usage '{}' (expected available, nocache or free)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RamUsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RamUsed::Available => write!(f, "available"),
+            RamUsed::NoCache => write!(f, "nocache"),
+            RamUsed::Free => write!(f, "free"),
+        }
+    }
+}
+
+/// Parse `/proc/meminfo` into bytes, keyed by field name
+pub fn parse_meminfo(text: &str) -> HashMap<String, u64> {
+    text.lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            let mut parts = value.split_whitespace();
+            let number: u64 = parts.next()?.parse().ok()?;
+            let bytes = match parts.next() {
+                Some("kB") => number * 1024,
+                // HugePages_* are counts
+                _ => number,
+            };
+            Some((key.to_owned(), bytes))
+        })
+        .collect()
+}
+
+/// Memory from `/proc/meminfo`; `None` if it can't be read
+pub fn read_meminfo(path: &Path) -> Option<MemoryStats> {
+    let fields = parse_meminfo(&fs::read_to_string(path).ok()?);
+    let field = |key: &str| fields.get(key).copied();
+    let swap_total = field("SwapTotal").unwrap_or(0);
+
+    Some(
+        MemoryStats {
+            total: field("MemTotal")?,
+            free: field("MemFree")?,
+            // Kernels before 3.14 have no MemAvailable
+            available: field("MemAvailable").or(field("MemFree"))?,
+            cached: field("Cached").map(|cached| cached + field("SReclaimable").unwrap_or(0)),
+            buffers: field("Buffers"),
+            compressed: field("Zswap"),
+            swap_total,
+            swap_used: swap_total.saturating_sub(field("SwapFree").unwrap_or(0)),
+            ..MemoryStats::default()
+        }
+        .with_used(RamUsed::default()),
+    )
+}
+
+/// `vm_stat` counters in pages, and the page size they are counted in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmStat {
+    pub page_size: u64,
+    pub pages: HashMap<String, u64>,
+}
+
+impl VmStat {
+    /// A counter in bytes, e.g. `bytes("Pages wired down")`
+    pub fn bytes(&self, key: &str) -> Option<u64> {
+        Some(self.pages.get(key)? * self.page_size)
+    }
+}
+
+/// Parse `vm_stat`: a page-size header, then "Name:   12345." lines
+pub fn parse_vm_stat(output: &str) -> Option<VmStat> {
+    let mut lines = output.lines();
+    let page_size = lines
+        .next()?
+        .split("page size of ")
+        .nth(1)?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+    let pages = lines
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            let count = value.trim().trim_end_matches('.').parse().ok()?;
+            Some((key.trim().trim_matches('"').to_owned(), count))
+        })
+        .collect();
+    Some(VmStat { page_size, pages })
+}
+
+/// Refine `base` (from sysinfo) with `vm_stat` and the kernel's pressure level
+pub async fn macos_memory(runner: &dyn CommandRunner, base: MemoryStats) -> MemoryStats {
+    let mut memory = match runner.run("vm_stat", &[], DEFAULT_TIMEOUT).await {
+        Ok(output) => match parse_vm_stat(&output) {
+            Some(vm) => base.with_vm_stat(&vm),
+            None => base,
+        },
+        Err(_) => base,
+    };
+    memory.pressure = runner
+        .run(
+            "sysctl",
+            &["-n", "kern.memorystatus_vm_pressure_level"],
+            DEFAULT_TIMEOUT,
+        )
+        .await
+        .ok()
+        .and_then(|output| MemoryPressure::from_sysctl(&output));
+    memory.with_used(RamUsed::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{macos_fixture, FixtureRunner};
+
+    const MIB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_read_meminfo() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/linux/proc/meminfo");
+        let memory = read_meminfo(&path).unwrap();
+        assert_eq!(memory.total, 32000 * MIB);
+        assert_eq!(memory.available, 20000 * MIB);
+        assert_eq!(memory.cached, Some(14800 * MIB));
+        assert_eq!(memory.buffers, Some(500 * MIB));
+        assert_eq!(memory.compressed, Some(100 * MIB));
+        assert_eq!(memory.wired, None);
+        assert_eq!(memory.swap_used, 256 * MIB);
+        assert_eq!(memory.pressure, None);
+
+        assert_eq!((memory.used, memory.usage()), (12000 * MIB, 37));
+        let memory = memory.with_used(RamUsed::NoCache);
+        assert_eq!((memory.used, memory.usage()), (12700 * MIB, 39));
+        assert_eq!(memory.with_used(RamUsed::Free).usage(), 87);
+
+        assert_eq!(read_meminfo(Path::new("/nonexistent")), None);
+    }
+
+    #[tokio::test]
+    async fn test_macos_memory() {
+        let base = MemoryStats {
+            total: 32 << 30,
+            available: 0,
+            free: 0,
+            swap_total: 2 << 30,
+            swap_used: 1 << 30,
+            ..MemoryStats::default()
+        };
+
+        let pages = |n: u64| n * 16384;
+        let memory = macos_memory(&macos_fixture("m2"), base.clone()).await;
+        assert_eq!(memory.free, pages(61249 + 18044));
+        assert_eq!(memory.cached, Some(pages(602188 + 12117)));
+        assert_eq!(memory.available, pages(61249 + 18044 + 602188 + 12117));
+        assert_eq!(memory.compressed, Some(pages(391203)));
+        assert_eq!(memory.wired, Some(pages(215033)));
+        assert_eq!(memory.swap_used, 1 << 30);
+        assert_eq!(memory.pressure, Some(MemoryPressure::Normal));
+        // sysinfo's estimate counted inactive file pages; this is closer to Activity Monitor
+        assert_eq!(memory.usage(), 66);
+
+        let memory = macos_memory(&macos_fixture("intel"), base.clone()).await;
+        assert_eq!(memory.wired, Some(998122 * 4096));
+        assert_eq!(memory.pressure, Some(MemoryPressure::Warning));
+
+        // No vm_stat: the sysinfo figures are kept
+        let memory = macos_memory(&FixtureRunner::new(), base.clone()).await;
+        assert_eq!((memory.cached, memory.pressure), (None, None));
+        assert_eq!(memory.used, 32 << 30);
+    }
+
+    #[test]
+    fn test_ram_used_from_str() {
+        assert_eq!("NoCache".parse(), Ok(RamUsed::NoCache));
+        assert_eq!(
+            "available".parse::<RamUsed>().unwrap().to_string(),
+            "available"
+        );
+        assert!("used".parse::<RamUsed>().is_err());
+        assert_eq!(
+            MemoryPressure::from_sysctl("4\n"),
+            Some(MemoryPressure::Critical)
+        );
+        assert_eq!(MemoryPressure::from_sysctl("0"), None);
+    }
+}
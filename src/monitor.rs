@@ -0,0 +1,476 @@
+// ======================== monitor.rs ========================
+// Sampling loop that streams system information over a Transport
+
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use crate::cpu_topology::CoreKind;
+use crate::disks::DiskFilter;
+use crate::fragment::{FragmentError, Fragmenter};
+use crate::helpers::{format_bytes, format_rate};
+use crate::memory::{MemoryStats, RamUsed};
+use crate::network::InterfaceFilter;
+use crate::processes::{ProcessStats, DEFAULT_PROCESSES};
+use crate::protocol::{self, Capabilities, Session, DEFAULT_HANDSHAKE_TIMEOUT};
+use crate::sampler::{latest_sample, SampleReceiver};
+use crate::sensors::SensorFilter;
+use crate::system_info::{CoreStats, GpuSelector, SystemInfo};
+use crate::transport::{Transport, TransportError};
+use crate::wire::Encoding;
+
+/// Settings for one streaming connection
+#[derive(Clone, Debug)]
+pub struct MonitorOptions {
+    pub interval: Duration,
+    /// Encoding used for legacy devices, and preferred for framed ones
+    pub encoding: Encoding,
+    /// What the host offers in its Hello
+    pub capabilities: Capabilities,
+    pub handshake_timeout: Duration,
+    /// Prefix for console output when several devices share the terminal
+    pub label: Option<String>,
+    /// What counts as used in the RAM percentage
+    pub ram_used: RamUsed,
+    /// GPU reported in the single-GPU fields
+    pub gpu: GpuSelector,
+    /// Sensors shown and sent
+    pub sensors: SensorFilter,
+    /// Disks shown and sent
+    pub disks: DiskFilter,
+    /// Network interfaces shown and sent
+    pub interfaces: InterfaceFilter,
+    /// Processes shown and sent in each top list
+    pub processes: usize,
+}
+
+impl Default for MonitorOptions {
+    fn default() -> Self {
+        MonitorOptions {
+            interval: Duration::from_secs(2),
+            encoding: Encoding::Packed,
+            capabilities: Capabilities::host(),
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            label: None,
+            ram_used: RamUsed::default(),
+            gpu: GpuSelector::default(),
+            sensors: SensorFilter::default(),
+            disks: DiskFilter::default(),
+            interfaces: InterfaceFilter::default(),
+            processes: DEFAULT_PROCESSES,
+        }
+    }
+}
+
+impl MonitorOptions {
+    /// `info` as this device gets it: RAM definition, GPU and filters applied
+    pub fn apply(&self, info: SystemInfo) -> SystemInfo {
+        info.with_ram_used(self.ram_used)
+            .with_gpu(&self.gpu)
+            .with_sensors(&self.sensors)
+            .with_disks(&self.disks)
+            .with_network(&self.interfaces)
+            .with_processes(self.processes)
+    }
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    Transport(TransportError),
+    Fragment(FragmentError),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Transport(e) => write!(f, "{}", e),
+            SendError::Fragment(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for SendError {}
+
+/// Put one encoded message on the wire.
+///
+/// Framed sessions are split into MTU-sized fragments; legacy devices get the
+/// message as a single write because they cannot reassemble.
+pub async fn send_message<T: Transport + ?Sized>(
+    transport: &mut T,
+    session: &Session,
+    fragmenter: &mut Fragmenter,
+    message: &[u8],
+) -> Result<(), SendError> {
+    let writes = match *session {
+        Session::Legacy { .. } => vec![message.to_vec()],
+        Session::Framed { max_payload, .. } => {
+            if message.len() > max_payload as usize {
+                return Err(SendError::Fragment(FragmentError::MessageTooLarge {
+                    size: message.len(),
+                    max: max_payload as usize,
+                }));
+            }
+            fragmenter
+                .split(message, transport.mtu())
+                .map_err(SendError::Fragment)?
+        }
+    };
+
+    for write in writes {
+        transport
+            .send_frame(&write)
+            .await
+            .map_err(SendError::Transport)?;
+    }
+    Ok(())
+}
+
+/// Main monitoring loop - reads samples and sends them to Flipper
+///
+/// Returns an error once the link is lost so the caller can decide whether to
+/// reconnect, and `Ok` when the sampler has stopped.
+pub async fn monitor_and_send_loop<T: Transport + ?Sized>(
+    transport: &mut T,
+    samples: &mut SampleReceiver,
+    options: &MonitorOptions,
+) -> Result<(), TransportError> {
+    println!("╔═══════════════════════════════════════════╗");
+    println!("║   Starting System Monitor                ║");
+    println!("║   Press Ctrl+C to stop                   ║");
+    println!("╚═══════════════════════════════════════════╝\n");
+
+    let session = protocol::negotiate(
+        transport,
+        &options.capabilities,
+        options.encoding,
+        options.handshake_timeout,
+    )
+    .await?;
+    match session {
+        Session::Legacy { encoding, .. } => {
+            println!("🤝 No handshake reply, using legacy {} format\n", encoding)
+        }
+        Session::Framed {
+            version, encoding, ..
+        } => println!(
+            "🤝 Protocol v{} negotiated ({} payloads)\n",
+            version, encoding
+        ),
+    }
+
+    let mut fragmenter = Fragmenter::new();
+    let mut iteration = 0;
+    let prefix = options
+        .label
+        .as_ref()
+        .map_or(String::new(), |label| format!("[{}] ", label));
+
+    loop {
+        iteration += 1;
+
+        // Latest shared sample
+        let Some(info) = latest_sample(samples).await else {
+            return Ok(());
+        };
+        let info = options.apply((*info).clone());
+
+        // Display to console
+        println!("📊 {}Update #{}", prefix, iteration);
+        print_system_info(&info);
+
+        // Serialize and send to Flipper
+        match session.encode_messages(&info) {
+            Ok(messages) => {
+                let mut sent = true;
+                for message in messages {
+                    match send_message(transport, &session, &mut fragmenter, &message).await {
+                        Ok(_) => {}
+                        Err(SendError::Transport(
+                            e @ (TransportError::Disconnected | TransportError::NotConnected),
+                        )) => {
+                            println!("   ⚠️  Lost connection: {}\n", e);
+                            return Err(e);
+                        }
+                        Err(e) => {
+                            println!("   ⚠️  Failed to send: {}", e);
+                            sent = false;
+                        }
+                    }
+                }
+                if sent {
+                    println!("   ✓ Sent to Flipper Zero\n");
+                } else {
+                    println!();
+                }
+            }
+            Err(e) => println!("   ⚠️  Failed to encode: {}\n", e),
+        }
+
+        // Wait before next update (adjust as needed)
+        tokio::time::sleep(options.interval).await;
+    }
+}
+
+/// Print a sample in the console format shared by the monitor and the demo
+pub fn print_system_info(info: &SystemInfo) {
+    println!("   CPU:  {}%", info.cpu_usage);
+    print_cores(&info.cores);
+    println!(
+        "   RAM:  {} {} ({}% used)",
+        info.ram_max,
+        String::from_utf8_lossy(&info.ram_unit),
+        info.ram_usage
+    );
+    print_memory(&info.memory);
+    println!("   GPU:  {}%", info.gpu_usage);
+    println!(
+        "   VRAM: {} {} ({}% used)",
+        info.vram_max,
+        String::from_utf8_lossy(&info.vram_unit),
+        info.vram_usage
+    );
+    if info.gpus.len() > 1 {
+        for (index, gpu) in info.gpus.iter().enumerate() {
+            let power = gpu
+                .power_mw
+                .map(|mw| format!(", {:.1} W", mw as f64 / 1000.0))
+                .unwrap_or_default();
+            println!(
+                "     #{} {}: {}%, {} {} ({}% used){}",
+                index,
+                gpu.name,
+                gpu.usage,
+                gpu.vram_max,
+                String::from_utf8_lossy(&gpu.vram_unit),
+                gpu.vram_usage,
+                power
+            );
+        }
+    }
+    for sensor in &info.sensors {
+        println!("   🌡  {}", sensor);
+    }
+    for disk in &info.disks {
+        println!("   💾 {}", disk);
+    }
+    if !info.network.interfaces.is_empty() {
+        println!(
+            "   🌐 Network: ↓ {} ↑ {}",
+            format_rate(info.network.rx_bytes_per_sec),
+            format_rate(info.network.tx_bytes_per_sec)
+        );
+        for interface in &info.network.interfaces {
+            println!("     {}", interface);
+        }
+    }
+    if info.power.battery.is_some() {
+        println!("   🔋 Battery: {}", info.power);
+    } else if !info.power.is_empty() {
+        println!("   🔌 Power: {}", info.power);
+    }
+    print_processes("Top CPU", &info.processes.by_cpu);
+    print_processes("Top memory", &info.processes.by_memory);
+}
+
+fn print_processes(title: &str, processes: &[ProcessStats]) {
+    if processes.is_empty() {
+        return;
+    }
+    println!("   🔝 {}:", title);
+    for process in processes {
+        println!("     {}", process);
+    }
+}
+
+/// Where the RAM goes, and swap, for whatever the platform reports
+fn print_memory(memory: &MemoryStats) {
+    if memory.total == 0 {
+        return;
+    }
+    let mut parts = vec![
+        format!("{} used", format_bytes(memory.used)),
+        format!("{} available", format_bytes(memory.available)),
+    ];
+    let optional = [
+        ("cached", memory.cached),
+        ("buffers", memory.buffers),
+        ("compressed", memory.compressed),
+        ("wired", memory.wired),
+    ];
+    for (label, bytes) in optional {
+        if let Some(bytes) = bytes {
+            parts.push(format!("{} {}", format_bytes(bytes), label));
+        }
+    }
+    println!("     {}", parts.join(", "));
+
+    let pressure = memory
+        .pressure
+        .map_or(String::new(), |p| format!(", pressure {}", p.name()));
+    println!(
+        "   Swap: {} of {}{}",
+        format_bytes(memory.swap_used),
+        format_bytes(memory.swap_total),
+        pressure
+    );
+}
+
+/// One line of per-core usage per kind of core
+fn print_cores(cores: &[CoreStats]) {
+    let groups = [
+        (None, "Cores"),
+        (Some(CoreKind::Performance), "P-cores"),
+        (Some(CoreKind::Efficiency), "E-cores"),
+    ];
+    for (kind, label) in groups {
+        let group: Vec<&CoreStats> = cores.iter().filter(|core| core.kind == kind).collect();
+        let Some(max_mhz) = group.iter().map(|core| core.frequency).max() else {
+            continue;
+        };
+        let usages: Vec<String> = group.iter().map(|core| core.usage.to_string()).collect();
+        println!(
+            "     {}: {} % (up to {} MHz)",
+            label,
+            usages.join(" "),
+            max_mhz
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fragment::Reassembler;
+    use crate::protocol::{decode_frame, encode_frame, Encodings, MessageType, MetricFields};
+    use crate::sampler::spawn_sampler;
+    use crate::transport::{ChannelTransport, DEFAULT_BLE_MTU};
+    use crate::wire;
+
+    fn options(encoding: Encoding) -> MonitorOptions {
+        MonitorOptions {
+            interval: Duration::from_millis(10),
+            encoding,
+            handshake_timeout: Duration::from_millis(50),
+            ..MonitorOptions::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_loop_streams_until_disconnect() {
+        let (mut transport, mut peer) = ChannelTransport::pair(DEFAULT_BLE_MTU);
+        transport.connect().await.unwrap();
+
+        let host = tokio::spawn(async move {
+            let (mut samples, _) = spawn_sampler(Duration::from_millis(10));
+            monitor_and_send_loop(&mut transport, &mut samples, &options(Encoding::Packed)).await
+        });
+
+        // Hello goes unanswered, as with the stock Flipper app
+        assert!(peer.recv().await.is_some());
+        for _ in 0..2 {
+            let frame = peer.recv().await.unwrap();
+            assert!(frame.len() <= DEFAULT_BLE_MTU);
+            let info = wire::decode_packed(&frame).unwrap();
+            assert!(info.ram_max > 0);
+        }
+
+        peer.set_link(false);
+        let result = host.await.unwrap();
+        assert!(matches!(result, Err(TransportError::Disconnected)));
+    }
+
+    #[tokio::test]
+    async fn test_loop_json_encoding() {
+        let (mut transport, mut peer) = ChannelTransport::pair(DEFAULT_BLE_MTU);
+        transport.connect().await.unwrap();
+
+        let host = tokio::spawn(async move {
+            let (mut samples, _) = spawn_sampler(Duration::from_millis(10));
+            monitor_and_send_loop(&mut transport, &mut samples, &options(Encoding::Json)).await
+        });
+
+        assert!(peer.recv().await.is_some());
+        let frame = peer.recv().await.unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&frame).unwrap();
+        assert!(value.get("cpu_usage").is_some());
+
+        drop(peer);
+        assert!(host.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_loop_sends_framed_metrics_after_handshake() {
+        let (mut transport, mut peer) = ChannelTransport::pair(64);
+        transport.connect().await.unwrap();
+
+        let host = tokio::spawn(async move {
+            let (mut samples, _) = spawn_sampler(Duration::from_millis(10));
+            monitor_and_send_loop(&mut transport, &mut samples, &options(Encoding::Packed)).await
+        });
+
+        let hello = decode_frame(&peer.recv().await.unwrap()).unwrap();
+        assert_eq!(hello.msg_type, MessageType::Hello);
+        let caps = Capabilities {
+            version: 1,
+            fields: MetricFields::CPU.union(MetricFields::RAM),
+            encodings: Encodings::PACKED,
+            max_payload: 64,
+        };
+        peer.send(
+            &encode_frame(MessageType::Capabilities, Encoding::Packed, &caps.encode()).unwrap(),
+        );
+
+        let mut reassembler = Reassembler::new(caps.max_payload as usize);
+        let message = loop {
+            if let Some(message) = reassembler.push(&peer.recv().await.unwrap()).unwrap() {
+                break message;
+            }
+        };
+        let frame = decode_frame(&message).unwrap();
+        assert_eq!(frame.msg_type, MessageType::Metrics);
+        let (fields, info) = protocol::decode_metrics(&frame.payload).unwrap();
+        assert_eq!(fields, caps.fields);
+        assert!(info.ram_max > 0);
+
+        drop(peer);
+        assert!(host.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_message_fragments_to_mtu() {
+        let (mut transport, mut peer) = ChannelTransport::pair(DEFAULT_BLE_MTU);
+        transport.connect().await.unwrap();
+        let session = Session::Framed {
+            version: 1,
+            fields: MetricFields::all(),
+            encoding: Encoding::Json,
+            max_payload: 512,
+        };
+        let message: Vec<u8> = (0..=255).collect();
+
+        let mut fragmenter = Fragmenter::new();
+        send_message(&mut transport, &session, &mut fragmenter, &message)
+            .await
+            .unwrap();
+        drop(transport);
+
+        let mut reassembler = Reassembler::new(512);
+        let mut writes = 0;
+        let mut result = None;
+        while let Some(write) = peer.recv().await {
+            assert!(write.len() <= DEFAULT_BLE_MTU);
+            writes += 1;
+            result = reassembler.push(&write).unwrap();
+        }
+        assert_eq!(writes, 16);
+        assert_eq!(result.unwrap(), message);
+
+        let (mut transport, _peer) = ChannelTransport::pair(DEFAULT_BLE_MTU);
+        transport.connect().await.unwrap();
+        let too_big = vec![0; 513];
+        assert!(matches!(
+            send_message(&mut transport, &session, &mut fragmenter, &too_big).await,
+            Err(SendError::Fragment(FragmentError::MessageTooLarge { .. }))
+        ));
+    }
+}
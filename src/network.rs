@@ -0,0 +1,519 @@
+// ======================== network.rs ========================
+// Per-interface throughput from sysinfo, link state and addresses from ip/ifconfig
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use sysinfo::Networks;
+
+use crate::command::{CommandRunner, DEFAULT_TIMEOUT};
+use crate::helpers::{format_rate, join_list, split_list};
+
+/// Whether an interface can carry traffic
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkState {
+    /// Loopback and some tunnels don't report a state
+    #[default]
+    Unknown,
+    Up,
+    Down,
+}
+
+impl LinkState {
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkState::Unknown => "unknown",
+            LinkState::Up => "up",
+            LinkState::Down => "down",
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            LinkState::Unknown => 0,
+            LinkState::Up => 1,
+            LinkState::Down => 2,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(LinkState::Unknown),
+            1 => Some(LinkState::Up),
+            2 => Some(LinkState::Down),
+            _ => None,
+        }
+    }
+}
+
+/// One network interface
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterfaceStats {
+    /// e.g. "en0", "enp5s0" or "wlp6s0"
+    pub name: String,
+    pub rx_bytes_per_sec: u64,
+    pub tx_bytes_per_sec: u64,
+    pub state: LinkState,
+    /// Preferably a routable address; link-local only if there is nothing else
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+}
+
+impl fmt::Display for InterfaceStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.state.name())?;
+        if let Some(ipv4) = self.ipv4 {
+            write!(f, " {}", ipv4)?;
+        }
+        if let Some(ipv6) = self.ipv6 {
+            write!(f, " {}", ipv6)?;
+        }
+        write!(
+            f,
+            ": ↓ {} ↑ {}",
+            format_rate(self.rx_bytes_per_sec),
+            format_rate(self.tx_bytes_per_sec)
+        )
+    }
+}
+
+/// Throughput over the selected interfaces
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkStats {
+    /// Sums over `interfaces`
+    pub rx_bytes_per_sec: u64,
+    pub tx_bytes_per_sec: u64,
+    /// Sorted by name
+    pub interfaces: Vec<InterfaceStats>,
+}
+
+impl NetworkStats {
+    pub fn new(interfaces: Vec<InterfaceStats>) -> Self {
+        NetworkStats {
+            rx_bytes_per_sec: interfaces.iter().map(|i| i.rx_bytes_per_sec).sum(),
+            tx_bytes_per_sec: interfaces.iter().map(|i| i.tx_bytes_per_sec).sum(),
+            interfaces,
+        }
+    }
+}
+
+/// Loopback, container bridges, VM networks and macOS's private tunnels
+pub const DEFAULT_EXCLUDES: [&str; 12] = [
+    "lo*", "docker*", "veth*", "br-*", "virbr*", "vmnet*", "bridge*", "utun*", "awdl*", "llw*",
+    "gif*", "stf*",
+];
+
+/// `*` matches any run of characters, everything else matches itself
+fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=name.len())
+                .filter(|&i| name.is_char_boundary(i))
+                .any(|i| glob_match(rest, &name[i..]))
+        }
+    }
+}
+
+/// Which interfaces to report, by name patterns with `*` wildcards.
+///
+/// An interface is reported if it matches an include (or there are none) and
+/// no exclude. The default leaves out `DEFAULT_EXCLUDES`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Default for InterfaceFilter {
+    fn default() -> Self {
+        InterfaceFilter {
+            include: Vec::new(),
+            exclude: DEFAULT_EXCLUDES.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+}
+
+impl InterfaceFilter {
+    /// Every interface, loopback and bridges included
+    pub fn all() -> Self {
+        InterfaceFilter {
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+
+    pub fn allows(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| glob_match(p, name)))
+            && !self.exclude.iter().any(|p| glob_match(p, name))
+    }
+
+    /// The allowed interfaces, with totals over just those
+    pub fn apply(&self, network: &NetworkStats) -> NetworkStats {
+        NetworkStats::new(
+            network
+                .interfaces
+                .iter()
+                .filter(|interface| self.allows(&interface.name))
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+impl FromStr for InterfaceFilter {
+    type Err = String;
+
+    /// Parse a list like "en*+wlp*" or "!docker*,!veth*"; a leading `!` excludes.
+    /// "all" selects everything and "default" the default.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let patterns = split_list(s);
+        if patterns.is_empty() {
+            return Err("at least one interface pattern must be given".to_owned());
+        }
+        if patterns.iter().any(|p| p.eq_ignore_ascii_case("default")) {
+            return Ok(InterfaceFilter::default());
+        }
+        if patterns.iter().any(|p| p.eq_ignore_ascii_case("all")) {
+            return Ok(InterfaceFilter::all());
+        }
+
+        let mut filter = InterfaceFilter::all();
+        for pattern in patterns {
+            match pattern.strip_prefix('!') {
+                Some("") => return Err("'!' must be followed by a pattern".to_owned()),
+                Some(excluded) => filter.exclude.push(excluded.to_owned()),
+                None => filter.include.push(pattern.to_owned()),
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for InterfaceFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == InterfaceFilter::default() {
+            return write!(f, "default");
+        }
+        if *self == InterfaceFilter::all() {
+            return write!(f, "all");
+        }
+        let patterns: Vec<String> = self
+            .include
+            .iter()
+            .cloned()
+            .chain(self.exclude.iter().map(|p| format!("!{}", p)))
+            .collect();
+        write!(f, "{}", join_list(&patterns))
+    }
+}
+
+/// Link state and addresses of one interface, from ip or ifconfig
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterfaceDetails {
+    pub state: LinkState,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+}
+
+fn is_routable(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => !v4.is_loopback() && !v4.is_link_local(),
+        // fe80::/10 is link-local
+        IpAddr::V6(v6) => !v6.is_loopback() && v6.segments()[0] & 0xffc0 != 0xfe80,
+    }
+}
+
+impl InterfaceDetails {
+    /// Keep the first address of each family, unless a routable one comes later
+    fn add(&mut self, addr: IpAddr) {
+        match addr {
+            IpAddr::V4(v4) => {
+                if !self.ipv4.is_some_and(|old| is_routable(old.into())) {
+                    self.ipv4 = Some(v4);
+                }
+            }
+            IpAddr::V6(v6) => {
+                if !self.ipv6.is_some_and(|old| is_routable(old.into())) {
+                    self.ipv6 = Some(v6);
+                }
+            }
+        }
+    }
+}
+
+/// Parse `ip -o addr show`: one "N: name family address/prefix ..." line per address
+pub fn parse_ip_addr(output: &str) -> HashMap<String, InterfaceDetails> {
+    let mut details: HashMap<String, InterfaceDetails> = HashMap::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [_, name, family, address, ..] = fields[..] else {
+            continue;
+        };
+        if family != "inet" && family != "inet6" {
+            continue;
+        }
+        // veth pairs are listed as "veth1a2b@if5"
+        let name = name.split('@').next().unwrap_or(name);
+        let address = address.split('/').next().unwrap_or(address);
+        if let Ok(addr) = address.parse() {
+            details.entry(name.to_owned()).or_default().add(addr);
+        }
+    }
+    details
+}
+
+/// Link state from `<sysfs_root>/class/net/<name>/operstate`
+pub fn read_operstate(sysfs_root: &Path, name: &str) -> LinkState {
+    let path = sysfs_root.join("class/net").join(name).join("operstate");
+    match fs::read_to_string(path).as_deref().map(str::trim) {
+        Ok("up") => LinkState::Up,
+        Ok("down" | "lowerlayerdown" | "notpresent" | "dormant") => LinkState::Down,
+        _ => LinkState::Unknown,
+    }
+}
+
+/// Link state of every interface in sysfs, plus addresses from `ip -o addr show`
+pub async fn linux_details(
+    runner: &dyn CommandRunner,
+    sysfs_root: &Path,
+) -> HashMap<String, InterfaceDetails> {
+    let mut details = runner
+        .run("ip", &["-o", "addr", "show"], DEFAULT_TIMEOUT)
+        .await
+        .map(|output| parse_ip_addr(&output))
+        .unwrap_or_default();
+    if let Ok(entries) = fs::read_dir(sysfs_root.join("class/net")) {
+        for entry in entries.flatten() {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            details.entry(name.clone()).or_default().state = read_operstate(sysfs_root, &name);
+        }
+    }
+    details
+}
+
+/// Parse `ifconfig`: a header line per interface, then indented details.
+///
+/// State comes from the "status:" line where there is one (Ethernet, Wi-Fi),
+/// otherwise from the UP and RUNNING flags.
+pub fn parse_ifconfig(output: &str) -> HashMap<String, InterfaceDetails> {
+    let mut interfaces: Vec<(String, InterfaceDetails)> = Vec::new();
+
+    for line in output.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            let Some((name, rest)) = line.split_once(": ") else {
+                continue;
+            };
+            let flags = rest
+                .split_once('<')
+                .and_then(|(_, flags)| flags.split_once('>'))
+                .map_or("", |(flags, _)| flags);
+            let running =
+                flags.split(',').any(|f| f == "UP") && flags.split(',').any(|f| f == "RUNNING");
+            interfaces.push((
+                name.to_owned(),
+                InterfaceDetails {
+                    state: if running {
+                        LinkState::Up
+                    } else {
+                        LinkState::Down
+                    },
+                    ..InterfaceDetails::default()
+                },
+            ));
+            continue;
+        }
+        let Some((_, interface)) = interfaces.last_mut() else {
+            continue;
+        };
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            ["status:", "active", ..] => interface.state = LinkState::Up,
+            ["status:", "inactive", ..] => interface.state = LinkState::Down,
+            ["inet" | "inet6", address, ..] => {
+                // Link-local IPv6 addresses carry a "%en0" zone
+                let address = address.split('%').next().unwrap_or(address);
+                if let Ok(addr) = address.parse() {
+                    interface.add(addr);
+                }
+            }
+            _ => {}
+        }
+    }
+    interfaces.into_iter().collect()
+}
+
+/// Link state and addresses of every interface from `ifconfig`
+pub async fn macos_details(runner: &dyn CommandRunner) -> HashMap<String, InterfaceDetails> {
+    runner
+        .run("ifconfig", &[], DEFAULT_TIMEOUT)
+        .await
+        .map(|output| parse_ifconfig(&output))
+        .unwrap_or_default()
+}
+
+/// Combine byte counts over `elapsed` with link details, sorted by name
+pub fn build_interfaces(
+    transferred: impl IntoIterator<Item = (String, u64, u64)>,
+    elapsed: Duration,
+    details: &HashMap<String, InterfaceDetails>,
+) -> Vec<InterfaceStats> {
+    let secs = elapsed.as_secs_f64();
+    let rate = |bytes: u64| {
+        if secs > 0.0 {
+            (bytes as f64 / secs).round() as u64
+        } else {
+            0
+        }
+    };
+    let mut interfaces: Vec<InterfaceStats> = transferred
+        .into_iter()
+        .map(|(name, received, transmitted)| {
+            let detail = details.get(&name).copied().unwrap_or_default();
+            InterfaceStats {
+                rx_bytes_per_sec: rate(received),
+                tx_bytes_per_sec: rate(transmitted),
+                state: detail.state,
+                ipv4: detail.ipv4,
+                ipv6: detail.ipv6,
+                name,
+            }
+        })
+        .collect();
+    interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+    interfaces
+}
+
+/// sysinfo's interface list and the time of the previous refresh
+pub struct NetworkProbe {
+    networks: Networks,
+    previous_at: Instant,
+}
+
+impl Default for NetworkProbe {
+    fn default() -> Self {
+        NetworkProbe::new()
+    }
+}
+
+impl NetworkProbe {
+    pub fn new() -> Self {
+        NetworkProbe {
+            networks: Networks::new_with_refreshed_list(),
+            previous_at: Instant::now(),
+        }
+    }
+
+    /// Rates since the previous call (or since the probe was created)
+    pub fn sample(&mut self, details: &HashMap<String, InterfaceDetails>) -> NetworkStats {
+        // Also picks up interfaces that came or went
+        self.networks.refresh_list();
+        let now = Instant::now();
+        let elapsed = now - self.previous_at;
+        self.previous_at = now;
+
+        let transferred = self
+            .networks
+            .iter()
+            .map(|(name, data)| (name.clone(), data.received(), data.transmitted()));
+        NetworkStats::new(build_interfaces(transferred, elapsed, details))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{macos_fixture, FixtureRunner};
+
+    const IP_ADDR: &str = include_str!("../tests/fixtures/linux/ip_addr.txt");
+
+    fn sysfs() -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/linux/sys")
+    }
+
+    #[tokio::test]
+    async fn test_linux_details() {
+        let runner = FixtureRunner::new().with_output(&["ip", "-o", "addr", "show"], IP_ADDR);
+        let details = linux_details(&runner, &sysfs()).await;
+
+        let wired = details["enp5s0"];
+        assert_eq!(wired.state, LinkState::Up);
+        assert_eq!(wired.ipv4, Some(Ipv4Addr::new(192, 168, 1, 20)));
+        // The global address wins over the link-local one listed after it
+        assert_eq!(
+            wired.ipv6,
+            Some("2a02:810d:1234:5600:4a21:bff:fe12:3456".parse().unwrap())
+        );
+        assert_eq!(details["lo"].state, LinkState::Unknown);
+        assert_eq!(details["wlp6s0"].state, LinkState::Down);
+        assert_eq!(details["wlp6s0"].ipv4, None);
+        assert!(details["veth3f1c2a7"].ipv6.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_macos_details() {
+        let details = macos_details(&macos_fixture("m2")).await;
+
+        let en0 = details["en0"];
+        assert_eq!(en0.state, LinkState::Up);
+        assert_eq!(en0.ipv4, Some(Ipv4Addr::new(192, 168, 1, 42)));
+        assert_eq!(
+            en0.ipv6,
+            Some("2a02:810d:1234:5600:10c4:4b2e:9f3a:71d0".parse().unwrap())
+        );
+        // "status: inactive" overrides the UP,RUNNING flags
+        assert_eq!(details["en1"].state, LinkState::Down);
+        assert_eq!(details["lo0"].state, LinkState::Up);
+        assert_eq!(details["utun0"].ipv6.unwrap().segments()[0], 0xfe80);
+        assert!(macos_details(&FixtureRunner::new()).await.is_empty());
+    }
+
+    #[test]
+    fn test_build_and_filter() {
+        let details = parse_ip_addr(IP_ADDR);
+        let interfaces = build_interfaces(
+            [
+                ("wlp6s0".to_owned(), 0, 0),
+                ("enp5s0".to_owned(), 4 << 20, 1 << 20),
+                ("docker0".to_owned(), 2048, 2048),
+                ("lo".to_owned(), 1 << 20, 1 << 20),
+            ],
+            Duration::from_secs(2),
+            &details,
+        );
+        let names: Vec<&str> = interfaces.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, ["docker0", "enp5s0", "lo", "wlp6s0"]);
+        assert_eq!(interfaces[1].rx_bytes_per_sec, 2 << 20);
+        assert_eq!(
+            interfaces[1].to_string(),
+            "enp5s0 (unknown) 192.168.1.20 2a02:810d:1234:5600:4a21:bff:fe12:3456: ↓ 2.0 MB/s ↑ 512 KB/s"
+        );
+
+        let all = NetworkStats::new(interfaces);
+        let network = InterfaceFilter::default().apply(&all);
+        assert_eq!(network.interfaces.len(), 2);
+        assert_eq!(network.rx_bytes_per_sec, 2 << 20);
+        assert_eq!(InterfaceFilter::all().apply(&all), all);
+
+        let filter: InterfaceFilter = "e*+w*,!wlp6*".parse().unwrap();
+        assert_eq!(filter.apply(&all).interfaces[0].name, "enp5s0");
+        assert_eq!(filter.apply(&all).interfaces.len(), 1);
+        assert_eq!(filter.to_string(), "e*+w*+!wlp6*");
+        assert_eq!("default".parse(), Ok(InterfaceFilter::default()));
+        assert!("!".parse::<InterfaceFilter>().is_err());
+        assert!(glob_match("br-*", "br-1a2b3c"));
+        assert!(glob_match("*0", "docker0"));
+        assert!(!glob_match("en*", "wlp6s0"));
+    }
+}
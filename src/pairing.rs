@@ -0,0 +1,241 @@
+// ======================== pairing.rs ========================
+// Remembering the chosen Flipper and picking one when several are in range
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+const APP_DIR: &str = "flipper-monitor";
+const STATE_FILE: &str = "state.json";
+
+/// Identity of a Flipper as seen by this host
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PairedDevice {
+    /// Platform peripheral id (BlueZ path on Linux, CoreBluetooth UUID on macOS)
+    pub id: String,
+    pub address: String,
+    pub name: Option<String>,
+}
+
+impl PairedDevice {
+    /// Whether a user-supplied id, address or name fragment refers to this device
+    pub fn matches_query(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        self.id.to_lowercase() == query
+            || self.address.to_lowercase() == query
+            || self
+                .name
+                .as_ref()
+                .is_some_and(|name| name.to_lowercase().contains(&query))
+    }
+
+    pub fn is_same_device(&self, other: &PairedDevice) -> bool {
+        self.id == other.id || (!self.address.is_empty() && self.address == other.address)
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("Unknown")
+    }
+}
+
+/// Host state persisted between runs
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct State {
+    pub paired: Option<PairedDevice>,
+}
+
+/// `$XDG_STATE_HOME/flipper-monitor/state.json`, or the platform's local data dir
+pub fn default_state_path() -> Option<PathBuf> {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join(APP_DIR).join(STATE_FILE))
+}
+
+impl State {
+    /// Load the state file; a missing file is an empty state
+    pub fn load(path: &Path) -> io::Result<State> {
+        match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write the state file, replacing it atomically
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let data = serde_json::to_vec_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(tmp, path)
+    }
+}
+
+/// How to choose between several matching devices
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// Remembered device if present, otherwise the only match, otherwise ask
+    #[default]
+    Auto,
+    /// Device whose id, address or name matches the query
+    Query(String),
+    /// Always show the picker
+    Interactive,
+}
+
+/// Result of applying a selector to a scan
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Pick {
+    /// Use the candidate at this index
+    Chosen(usize),
+    /// Several candidates and nothing to decide between them
+    Ambiguous,
+    /// Nothing matched
+    NotFound,
+}
+
+/// Choose among `candidates` (ordered strongest signal first)
+pub fn pick(
+    candidates: &[PairedDevice],
+    paired: Option<&PairedDevice>,
+    selector: &DeviceSelector,
+) -> Pick {
+    match selector {
+        DeviceSelector::Query(query) => candidates
+            .iter()
+            .position(|c| c.matches_query(query))
+            .map_or(Pick::NotFound, Pick::Chosen),
+        DeviceSelector::Interactive if candidates.is_empty() => Pick::NotFound,
+        DeviceSelector::Interactive => Pick::Ambiguous,
+        DeviceSelector::Auto => {
+            if let Some(index) =
+                paired.and_then(|p| candidates.iter().position(|c| c.is_same_device(p)))
+            {
+                return Pick::Chosen(index);
+            }
+            match candidates.len() {
+                0 => Pick::NotFound,
+                1 => Pick::Chosen(0),
+                _ => Pick::Ambiguous,
+            }
+        }
+    }
+}
+
+/// Ask on the terminal which candidate to use.
+///
+/// Returns `None` when stdin is not interactive or the answer is not a valid choice.
+pub fn prompt_choice(candidates: &[(PairedDevice, Option<i16>)]) -> Option<usize> {
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+
+    println!("\n📱 Several Flipper Zero devices found:");
+    for (i, (device, rssi)) in candidates.iter().enumerate() {
+        println!(
+            "   [{}] {} ({}, {} dBm)",
+            i + 1,
+            device.display_name(),
+            device.address,
+            rssi.map_or("?".to_owned(), |r| r.to_string())
+        );
+    }
+    print!("Select a device [1-{}]: ", candidates.len());
+    io::stdout().flush().ok()?;
+
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line).ok()?;
+    parse_choice(&line, candidates.len())
+}
+
+fn parse_choice(line: &str, count: usize) -> Option<usize> {
+    match line.trim().parse::<usize>() {
+        Ok(n) if (1..=count).contains(&n) => Some(n - 1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(id: &str, address: &str, name: &str) -> PairedDevice {
+        PairedDevice {
+            id: id.to_owned(),
+            address: address.to_owned(),
+            name: Some(name.to_owned()),
+        }
+    }
+
+    fn office() -> Vec<PairedDevice> {
+        vec![
+            device("hci0/dev_AA", "AA:AA:AA:AA:AA:AA", "Flipper Alpha"),
+            device("hci0/dev_BB", "BB:BB:BB:BB:BB:BB", "Flipper Bravo"),
+        ]
+    }
+
+    #[test]
+    fn test_auto_prefers_remembered_device() {
+        let candidates = office();
+        assert_eq!(
+            pick(&candidates, Some(&candidates[1]), &DeviceSelector::Auto),
+            Pick::Chosen(1)
+        );
+        assert_eq!(
+            pick(&candidates, None, &DeviceSelector::Auto),
+            Pick::Ambiguous
+        );
+        assert_eq!(
+            pick(&candidates[..1], None, &DeviceSelector::Auto),
+            Pick::Chosen(0)
+        );
+        assert_eq!(pick(&[], None, &DeviceSelector::Auto), Pick::NotFound);
+    }
+
+    #[test]
+    fn test_query_matches_id_address_or_name() {
+        let candidates = office();
+        let by = |q: &str| pick(&candidates, None, &DeviceSelector::Query(q.to_owned()));
+        assert_eq!(by("bb:bb:bb:bb:bb:bb"), Pick::Chosen(1));
+        assert_eq!(by("hci0/dev_AA"), Pick::Chosen(0));
+        assert_eq!(by("bravo"), Pick::Chosen(1));
+        assert_eq!(by("charlie"), Pick::NotFound);
+        assert_eq!(
+            pick(
+                &candidates,
+                Some(&candidates[0]),
+                &DeviceSelector::Interactive
+            ),
+            Pick::Ambiguous
+        );
+    }
+
+    #[test]
+    fn test_parse_choice() {
+        assert_eq!(parse_choice("2\n", 3), Some(1));
+        assert_eq!(parse_choice("0", 3), None);
+        assert_eq!(parse_choice("4", 3), None);
+        assert_eq!(parse_choice("x", 3), None);
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let dir = std::env::temp_dir().join(format!("flipper-state-{}", std::process::id()));
+        let path = dir.join("nested").join(STATE_FILE);
+
+        assert_eq!(State::load(&path).unwrap(), State::default());
+
+        let state = State {
+            paired: Some(office().remove(0)),
+        };
+        state.save(&path).unwrap();
+        assert_eq!(State::load(&path).unwrap(), state);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,539 @@
+// ======================== power.rs ========================
+// Battery and power source from /sys/class/power_supply, pmset and ioreg
+
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::command::{CommandRunner, DEFAULT_TIMEOUT};
+use crate::ioreg;
+
+/// Where the system draws power from
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerSource {
+    Ac,
+    Battery,
+}
+
+impl PowerSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            PowerSource::Ac => "AC",
+            PowerSource::Battery => "battery",
+        }
+    }
+
+    /// 0 is reserved for "unknown" on the wire
+    pub fn to_u8(self) -> u8 {
+        match self {
+            PowerSource::Ac => 1,
+            PowerSource::Battery => 2,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(PowerSource::Ac),
+            2 => Some(PowerSource::Battery),
+            _ => None,
+        }
+    }
+}
+
+/// What the battery is doing
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargeState {
+    Discharging,
+    Charging,
+    Full,
+    /// On AC but held below full, e.g. by a charge limit
+    NotCharging,
+}
+
+impl ChargeState {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChargeState::Discharging => "discharging",
+            ChargeState::Charging => "charging",
+            ChargeState::Full => "full",
+            ChargeState::NotCharging => "not charging",
+        }
+    }
+
+    /// 0 is reserved for "unknown" on the wire
+    pub fn to_u8(self) -> u8 {
+        match self {
+            ChargeState::Discharging => 1,
+            ChargeState::Charging => 2,
+            ChargeState::Full => 3,
+            ChargeState::NotCharging => 4,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(ChargeState::Discharging),
+            2 => Some(ChargeState::Charging),
+            3 => Some(ChargeState::Full),
+            4 => Some(ChargeState::NotCharging),
+            _ => None,
+        }
+    }
+
+    /// The `status` attribute of a power_supply battery
+    fn from_sysfs(status: &str) -> Option<Self> {
+        match status {
+            "Discharging" => Some(ChargeState::Discharging),
+            "Charging" => Some(ChargeState::Charging),
+            "Full" => Some(ChargeState::Full),
+            "Not charging" => Some(ChargeState::NotCharging),
+            _ => None,
+        }
+    }
+
+    /// The state field of `pmset -g batt`
+    fn from_pmset(state: &str) -> Option<Self> {
+        match state {
+            "discharging" => Some(ChargeState::Discharging),
+            "charging" | "finishing charge" => Some(ChargeState::Charging),
+            "charged" => Some(ChargeState::Full),
+            "AC attached" => Some(ChargeState::NotCharging),
+            _ => None,
+        }
+    }
+}
+
+/// How hard macOS is working to keep the machine cool, as powermetrics reports it
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ThermalPressure {
+    Nominal,
+    Moderate,
+    Heavy,
+    Trapping,
+    Sleeping,
+}
+
+impl ThermalPressure {
+    pub fn name(self) -> &'static str {
+        match self {
+            ThermalPressure::Nominal => "nominal",
+            ThermalPressure::Moderate => "moderate",
+            ThermalPressure::Heavy => "heavy",
+            ThermalPressure::Trapping => "trapping",
+            ThermalPressure::Sleeping => "sleeping",
+        }
+    }
+
+    /// 0 is reserved for "unknown" on the wire
+    pub fn to_u8(self) -> u8 {
+        match self {
+            ThermalPressure::Nominal => 1,
+            ThermalPressure::Moderate => 2,
+            ThermalPressure::Heavy => 3,
+            ThermalPressure::Trapping => 4,
+            ThermalPressure::Sleeping => 5,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(ThermalPressure::Nominal),
+            2 => Some(ThermalPressure::Moderate),
+            3 => Some(ThermalPressure::Heavy),
+            4 => Some(ThermalPressure::Trapping),
+            5 => Some(ThermalPressure::Sleeping),
+            _ => None,
+        }
+    }
+
+    /// The `thermal_pressure` string of a powermetrics sample
+    pub fn from_powermetrics(level: &str) -> Option<Self> {
+        match level {
+            "Nominal" => Some(ThermalPressure::Nominal),
+            "Moderate" => Some(ThermalPressure::Moderate),
+            "Heavy" => Some(ThermalPressure::Heavy),
+            "Trapping" => Some(ThermalPressure::Trapping),
+            "Sleeping" => Some(ThermalPressure::Sleeping),
+            _ => None,
+        }
+    }
+}
+
+/// The internal battery, or all of them combined
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatteryStats {
+    /// Charge, 0-100
+    pub percent: u8,
+    pub state: Option<ChargeState>,
+    /// Until empty when discharging, until full when charging; `None` while estimating
+    pub minutes_remaining: Option<u32>,
+    pub cycle_count: Option<u32>,
+}
+
+/// Power source, battery and how much the system is drawing
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PowerStats {
+    pub source: Option<PowerSource>,
+    /// `None` on desktops
+    pub battery: Option<BatteryStats>,
+    /// System power draw in milliwatts, where the platform measures it
+    pub power_draw_mw: Option<u32>,
+    /// Only known while powermetrics is running
+    pub thermal_pressure: Option<ThermalPressure>,
+}
+
+impl PowerStats {
+    /// Nothing to report, e.g. a desktop without power_supply entries
+    pub fn is_empty(&self) -> bool {
+        self.source.is_none()
+            && self.battery.is_none()
+            && self.power_draw_mw.is_none()
+            && self.thermal_pressure.is_none()
+    }
+}
+
+impl fmt::Display for PowerStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(battery) = &self.battery {
+            parts.push(format!("{}%", battery.percent));
+            if let Some(state) = battery.state {
+                parts.push(state.name().to_owned());
+            }
+            if let Some(minutes) = battery.minutes_remaining {
+                parts.push(format!("{}:{:02} remaining", minutes / 60, minutes % 60));
+            }
+            if let Some(cycles) = battery.cycle_count {
+                parts.push(format!("{} cycles", cycles));
+            }
+        }
+        if let Some(source) = self.source {
+            parts.push(format!("on {}", source.name()));
+        }
+        if let Some(mw) = self.power_draw_mw {
+            parts.push(format!("{:.1} W", mw as f64 / 1000.0));
+        }
+        if let Some(pressure) = self.thermal_pressure {
+            parts.push(format!("{} thermal pressure", pressure.name()));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_owned())
+}
+
+fn read_number(path: &Path) -> Option<u64> {
+    read_trimmed(path)?.parse().ok()
+}
+
+/// One system battery under power_supply, with energies in µWh and power in µW
+#[derive(Debug, Default)]
+struct SupplyBattery {
+    state: Option<ChargeState>,
+    capacity: Option<u64>,
+    energy_now: Option<u64>,
+    energy_full: Option<u64>,
+    power: Option<u64>,
+    cycle_count: Option<u32>,
+}
+
+impl SupplyBattery {
+    /// Drivers report either energy_* (µWh) and power_now, or charge_* (µAh) and
+    /// current_now; the latter are converted with voltage_now
+    fn read(dir: &Path) -> Self {
+        let number = |name: &str| read_number(&dir.join(name));
+        let voltage = number("voltage_now");
+        let from_charge = |name: &str| Some(number(name)? * voltage? / 1_000_000);
+
+        SupplyBattery {
+            state: read_trimmed(&dir.join("status"))
+                .and_then(|status| ChargeState::from_sysfs(&status)),
+            capacity: number("capacity"),
+            energy_now: number("energy_now").or_else(|| from_charge("charge_now")),
+            energy_full: number("energy_full").or_else(|| from_charge("charge_full")),
+            power: number("power_now").or_else(|| from_charge("current_now")),
+            // Many drivers report 0 when they don't count cycles
+            cycle_count: number("cycle_count")
+                .filter(|&n| n > 0)
+                .map(|n| n.min(u32::MAX as u64) as u32),
+        }
+    }
+}
+
+/// Combine the batteries as one: charge and time from the summed energies
+fn combine(batteries: &[SupplyBattery]) -> Option<BatteryStats> {
+    let first = batteries.first()?;
+    let sum = |field: fn(&SupplyBattery) -> Option<u64>| -> Option<u64> {
+        batteries.iter().map(field).sum()
+    };
+    let energy_now = sum(|b| b.energy_now);
+    let energy_full = sum(|b| b.energy_full).filter(|&full| full > 0);
+    let power = sum(|b| b.power).filter(|&power| power > 0);
+
+    let percent = match (energy_now, energy_full) {
+        (Some(now), Some(full)) => (now * 100 / full).min(100) as u8,
+        _ => {
+            let capacities: Vec<u64> = batteries.iter().filter_map(|b| b.capacity).collect();
+            match capacities.len() {
+                0 => 0,
+                n => (capacities.iter().sum::<u64>() / n as u64).min(100) as u8,
+            }
+        }
+    };
+    // One battery charging or discharging says more than another sitting full
+    let state = batteries
+        .iter()
+        .filter_map(|b| b.state)
+        .find(|s| matches!(s, ChargeState::Discharging | ChargeState::Charging))
+        .or(first.state);
+    let minutes_remaining = match (state, energy_now, energy_full, power) {
+        (Some(ChargeState::Discharging), Some(now), _, Some(power)) => Some(now * 60 / power),
+        (Some(ChargeState::Charging), Some(now), Some(full), Some(power)) => {
+            Some(full.saturating_sub(now) * 60 / power)
+        }
+        _ => None,
+    };
+
+    Some(BatteryStats {
+        percent,
+        state,
+        minutes_remaining: minutes_remaining.map(|m| m.min(u32::MAX as u64) as u32),
+        cycle_count: batteries.iter().filter_map(|b| b.cycle_count).max(),
+    })
+}
+
+/// Power source and batteries under `<sysfs_root>/class/power_supply`.
+///
+/// Peripheral batteries (scope "Device", e.g. a wireless mouse) are left out.
+/// The draw is only known on battery: while charging, the gauge measures the
+/// charging current instead.
+pub fn read_power_supply(sysfs_root: &Path) -> PowerStats {
+    let Ok(entries) = fs::read_dir(sysfs_root.join("class/power_supply")) else {
+        return PowerStats::default();
+    };
+    let mut dirs: Vec<_> = entries.flatten().map(|entry| entry.path()).collect();
+    dirs.sort();
+
+    let mut mains: Option<bool> = None;
+    let mut batteries = Vec::new();
+    for dir in dirs {
+        match read_trimmed(&dir.join("type")).as_deref() {
+            Some("Mains") | Some("USB") => {
+                let online = read_number(&dir.join("online")) == Some(1);
+                mains = Some(mains.unwrap_or(false) || online);
+            }
+            Some("Battery") if read_trimmed(&dir.join("scope")).as_deref() != Some("Device") => {
+                batteries.push(SupplyBattery::read(&dir));
+            }
+            _ => {}
+        }
+    }
+
+    let battery = combine(&batteries);
+    let discharging = battery.and_then(|b| b.state) == Some(ChargeState::Discharging);
+    let source = match mains {
+        Some(true) => Some(PowerSource::Ac),
+        Some(false) => Some(PowerSource::Battery),
+        // No AC adapter entry: go by what the battery is doing
+        None if discharging => Some(PowerSource::Battery),
+        None => battery.map(|_| PowerSource::Ac),
+    };
+    let power_draw_mw = batteries
+        .iter()
+        .map(|b| b.power)
+        .sum::<Option<u64>>()
+        .filter(|_| discharging)
+        .map(|uw| (uw / 1000).min(u32::MAX as u64) as u32);
+
+    PowerStats {
+        source,
+        battery,
+        power_draw_mw,
+        thermal_pressure: None,
+    }
+}
+
+/// Parse `pmset -g batt` (the status follows a tab):
+///
+/// ```text
+/// Now drawing from 'Battery Power'
+///  -InternalBattery-0 (id=4653155)    82%; discharging; 5:12 remaining present: true
+/// ```
+pub fn parse_pmset_batt(output: &str) -> PowerStats {
+    let mut lines = output.lines();
+    let source = lines.next().and_then(|line| {
+        if line.contains("'AC Power'") {
+            Some(PowerSource::Ac)
+        } else if line.contains("'Battery Power'") {
+            Some(PowerSource::Battery)
+        } else {
+            None
+        }
+    });
+
+    let battery = lines
+        .find(|line| line.contains("InternalBattery"))
+        .and_then(|line| {
+            let (_, status) = line.split_once('\t')?;
+            let mut fields = status.split(';').map(str::trim);
+            let percent = fields.next()?.strip_suffix('%')?.parse::<u8>().ok()?;
+            let state = fields.next().and_then(ChargeState::from_pmset);
+            // "5:12 remaining present: true", "(no estimate) ..." or "not charging ..."
+            let minutes_remaining = fields.next().and_then(|rest| {
+                let (hours, minutes) = rest.split_whitespace().next()?.split_once(':')?;
+                Some(hours.parse::<u32>().ok()? * 60 + minutes.parse::<u32>().ok()?)
+            });
+            Some(BatteryStats {
+                percent: percent.min(100),
+                state,
+                minutes_remaining: minutes_remaining.filter(|_| state != Some(ChargeState::Full)),
+                cycle_count: None,
+            })
+        });
+
+    PowerStats {
+        source,
+        battery,
+        power_draw_mw: None,
+        thermal_pressure: None,
+    }
+}
+
+/// `pmset` for charge and state, the AppleSmartBattery gauge for cycles and draw.
+///
+/// Apple Silicon publishes the system load directly; elsewhere the draw is
+/// battery voltage times current, which only covers the system on battery.
+pub async fn macos_power(runner: &dyn CommandRunner) -> PowerStats {
+    let mut power = match runner.run("pmset", &["-g", "batt"], DEFAULT_TIMEOUT).await {
+        Ok(output) => parse_pmset_batt(&output),
+        Err(_) => return PowerStats::default(),
+    };
+    let Some(battery) = power.battery.as_mut() else {
+        return power;
+    };
+
+    let gauge = runner
+        .run("ioreg", &ioreg::SMART_BATTERY_ARGS, DEFAULT_TIMEOUT)
+        .await
+        .ok()
+        .and_then(|output| ioreg::parse(&output).ok()?.into_iter().next());
+    let Some(gauge) = gauge else {
+        return power;
+    };
+    battery.cycle_count = gauge
+        .integer("CycleCount")
+        .map(|n| n.min(u32::MAX as u64) as u32);
+
+    let system_load = gauge
+        .properties
+        .get("PowerTelemetryData")
+        .and_then(|data| {
+            data.as_dictionary()?
+                .get("SystemLoad")?
+                .as_unsigned_integer()
+        })
+        .filter(|&mw| mw > 0);
+    let discharging = battery.state == Some(ChargeState::Discharging);
+    let battery_draw = match (gauge.integer("Voltage"), gauge.signed("InstantAmperage")) {
+        (Some(mv), Some(ma)) if discharging => Some(mv * ma.unsigned_abs() / 1000),
+        _ => None,
+    };
+    power.power_draw_mw = system_load
+        .or(battery_draw)
+        .map(|mw| mw.min(u32::MAX as u64) as u32);
+    power
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{macos_fixture, FixtureRunner};
+
+    #[test]
+    fn test_read_power_supply() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/linux/sys");
+        let power = read_power_supply(&root);
+        // BAT0: 40 of 50 Wh at 8 W; BAT1: 12 of 24 Wh at 6 W; the mouse is ignored
+        assert_eq!(
+            power,
+            PowerStats {
+                source: Some(PowerSource::Battery),
+                battery: Some(BatteryStats {
+                    percent: 70,
+                    state: Some(ChargeState::Discharging),
+                    minutes_remaining: Some(222),
+                    cycle_count: Some(312),
+                }),
+                power_draw_mw: Some(14_000),
+                thermal_pressure: None,
+            }
+        );
+        assert_eq!(
+            power.to_string(),
+            "70%, discharging, 3:42 remaining, 312 cycles, on battery, 14.0 W"
+        );
+
+        let desktop = read_power_supply(Path::new("/nonexistent"));
+        assert!(desktop.is_empty());
+    }
+
+    #[test]
+    fn test_parse_pmset_batt() {
+        let power = parse_pmset_batt(
+            "Now drawing from 'AC Power'\n -InternalBattery-0 (id=4653155)\t64%; charging; 1:23 remaining present: true\n",
+        );
+        assert_eq!(power.source, Some(PowerSource::Ac));
+        let battery = power.battery.unwrap();
+        assert_eq!(battery.state, Some(ChargeState::Charging));
+        assert_eq!(battery.minutes_remaining, Some(83));
+
+        let power = parse_pmset_batt(
+            "Now drawing from 'Battery Power'\n -InternalBattery-0 (id=4653155)\t97%; discharging; (no estimate) present: true\n",
+        );
+        assert_eq!(power.battery.unwrap().minutes_remaining, None);
+
+        let power = parse_pmset_batt(
+            "Now drawing from 'AC Power'\n -InternalBattery-0 (id=4653155)\t80%; AC attached; not charging present: true\n",
+        );
+        assert_eq!(power.battery.unwrap().state, Some(ChargeState::NotCharging));
+
+        // A Mac mini
+        let power = parse_pmset_batt("Now drawing from 'AC Power'\n");
+        assert_eq!(power.source, Some(PowerSource::Ac));
+        assert_eq!(power.battery, None);
+    }
+
+    #[tokio::test]
+    async fn test_macos_power() {
+        let power = macos_power(&macos_fixture("m2")).await;
+        assert_eq!(power.source, Some(PowerSource::Battery));
+        assert_eq!(
+            power.battery,
+            Some(BatteryStats {
+                percent: 82,
+                state: Some(ChargeState::Discharging),
+                minutes_remaining: Some(312),
+                cycle_count: Some(143),
+            })
+        );
+        assert_eq!(power.power_draw_mw, Some(9120));
+
+        // Full on AC: no time left to report, and the battery isn't powering anything
+        let power = macos_power(&macos_fixture("intel")).await;
+        assert_eq!(power.source, Some(PowerSource::Ac));
+        let battery = power.battery.unwrap();
+        assert_eq!(battery.state, Some(ChargeState::Full));
+        assert_eq!(battery.minutes_remaining, None);
+        assert_eq!(battery.cycle_count, Some(871));
+        assert_eq!(power.power_draw_mw, None);
+
+        let power = macos_power(&FixtureRunner::new()).await;
+        assert!(power.is_empty());
+    }
+}
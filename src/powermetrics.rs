@@ -0,0 +1,253 @@
+// ======================== powermetrics.rs ========================
+// A long-running `powermetrics --format plist` process (macOS, needs root)
+
+use plist::{Dictionary, Value};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::command::{exit_error, spawn_error, CommandError};
+use crate::power::ThermalPressure;
+
+const PROGRAM: &str = "powermetrics";
+
+/// Samplers requested from powermetrics
+pub const SAMPLERS: &str = "cpu_power,gpu_power,thermal";
+
+/// One powermetrics sample; fields its samplers didn't report are `None`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PowerSample {
+    /// Percent of the interval the GPU was active
+    pub gpu_residency: Option<f64>,
+    /// Milliwatts
+    pub cpu_power: Option<f64>,
+    pub gpu_power: Option<f64>,
+    pub ane_power: Option<f64>,
+    pub thermal_pressure: Option<ThermalPressure>,
+}
+
+impl PowerSample {
+    /// CPU, GPU and ANE together in milliwatts; `None` if none of them were reported
+    pub fn package_power_mw(&self) -> Option<u32> {
+        [self.cpu_power, self.gpu_power, self.ane_power]
+            .into_iter()
+            .flatten()
+            .reduce(|a, b| a + b)
+            .map(|mw| mw.round() as u32)
+    }
+}
+
+/// Latest sample; `None` until the first one arrives
+pub type PowerReceiver = watch::Receiver<Option<Arc<PowerSample>>>;
+
+/// powermetrics reports numbers as integers or reals depending on the value
+fn number(dict: &Dictionary, key: &str) -> Option<f64> {
+    let value = dict.get(key)?;
+    value
+        .as_real()
+        .or_else(|| value.as_signed_integer().map(|v| v as f64))
+        .or_else(|| value.as_unsigned_integer().map(|v| v as f64))
+}
+
+/// Parse one plist document from the stream
+pub fn parse_sample(document: &[u8]) -> Result<PowerSample, CommandError> {
+    let root = Value::from_reader_xml(document)
+        .map_err(|e| CommandError::unparseable(PROGRAM, e.to_string()))?
+        .into_dictionary()
+        .ok_or_else(|| CommandError::unparseable(PROGRAM, "expected a dictionary"))?;
+
+    let processor = root.get("processor").and_then(Value::as_dictionary);
+    let gpu = root.get("gpu").and_then(Value::as_dictionary);
+    let power = |key: &str| processor.and_then(|p| number(p, key));
+
+    Ok(PowerSample {
+        gpu_residency: gpu
+            .and_then(|gpu| number(gpu, "idle_ratio"))
+            .map(|idle| ((1.0 - idle) * 100.0).clamp(0.0, 100.0)),
+        cpu_power: power("cpu_power"),
+        gpu_power: power("gpu_power"),
+        ane_power: power("ane_power"),
+        thermal_pressure: root
+            .get("thermal_pressure")
+            .and_then(Value::as_string)
+            .and_then(ThermalPressure::from_powermetrics),
+    })
+}
+
+/// Splits the stream into documents; powermetrics ends each with a NUL byte
+#[derive(Debug, Default)]
+pub struct NulSplitter {
+    pending: Vec<u8>,
+}
+
+impl NulSplitter {
+    /// Add bytes as they arrive and take every document they complete
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        self.pending.extend_from_slice(bytes);
+        let mut documents = Vec::new();
+        while let Some(end) = self.pending.iter().position(|&b| b == 0) {
+            let document: Vec<u8> = self.pending.drain(..=end).take(end).collect();
+            if !document.iter().all(u8::is_ascii_whitespace) {
+                documents.push(document);
+            }
+        }
+        documents
+    }
+}
+
+/// Publish every sample in `stream` until it ends or nobody is listening.
+///
+/// Unreadable documents are reported and skipped. Returns how many samples were read.
+pub async fn read_stream<R>(
+    mut stream: R,
+    samples: &watch::Sender<Option<Arc<PowerSample>>>,
+) -> Result<usize, CommandError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut splitter = NulSplitter::default();
+    let mut buf = [0u8; 8192];
+    let mut count = 0;
+
+    loop {
+        let n = stream
+            .read(&mut buf)
+            .await
+            .map_err(|e| CommandError::Failed {
+                program: PROGRAM.to_owned(),
+                message: e.to_string(),
+            })?;
+        if n == 0 {
+            return Ok(count);
+        }
+        for document in splitter.push(&buf[..n]) {
+            let sample = match parse_sample(&document) {
+                Ok(sample) => sample,
+                Err(e) => {
+                    println!("⚠️  Skipping powermetrics sample: {}", e);
+                    continue;
+                }
+            };
+            count += 1;
+            if samples.send(Some(Arc::new(sample))).is_err() {
+                return Ok(count);
+            }
+        }
+    }
+}
+
+/// Keep one powermetrics process sampling every `interval` until all receivers are dropped.
+///
+/// The task ends with an error if powermetrics can't run, e.g. without root.
+pub fn spawn_powermetrics(
+    interval: Duration,
+) -> (PowerReceiver, JoinHandle<Result<(), CommandError>>) {
+    let (tx, rx) = watch::channel(None);
+
+    let handle = tokio::spawn(async move {
+        let interval_ms = interval.as_millis().max(100).to_string();
+        let mut child = Command::new(PROGRAM)
+            .args([
+                "--samplers",
+                SAMPLERS,
+                "--format",
+                "plist",
+                "-i",
+                &interval_ms,
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| spawn_error(PROGRAM, e))?;
+
+        // Drained alongside stdout so a chatty powermetrics never blocks on a full pipe
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let stderr = tokio::spawn(async move {
+            let mut bytes = Vec::new();
+            let _ = stderr.read_to_end(&mut bytes).await;
+            bytes
+        });
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        read_stream(stdout, &tx).await?;
+        if tx.is_closed() {
+            // Nobody is listening; dropping the child kills it
+            return Ok(());
+        }
+
+        let status = child.wait().await.map_err(|e| spawn_error(PROGRAM, e))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(exit_error(
+                PROGRAM,
+                status,
+                &stderr.await.unwrap_or_default(),
+            ))
+        }
+    });
+
+    (rx, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STREAM: &[u8] = include_bytes!("../tests/fixtures/macos/m1/powermetrics_stream.plist");
+
+    #[test]
+    fn test_splitter_handles_partial_documents() {
+        let mut splitter = NulSplitter::default();
+        let mut documents = Vec::new();
+        for chunk in STREAM.chunks(7) {
+            documents.extend(splitter.push(chunk));
+        }
+        assert_eq!(documents.len(), 2);
+        assert!(documents.iter().all(|doc| !doc.contains(&0)));
+        assert!(splitter.push(b"\n\0").is_empty());
+    }
+
+    #[test]
+    fn test_parse_sample() {
+        let document = STREAM.split(|&b| b == 0).next().unwrap();
+        let sample = parse_sample(document).unwrap();
+        assert_eq!(sample.gpu_residency.map(f64::round), Some(12.0));
+        assert_eq!(sample.cpu_power, Some(410.0));
+        assert_eq!(sample.gpu_power, Some(38.0));
+        assert_eq!(sample.ane_power, Some(0.0));
+        assert_eq!(sample.package_power_mw(), Some(448));
+        assert_eq!(sample.thermal_pressure, Some(ThermalPressure::Nominal));
+        assert_eq!(PowerSample::default().package_power_mw(), None);
+
+        assert!(matches!(
+            parse_sample(b"GPU HW active residency: 3.62%"),
+            Err(CommandError::Unparseable { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_stream_publishes_latest() {
+        let (tx, mut rx) = watch::channel(None);
+        assert_eq!(read_stream(STREAM, &tx).await, Ok(2));
+
+        let latest = rx.borrow_and_update().clone().unwrap();
+        assert_eq!(latest.gpu_residency.map(f64::round), Some(45.0));
+        assert_eq!(latest.ane_power, Some(115.0));
+        assert_eq!(latest.thermal_pressure, Some(ThermalPressure::Moderate));
+    }
+
+    #[tokio::test]
+    async fn test_read_stream_skips_unreadable_documents() {
+        let (tx, rx) = watch::channel(None);
+        let stream = [b"powermetrics: unrecognized sampler\n\0".as_slice(), STREAM].concat();
+        assert_eq!(read_stream(stream.as_slice(), &tx).await, Ok(2));
+        assert!(rx.borrow().is_some());
+    }
+}
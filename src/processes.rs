@@ -0,0 +1,159 @@
+// ======================== processes.rs ========================
+// Busiest processes by CPU and by memory, from sysinfo
+
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::fmt;
+use sysinfo::System;
+
+use crate::helpers::format_bytes;
+
+/// Most processes kept per list
+pub const MAX_PROCESSES: usize = 16;
+
+/// Processes per list unless configured otherwise
+pub const DEFAULT_PROCESSES: usize = 5;
+
+/// One process at the time of the sample
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessStats {
+    pub pid: u32,
+    pub name: String,
+    /// Tenths of a percent of one core, as `top` shows it (can exceed 1000)
+    pub cpu: u16,
+    /// Resident memory in bytes
+    pub memory: u64,
+}
+
+impl fmt::Display for ProcessStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}): {}.{}% CPU, {}",
+            self.name,
+            self.pid,
+            self.cpu / 10,
+            self.cpu % 10,
+            format_bytes(self.memory)
+        )
+    }
+}
+
+/// The top of the process list, sorted both ways
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TopProcesses {
+    /// Busiest first; idle processes are left out
+    pub by_cpu: Vec<ProcessStats>,
+    /// Largest first
+    pub by_memory: Vec<ProcessStats>,
+}
+
+impl TopProcesses {
+    /// The `count` busiest and largest of `processes`; ties go to the lower pid
+    pub fn new(processes: &[ProcessStats], count: usize) -> Self {
+        let mut by_cpu: Vec<ProcessStats> =
+            processes.iter().filter(|p| p.cpu > 0).cloned().collect();
+        by_cpu.sort_by_key(|p| (Reverse(p.cpu), p.pid));
+        by_cpu.truncate(count);
+
+        let mut by_memory = processes.to_vec();
+        by_memory.sort_by_key(|p| (Reverse(p.memory), p.pid));
+        by_memory.truncate(count);
+
+        TopProcesses { by_cpu, by_memory }
+    }
+
+    /// Keep the first `count` of each list
+    pub fn truncate(&mut self, count: usize) {
+        self.by_cpu.truncate(count);
+        self.by_memory.truncate(count);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_cpu.is_empty() && self.by_memory.is_empty()
+    }
+}
+
+/// Every process sysinfo knows, leaving out Linux threads (which it lists as tasks)
+pub fn from_system(system: &System) -> Vec<ProcessStats> {
+    system
+        .processes()
+        .values()
+        .filter(|process| process.thread_kind().is_none())
+        .map(|process| ProcessStats {
+            pid: process.pid().as_u32(),
+            name: process.name().to_owned(),
+            cpu: (process.cpu_usage() * 10.0).round().min(u16::MAX as f32) as u16,
+            memory: process.memory(),
+        })
+        .collect()
+}
+
+/// Parse a per-list process count, 0 to `MAX_PROCESSES`
+pub fn parse_process_count(s: &str) -> Result<usize, String> {
+    match s.trim().parse::<usize>() {
+        Ok(count) if count <= MAX_PROCESSES => Ok(count),
+        _ => Err(format!(
+            "expected 0 to {} processes, got '{}'",
+            MAX_PROCESSES, s
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u32, name: &str, cpu: u16, memory: u64) -> ProcessStats {
+        ProcessStats {
+            pid,
+            name: name.to_owned(),
+            cpu,
+            memory,
+        }
+    }
+
+    #[test]
+    fn test_top_processes() {
+        let processes = [
+            process(1, "launchd", 0, 40 << 20),
+            process(812, "WindowServer", 231, 900 << 20),
+            process(4211, "firefox", 1452, 3 << 30),
+            process(5120, "rustc", 3980, 1 << 30),
+            process(5121, "rustc", 1452, 800 << 20),
+        ];
+        let mut top = TopProcesses::new(&processes, 3);
+        let pids = |list: &[ProcessStats]| list.iter().map(|p| p.pid).collect::<Vec<_>>();
+        assert_eq!(pids(&top.by_cpu), [5120, 4211, 5121]);
+        assert_eq!(pids(&top.by_memory), [4211, 5120, 812]);
+        assert_eq!(
+            top.by_cpu[0].to_string(),
+            "rustc (5120): 398.0% CPU, 1.0 GB"
+        );
+
+        // Idle processes never make the CPU list
+        let top_all = TopProcesses::new(&processes, MAX_PROCESSES);
+        assert_eq!(top_all.by_cpu.len(), 4);
+        assert_eq!(top_all.by_memory.len(), 5);
+
+        top.truncate(0);
+        assert!(top.is_empty());
+    }
+
+    #[test]
+    fn test_parse_process_count() {
+        assert_eq!(parse_process_count("5"), Ok(5));
+        assert_eq!(parse_process_count(" 0 "), Ok(0));
+        assert!(parse_process_count("17").is_err());
+        assert!(parse_process_count("many").is_err());
+    }
+
+    #[test]
+    fn test_from_system_skips_threads() {
+        let mut system = System::new();
+        system.refresh_processes();
+        let processes = from_system(&system);
+        let own = std::process::id();
+        assert_eq!(processes.iter().filter(|p| p.pid == own).count(), 1);
+    }
+}
@@ -0,0 +1,1794 @@
+// ======================== protocol.rs ========================
+// Versioned message envelope and capability handshake
+//
+// Every framed message is laid out as:
+//
+//   magic "FM" | version u8 | type u8 | encoding u8 | length u16 | payload | crc16
+//
+// All integers are little-endian. The CRC is CRC-16/CCITT-FALSE over everything
+// before it. Flipper apps that never answer the Hello keep receiving the bare
+// packed struct, so the stock PC Monitor app continues to work unchanged.
+//
+// Hello and Capabilities are exchanged as single writes. Once a session is
+// framed, every host message is split into fragments (see fragment.rs).
+
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::cpu_topology::CoreKind;
+use crate::disks::{DiskIo, DiskStats};
+use crate::helpers::{split_list, truncate_utf8};
+use crate::memory::{MemoryPressure, MemoryStats};
+use crate::network::{InterfaceStats, LinkState, NetworkStats};
+use crate::power::{BatteryStats, ChargeState, PowerSource, PowerStats, ThermalPressure};
+use crate::processes::{ProcessStats, TopProcesses};
+use crate::sensors::{Sensor, SensorKind};
+use crate::system_info::{CoreStats, GpuStats, SystemInfo};
+use crate::transport::{Transport, TransportError};
+use crate::wire::{self, DecodeError, Encoding, WireReader, WireWriter};
+
+pub const MAGIC: [u8; 2] = *b"FM";
+pub const PROTOCOL_VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 7;
+pub const CRC_LEN: usize = 2;
+
+/// How long to wait for a Capabilities reply before falling back to legacy mode
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(1500);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    /// Host -> device: protocol version and what the host can send
+    Hello,
+    /// Device -> host: protocol version and what the device understands
+    Capabilities,
+    /// Host -> device: one `SystemInfo` sample restricted to the negotiated fields
+    Metrics,
+    /// Host -> device: every GPU, for apps that page through them
+    Gpus,
+    /// Host -> device: usage and clock of every logical CPU
+    Cores,
+    /// Host -> device: labelled temperatures and fan speeds
+    Sensors,
+    /// Host -> device: capacity and throughput of every selected disk
+    Disks,
+    /// Host -> device: total and per-interface throughput, link state and addresses
+    Network,
+    /// Host -> device: memory breakdown, swap and pressure
+    Memory,
+    /// Host -> device: power source, battery and power draw
+    Power,
+    /// Host -> device: busiest processes by CPU and by memory
+    Processes,
+    /// A type introduced by a newer peer; receivers skip it
+    Unknown(u8),
+}
+
+impl MessageType {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            MessageType::Hello => 0x01,
+            MessageType::Capabilities => 0x02,
+            MessageType::Metrics => 0x10,
+            MessageType::Gpus => 0x11,
+            MessageType::Cores => 0x12,
+            MessageType::Sensors => 0x13,
+            MessageType::Disks => 0x14,
+            MessageType::Network => 0x15,
+            MessageType::Memory => 0x16,
+            MessageType::Power => 0x17,
+            MessageType::Processes => 0x18,
+            MessageType::Unknown(v) => v,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            0x01 => MessageType::Hello,
+            0x02 => MessageType::Capabilities,
+            0x10 => MessageType::Metrics,
+            0x11 => MessageType::Gpus,
+            0x12 => MessageType::Cores,
+            0x13 => MessageType::Sensors,
+            0x14 => MessageType::Disks,
+            0x15 => MessageType::Network,
+            0x16 => MessageType::Memory,
+            0x17 => MessageType::Power,
+            0x18 => MessageType::Processes,
+            v => MessageType::Unknown(v),
+        }
+    }
+}
+
+/// Bit set of the metric fields a peer can send or understand
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MetricFields(pub u32);
+
+impl MetricFields {
+    pub const CPU: MetricFields = MetricFields(1 << 0);
+    pub const RAM: MetricFields = MetricFields(1 << 1);
+    pub const GPU: MetricFields = MetricFields(1 << 2);
+    pub const VRAM: MetricFields = MetricFields(1 << 3);
+    /// The per-GPU list, sent as its own Gpus message
+    pub const GPUS: MetricFields = MetricFields(1 << 4);
+    /// Per-core CPU usage and clock, sent as its own Cores message
+    pub const CORES: MetricFields = MetricFields(1 << 5);
+    /// Temperatures and fans, sent as their own Sensors message
+    pub const SENSORS: MetricFields = MetricFields(1 << 6);
+    /// Per-mount capacity and I/O, sent as their own Disks message
+    pub const DISKS: MetricFields = MetricFields(1 << 7);
+    /// Network throughput and interfaces, sent as their own Network message
+    pub const NETWORK: MetricFields = MetricFields(1 << 8);
+    /// Memory breakdown, swap and pressure, sent as their own Memory message
+    pub const MEMORY: MetricFields = MetricFields(1 << 9);
+    /// Power source and battery, sent as their own Power message
+    pub const POWER: MetricFields = MetricFields(1 << 10);
+    /// Top processes, sent as their own Processes message
+    pub const PROCESSES: MetricFields = MetricFields(1 << 11);
+
+    /// Fields understood by the stock PC Monitor app
+    pub const LEGACY: MetricFields = MetricFields(0b1111);
+    /// Fields whose values travel in Metrics messages
+    pub const IN_METRICS: MetricFields = Self::LEGACY;
+
+    pub const fn empty() -> Self {
+        MetricFields(0)
+    }
+
+    /// Every field this build knows how to send
+    pub const fn all() -> Self {
+        Self::LEGACY
+            .union(Self::GPUS)
+            .union(Self::CORES)
+            .union(Self::SENSORS)
+            .union(Self::DISKS)
+            .union(Self::NETWORK)
+            .union(Self::MEMORY)
+            .union(Self::POWER)
+            .union(Self::PROCESSES)
+    }
+
+    pub const fn contains(self, other: MetricFields) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersection(self, other: MetricFields) -> Self {
+        MetricFields(self.0 & other.0)
+    }
+
+    pub const fn union(self, other: MetricFields) -> Self {
+        MetricFields(self.0 | other.0)
+    }
+}
+
+impl FromStr for MetricFields {
+    type Err = String;
+
+    /// Parse a list like "cpu+ram" or "cpu,gpu,vram"; "all" selects everything
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = MetricFields::empty();
+        for name in split_list(s) {
+            fields = fields.union(match name.to_ascii_lowercase().as_str() {
+                "cpu" => MetricFields::CPU,
+                "ram" => MetricFields::RAM,
+                "gpu" => MetricFields::GPU,
+                "vram" => MetricFields::VRAM,
+                "gpus" => MetricFields::GPUS,
+                "cores" => MetricFields::CORES,
+                "sensors" => MetricFields::SENSORS,
+                "disks" => MetricFields::DISKS,
+                "network" => MetricFields::NETWORK,
+                "memory" => MetricFields::MEMORY,
+                "power" => MetricFields::POWER,
+                "processes" => MetricFields::PROCESSES,
+                "all" => MetricFields::all(),
+                other => return Err(format!("unknown metric '{}'", other)),
+            });
+        }
+        if fields == MetricFields::empty() {
+            return Err("at least one metric must be selected".to_owned());
+        }
+        Ok(fields)
+    }
+}
+
+/// Bit set of payload encodings a peer can parse
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Encodings(pub u8);
+
+impl Encodings {
+    pub const PACKED: Encodings = Encodings(1 << 0);
+    pub const JSON: Encodings = Encodings(1 << 1);
+
+    pub fn supports(self, encoding: Encoding) -> bool {
+        self.0 & Self::bit(encoding) != 0
+    }
+
+    fn bit(encoding: Encoding) -> u8 {
+        match encoding {
+            Encoding::Packed => Self::PACKED.0,
+            Encoding::Json => Self::JSON.0,
+        }
+    }
+}
+
+/// Payload of both Hello and Capabilities
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub version: u8,
+    pub fields: MetricFields,
+    pub encodings: Encodings,
+    /// Largest framed message the sender can reassemble
+    pub max_payload: u16,
+}
+
+impl Capabilities {
+    /// What this host build can produce
+    pub fn host() -> Self {
+        Capabilities {
+            version: PROTOCOL_VERSION,
+            fields: MetricFields::all(),
+            encodings: Encodings(Encodings::PACKED.0 | Encodings::JSON.0),
+            max_payload: u16::MAX,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = WireWriter::new();
+        w.u8(self.version)
+            .u32(self.fields.0)
+            .u8(self.encodings.0)
+            .u16(self.max_payload);
+        w.into_inner()
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = WireReader::new(payload);
+        Ok(Capabilities {
+            version: r.u8()?,
+            fields: MetricFields(r.u32()?),
+            encodings: Encodings(r.u8()?),
+            max_payload: r.u16()?,
+        })
+    }
+}
+
+/// A decoded envelope
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub version: u8,
+    pub msg_type: MessageType,
+    pub encoding: Encoding,
+    pub payload: Vec<u8>,
+}
+
+/// CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF), cheap to compute on the Flipper
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn encoding_to_u8(encoding: Encoding) -> u8 {
+    match encoding {
+        Encoding::Packed => 0,
+        Encoding::Json => 1,
+    }
+}
+
+fn encoding_from_u8(v: u8) -> Result<Encoding, DecodeError> {
+    match v {
+        0 => Ok(Encoding::Packed),
+        1 => Ok(Encoding::Json),
+        _ => Err(DecodeError::Invalid("unknown payload encoding")),
+    }
+}
+
+#[derive(Debug)]
+pub enum EncodeError {
+    Json(serde_json::Error),
+    /// The payload doesn't fit the envelope's u16 length
+    PayloadTooLarge(usize),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Json(e) => write!(f, "{}", e),
+            EncodeError::PayloadTooLarge(size) => {
+                write!(f, "payload of {} bytes does not fit in a frame", size)
+            }
+        }
+    }
+}
+
+impl Error for EncodeError {}
+
+impl From<serde_json::Error> for EncodeError {
+    fn from(e: serde_json::Error) -> Self {
+        EncodeError::Json(e)
+    }
+}
+
+/// Wrap a payload in the envelope
+pub fn encode_frame(
+    msg_type: MessageType,
+    encoding: Encoding,
+    payload: &[u8],
+) -> Result<Vec<u8>, EncodeError> {
+    let length =
+        u16::try_from(payload.len()).map_err(|_| EncodeError::PayloadTooLarge(payload.len()))?;
+    let mut w = WireWriter::new();
+    w.bytes(&MAGIC)
+        .u8(PROTOCOL_VERSION)
+        .u8(msg_type.to_u8())
+        .u8(encoding_to_u8(encoding))
+        .u16(length)
+        .bytes(payload);
+    let mut frame = w.into_inner();
+    let crc = crc16(&frame);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    Ok(frame)
+}
+
+/// Parse and verify an envelope
+pub fn decode_frame(data: &[u8]) -> Result<Frame, DecodeError> {
+    if data.len() < HEADER_LEN + CRC_LEN {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+
+    let (body, crc) = data.split_at(data.len() - CRC_LEN);
+    let mut r = WireReader::new(body);
+    if r.bytes(2)? != MAGIC {
+        return Err(DecodeError::Invalid("bad magic"));
+    }
+    let version = r.u8()?;
+    let msg_type = MessageType::from_u8(r.u8()?);
+    let encoding = encoding_from_u8(r.u8()?)?;
+    let length = r.u16()? as usize;
+    if r.remaining() != length {
+        return Err(DecodeError::Length {
+            expected: length,
+            actual: r.remaining(),
+        });
+    }
+    if u16::from_le_bytes([crc[0], crc[1]]) != crc16(body) {
+        return Err(DecodeError::Invalid("crc mismatch"));
+    }
+
+    Ok(Frame {
+        version,
+        msg_type,
+        encoding,
+        payload: r.bytes(length)?.to_vec(),
+    })
+}
+
+/// Encode the negotiated subset of a sample.
+///
+/// The packed form is the field mask followed by each present field in bit order,
+/// so new fields can be appended without changing the position of existing ones.
+/// Only fields in `MetricFields::IN_METRICS` are written.
+pub fn encode_metrics(
+    info: &SystemInfo,
+    fields: MetricFields,
+    encoding: Encoding,
+) -> Result<Vec<u8>, serde_json::Error> {
+    let fields = fields.intersection(MetricFields::IN_METRICS);
+    match encoding {
+        Encoding::Packed => {
+            let mut w = WireWriter::new();
+            w.u32(fields.0);
+            if fields.contains(MetricFields::CPU) {
+                w.u8(info.cpu_usage);
+            }
+            if fields.contains(MetricFields::RAM) {
+                w.u16(info.ram_max).u8(info.ram_usage).bytes(&info.ram_unit);
+            }
+            if fields.contains(MetricFields::GPU) {
+                w.u8(info.gpu_usage);
+            }
+            if fields.contains(MetricFields::VRAM) {
+                w.u16(info.vram_max)
+                    .u8(info.vram_usage)
+                    .bytes(&info.vram_unit);
+            }
+            Ok(w.into_inner())
+        }
+        Encoding::Json => {
+            let mut all = match serde_json::to_value(info)? {
+                Value::Object(map) => map,
+                _ => Map::new(),
+            };
+            let mut out = Map::new();
+            let groups: [(MetricFields, &[&str]); 4] = [
+                (MetricFields::CPU, &["cpu_usage"]),
+                (MetricFields::RAM, &["ram_max", "ram_usage", "ram_unit"]),
+                (MetricFields::GPU, &["gpu_usage"]),
+                (MetricFields::VRAM, &["vram_max", "vram_usage", "vram_unit"]),
+            ];
+            for (field, keys) in groups {
+                if fields.contains(field) {
+                    for key in keys {
+                        if let Some(v) = all.remove(*key) {
+                            out.insert(key.to_string(), v);
+                        }
+                    }
+                }
+            }
+            serde_json::to_vec(&out)
+        }
+    }
+}
+
+/// Zero the fields that were not selected, for layouts that always carry all of them
+pub fn mask_fields(info: &SystemInfo, fields: MetricFields) -> SystemInfo {
+    let mut masked = info.clone();
+    if !fields.contains(MetricFields::CPU) {
+        masked.cpu_usage = 0;
+    }
+    if !fields.contains(MetricFields::RAM) {
+        masked.ram_max = 0;
+        masked.ram_usage = 0;
+        masked.ram_unit = [0; 4];
+    }
+    if !fields.contains(MetricFields::GPU) {
+        masked.gpu_usage = 0;
+    }
+    if !fields.contains(MetricFields::VRAM) {
+        masked.vram_max = 0;
+        masked.vram_usage = 0;
+        masked.vram_unit = [0; 4];
+    }
+    masked
+}
+
+/// Decode a packed Metrics payload; absent fields are left zeroed
+pub fn decode_metrics(payload: &[u8]) -> Result<(MetricFields, SystemInfo), DecodeError> {
+    let mut r = WireReader::new(payload);
+    let fields = MetricFields(r.u32()?);
+    let mut info = SystemInfo::default();
+    if fields.contains(MetricFields::CPU) {
+        info.cpu_usage = r.u8()?;
+    }
+    if fields.contains(MetricFields::RAM) {
+        info.ram_max = r.u16()?;
+        info.ram_usage = r.u8()?;
+        info.ram_unit = r.array4()?;
+    }
+    if fields.contains(MetricFields::GPU) {
+        info.gpu_usage = r.u8()?;
+    }
+    if fields.contains(MetricFields::VRAM) {
+        info.vram_max = r.u16()?;
+        info.vram_usage = r.u8()?;
+        info.vram_unit = r.array4()?;
+    }
+    Ok((fields, info))
+}
+
+/// Longest GPU name sent in a Gpus message, in bytes
+pub const GPU_NAME_MAX: usize = 24;
+
+/// Sent in a Gpus message for GPUs without a power reading
+const NO_GPU_POWER: u32 = u32::MAX;
+
+/// Encode every GPU, at most 255 of them, with names cut to `GPU_NAME_MAX` bytes.
+///
+/// The packed form is a count followed by, per GPU:
+///
+///   usage u8 | vram_max u16 | vram_usage u8 | vram_unit [4] | power mW u32
+///   | name_len u8 | name
+///
+/// Power is 0xFFFFFFFF for GPUs that don't report it.
+pub fn encode_gpus(gpus: &[GpuStats], encoding: Encoding) -> Result<Vec<u8>, serde_json::Error> {
+    let gpus: Vec<GpuStats> = gpus
+        .iter()
+        .take(u8::MAX as usize)
+        .map(|gpu| GpuStats {
+            name: truncate_utf8(&gpu.name, GPU_NAME_MAX).to_owned(),
+            ..gpu.clone()
+        })
+        .collect();
+
+    match encoding {
+        Encoding::Packed => {
+            let mut w = WireWriter::new();
+            w.u8(gpus.len() as u8);
+            for gpu in &gpus {
+                w.u8(gpu.usage)
+                    .u16(gpu.vram_max)
+                    .u8(gpu.vram_usage)
+                    .bytes(&gpu.vram_unit)
+                    .u32(
+                        gpu.power_mw
+                            .map_or(NO_GPU_POWER, |mw| mw.min(NO_GPU_POWER - 1)),
+                    )
+                    .u8(gpu.name.len() as u8)
+                    .bytes(gpu.name.as_bytes());
+            }
+            Ok(w.into_inner())
+        }
+        Encoding::Json => serde_json::to_vec(&serde_json::json!({ "gpus": gpus })),
+    }
+}
+
+/// Decode a packed Gpus payload
+pub fn decode_gpus(payload: &[u8]) -> Result<Vec<GpuStats>, DecodeError> {
+    let mut r = WireReader::new(payload);
+    let count = r.u8()?;
+    (0..count)
+        .map(|_| {
+            let usage = r.u8()?;
+            let vram_max = r.u16()?;
+            let vram_usage = r.u8()?;
+            let vram_unit = r.array4()?;
+            let power = r.u32()?;
+            let name_len = r.u8()? as usize;
+            let name = std::str::from_utf8(r.bytes(name_len)?)
+                .map_err(|_| DecodeError::Invalid("GPU name is not UTF-8"))?;
+            Ok(GpuStats {
+                name: name.to_owned(),
+                usage,
+                vram_max,
+                vram_usage,
+                vram_unit,
+                power_mw: (power != NO_GPU_POWER).then_some(power),
+            })
+        })
+        .collect()
+}
+
+/// Set on a core's usage byte in a packed Cores message if it is an efficiency core
+pub const EFFICIENCY_CORE_FLAG: u8 = 0x80;
+
+/// Encode every logical CPU, at most 255 of them.
+///
+/// The packed form is laid out for drawing a bar chart:
+///
+///   count u8 | usage u8 * count | MHz u16 * count
+///
+/// Usage is 0-100 with `EFFICIENCY_CORE_FLAG` set on efficiency cores of hybrid CPUs.
+pub fn encode_cores(cores: &[CoreStats], encoding: Encoding) -> Result<Vec<u8>, serde_json::Error> {
+    let cores = &cores[..cores.len().min(u8::MAX as usize)];
+    match encoding {
+        Encoding::Packed => {
+            let mut w = WireWriter::new();
+            w.u8(cores.len() as u8);
+            for core in cores {
+                let flag = match core.kind {
+                    Some(CoreKind::Efficiency) => EFFICIENCY_CORE_FLAG,
+                    _ => 0,
+                };
+                w.u8(core.usage.min(100) | flag);
+            }
+            for core in cores {
+                w.u16(core.frequency);
+            }
+            Ok(w.into_inner())
+        }
+        Encoding::Json => serde_json::to_vec(&serde_json::json!({ "cores": cores })),
+    }
+}
+
+/// Decode a packed Cores payload.
+///
+/// Core kinds are only known when at least one efficiency core is flagged.
+pub fn decode_cores(payload: &[u8]) -> Result<Vec<CoreStats>, DecodeError> {
+    let mut r = WireReader::new(payload);
+    let count = r.u8()? as usize;
+    let usages = r.bytes(count)?;
+    let hybrid = usages.iter().any(|u| u & EFFICIENCY_CORE_FLAG != 0);
+    usages
+        .iter()
+        .map(|&usage| {
+            let kind = match (hybrid, usage & EFFICIENCY_CORE_FLAG != 0) {
+                (false, _) => None,
+                (true, true) => Some(CoreKind::Efficiency),
+                (true, false) => Some(CoreKind::Performance),
+            };
+            Ok(CoreStats {
+                usage: usage & !EFFICIENCY_CORE_FLAG,
+                frequency: r.u16()?,
+                kind,
+            })
+        })
+        .collect()
+}
+
+/// Longest sensor label sent in a Sensors message, in bytes
+pub const SENSOR_LABEL_MAX: usize = 20;
+
+/// Set on a fan's kind byte when its value is percent of full speed rather than RPM
+const SENSOR_PERCENT_FLAG: u8 = 0x80;
+
+/// Encode sensor readings, at most 255 of them, with labels cut to `SENSOR_LABEL_MAX` bytes.
+///
+/// The packed form is a count followed by, per sensor:
+///
+///   kind u8 | value u16 | label_len u8 | label
+///
+/// Kinds are 0 cpu, 1 gpu, 2 storage, 3 other (temperatures, value is a signed
+/// tenth of a degree Celsius) and 4 fan (value is RPM). Bit 7 of the kind is
+/// set on fans that only report percent of full speed.
+pub fn encode_sensors(
+    sensors: &[Sensor],
+    encoding: Encoding,
+) -> Result<Vec<u8>, serde_json::Error> {
+    let sensors: Vec<Sensor> = sensors
+        .iter()
+        .take(u8::MAX as usize)
+        .map(|sensor| Sensor {
+            label: truncate_utf8(&sensor.label, SENSOR_LABEL_MAX).to_owned(),
+            ..sensor.clone()
+        })
+        .collect();
+
+    match encoding {
+        Encoding::Packed => {
+            let mut w = WireWriter::new();
+            w.u8(sensors.len() as u8);
+            for sensor in &sensors {
+                let value = match sensor.kind {
+                    SensorKind::Fan => sensor.value.clamp(0, u16::MAX as i32) as u16,
+                    _ => sensor.value.clamp(i16::MIN as i32, i16::MAX as i32) as i16 as u16,
+                };
+                let flags = if sensor.percent {
+                    SENSOR_PERCENT_FLAG
+                } else {
+                    0
+                };
+                w.u8(sensor.kind.to_u8() | flags)
+                    .u16(value)
+                    .u8(sensor.label.len() as u8)
+                    .bytes(sensor.label.as_bytes());
+            }
+            Ok(w.into_inner())
+        }
+        Encoding::Json => serde_json::to_vec(&serde_json::json!({ "sensors": sensors })),
+    }
+}
+
+/// Decode a packed Sensors payload
+pub fn decode_sensors(payload: &[u8]) -> Result<Vec<Sensor>, DecodeError> {
+    let mut r = WireReader::new(payload);
+    let count = r.u8()?;
+    (0..count)
+        .map(|_| {
+            let byte = r.u8()?;
+            let kind = SensorKind::from_u8(byte & !SENSOR_PERCENT_FLAG)
+                .ok_or(DecodeError::Invalid("unknown sensor kind"))?;
+            let raw = r.u16()?;
+            let value = match kind {
+                SensorKind::Fan => raw as i32,
+                _ => raw as i16 as i32,
+            };
+            let label_len = r.u8()? as usize;
+            let label = std::str::from_utf8(r.bytes(label_len)?)
+                .map_err(|_| DecodeError::Invalid("sensor label is not UTF-8"))?;
+            Ok(Sensor {
+                label: label.to_owned(),
+                kind,
+                value,
+                percent: byte & SENSOR_PERCENT_FLAG != 0,
+            })
+        })
+        .collect()
+}
+
+/// Longest mount point sent in a Disks message, in bytes
+pub const DISK_LABEL_MAX: usize = 16;
+
+/// Read rate sent for disks without I/O counters
+const NO_DISK_IO: u32 = u32::MAX;
+
+/// Encode disks, at most 255 of them, with mount points cut to `DISK_LABEL_MAX` bytes.
+///
+/// The packed form is a count followed by, per disk:
+///
+///   usage u8 | size u16 | unit [u8; 4] | read KiB/s u32 | write KiB/s u32
+///   | read IOPS u16 | write IOPS u16 | label_len u8 | mount point
+///
+/// A read rate of 0xFFFFFFFF means the disk has no I/O figures. The device
+/// name is only in the JSON form.
+pub fn encode_disks(disks: &[DiskStats], encoding: Encoding) -> Result<Vec<u8>, serde_json::Error> {
+    let disks: Vec<DiskStats> = disks
+        .iter()
+        .take(u8::MAX as usize)
+        .map(|disk| DiskStats {
+            mount: truncate_utf8(&disk.mount, DISK_LABEL_MAX).to_owned(),
+            ..disk.clone()
+        })
+        .collect();
+
+    match encoding {
+        Encoding::Packed => {
+            let kib = |bytes: u64| (bytes / 1024).min(NO_DISK_IO as u64 - 1) as u32;
+            let iops = |n: u32| n.min(u16::MAX as u32) as u16;
+            let mut w = WireWriter::new();
+            w.u8(disks.len() as u8);
+            for disk in &disks {
+                w.u8(disk.usage).u16(disk.size).bytes(&disk.unit);
+                match disk.io {
+                    Some(io) => w
+                        .u32(kib(io.read_bytes_per_sec))
+                        .u32(kib(io.write_bytes_per_sec))
+                        .u16(iops(io.read_iops))
+                        .u16(iops(io.write_iops)),
+                    None => w.u32(NO_DISK_IO).u32(0).u16(0).u16(0),
+                };
+                w.u8(disk.mount.len() as u8).bytes(disk.mount.as_bytes());
+            }
+            Ok(w.into_inner())
+        }
+        Encoding::Json => serde_json::to_vec(&serde_json::json!({ "disks": disks })),
+    }
+}
+
+/// Decode a packed Disks payload; rates come back rounded down to whole KiB/s
+pub fn decode_disks(payload: &[u8]) -> Result<Vec<DiskStats>, DecodeError> {
+    let mut r = WireReader::new(payload);
+    let count = r.u8()?;
+    (0..count)
+        .map(|_| {
+            let usage = r.u8()?;
+            let size = r.u16()?;
+            let unit = r.array4()?;
+            let (read, write) = (r.u32()?, r.u32()?);
+            let (read_iops, write_iops) = (r.u16()?, r.u16()?);
+            let label_len = r.u8()? as usize;
+            let mount = std::str::from_utf8(r.bytes(label_len)?)
+                .map_err(|_| DecodeError::Invalid("disk label is not UTF-8"))?;
+            Ok(DiskStats {
+                mount: mount.to_owned(),
+                device: String::new(),
+                size,
+                unit,
+                usage,
+                io: (read != NO_DISK_IO).then_some(DiskIo {
+                    read_bytes_per_sec: read as u64 * 1024,
+                    write_bytes_per_sec: write as u64 * 1024,
+                    read_iops: read_iops as u32,
+                    write_iops: write_iops as u32,
+                }),
+            })
+        })
+        .collect()
+}
+
+/// Longest interface name sent in a Network message, in bytes
+pub const INTERFACE_NAME_MAX: usize = 12;
+
+/// Set in an interface's flags when an IPv4 / IPv6 address follows
+const HAS_IPV4_FLAG: u8 = 0x40;
+const HAS_IPV6_FLAG: u8 = 0x80;
+const LINK_STATE_MASK: u8 = 0x03;
+
+/// Encode network throughput and at most 255 interfaces, with names cut to
+/// `INTERFACE_NAME_MAX` bytes.
+///
+/// The packed form is the totals, `rx KiB/s u32 | tx KiB/s u32`, then a count
+/// followed by, per interface:
+///
+///   flags u8 | rx KiB/s u32 | tx KiB/s u32 | [ipv4 [u8; 4]] | [ipv6 [u8; 16]]
+///   | name_len u8 | name
+///
+/// Bits 0-1 of the flags are the link state (0 unknown, 1 up, 2 down); bit 6
+/// and bit 7 say whether the IPv4 and IPv6 address are present.
+pub fn encode_network(
+    network: &NetworkStats,
+    encoding: Encoding,
+) -> Result<Vec<u8>, serde_json::Error> {
+    let network = NetworkStats {
+        interfaces: network
+            .interfaces
+            .iter()
+            .take(u8::MAX as usize)
+            .map(|interface| InterfaceStats {
+                name: truncate_utf8(&interface.name, INTERFACE_NAME_MAX).to_owned(),
+                ..interface.clone()
+            })
+            .collect(),
+        ..network.clone()
+    };
+
+    match encoding {
+        Encoding::Packed => {
+            let kib = |bytes: u64| (bytes / 1024).min(u32::MAX as u64) as u32;
+            let mut w = WireWriter::new();
+            w.u32(kib(network.rx_bytes_per_sec))
+                .u32(kib(network.tx_bytes_per_sec))
+                .u8(network.interfaces.len() as u8);
+            for interface in &network.interfaces {
+                let mut flags = interface.state.to_u8();
+                if interface.ipv4.is_some() {
+                    flags |= HAS_IPV4_FLAG;
+                }
+                if interface.ipv6.is_some() {
+                    flags |= HAS_IPV6_FLAG;
+                }
+                w.u8(flags)
+                    .u32(kib(interface.rx_bytes_per_sec))
+                    .u32(kib(interface.tx_bytes_per_sec));
+                if let Some(ipv4) = interface.ipv4 {
+                    w.bytes(&ipv4.octets());
+                }
+                if let Some(ipv6) = interface.ipv6 {
+                    w.bytes(&ipv6.octets());
+                }
+                w.u8(interface.name.len() as u8)
+                    .bytes(interface.name.as_bytes());
+            }
+            Ok(w.into_inner())
+        }
+        Encoding::Json => serde_json::to_vec(&serde_json::json!({ "network": network })),
+    }
+}
+
+/// Decode a packed Network payload; rates come back rounded down to whole KiB/s
+pub fn decode_network(payload: &[u8]) -> Result<NetworkStats, DecodeError> {
+    let mut r = WireReader::new(payload);
+    let rx_bytes_per_sec = r.u32()? as u64 * 1024;
+    let tx_bytes_per_sec = r.u32()? as u64 * 1024;
+    let count = r.u8()?;
+    let interfaces = (0..count)
+        .map(|_| {
+            let flags = r.u8()?;
+            let state = LinkState::from_u8(flags & LINK_STATE_MASK)
+                .ok_or(DecodeError::Invalid("unknown link state"))?;
+            let rx = r.u32()? as u64 * 1024;
+            let tx = r.u32()? as u64 * 1024;
+            let ipv4 = match flags & HAS_IPV4_FLAG {
+                0 => None,
+                _ => Some(<[u8; 4]>::try_from(r.bytes(4)?).unwrap().into()),
+            };
+            let ipv6 = match flags & HAS_IPV6_FLAG {
+                0 => None,
+                _ => Some(<[u8; 16]>::try_from(r.bytes(16)?).unwrap().into()),
+            };
+            let name_len = r.u8()? as usize;
+            let name = std::str::from_utf8(r.bytes(name_len)?)
+                .map_err(|_| DecodeError::Invalid("interface name is not UTF-8"))?;
+            Ok(InterfaceStats {
+                name: name.to_owned(),
+                rx_bytes_per_sec: rx,
+                tx_bytes_per_sec: tx,
+                state,
+                ipv4,
+                ipv6,
+            })
+        })
+        .collect::<Result<_, DecodeError>>()?;
+    Ok(NetworkStats {
+        rx_bytes_per_sec,
+        tx_bytes_per_sec,
+        interfaces,
+    })
+}
+
+/// Sent in a Memory message for figures the platform doesn't expose
+const NO_MEMORY_VALUE: u32 = u32::MAX;
+
+/// Encode the memory breakdown.
+///
+/// The packed form is ten sizes in MiB, each a u32 (0xFFFFFFFF where the
+/// platform has no such figure), then the pressure level:
+///
+///   total | used | available | free | cached | buffers | compressed | wired
+///   | swap_total | swap_used | pressure u8
+///
+/// Pressure is 0 unknown, 1 normal, 2 warning or 3 critical.
+pub fn encode_memory(
+    memory: &MemoryStats,
+    encoding: Encoding,
+) -> Result<Vec<u8>, serde_json::Error> {
+    match encoding {
+        Encoding::Packed => {
+            let mib = |bytes: u64| (bytes >> 20).min(NO_MEMORY_VALUE as u64 - 1) as u32;
+            let optional = |bytes: Option<u64>| bytes.map_or(NO_MEMORY_VALUE, mib);
+            let mut w = WireWriter::new();
+            w.u32(mib(memory.total))
+                .u32(mib(memory.used))
+                .u32(mib(memory.available))
+                .u32(mib(memory.free))
+                .u32(optional(memory.cached))
+                .u32(optional(memory.buffers))
+                .u32(optional(memory.compressed))
+                .u32(optional(memory.wired))
+                .u32(mib(memory.swap_total))
+                .u32(mib(memory.swap_used))
+                .u8(memory.pressure.map_or(0, MemoryPressure::to_u8));
+            Ok(w.into_inner())
+        }
+        Encoding::Json => serde_json::to_vec(&serde_json::json!({ "memory": memory })),
+    }
+}
+
+/// Decode a packed Memory payload; sizes come back rounded down to whole MiB
+pub fn decode_memory(payload: &[u8]) -> Result<MemoryStats, DecodeError> {
+    let mut r = WireReader::new(payload);
+    let mut sizes = [0u32; 10];
+    for size in &mut sizes {
+        *size = r.u32()?;
+    }
+    let bytes = |mib: u32| (mib as u64) << 20;
+    let optional = |mib: u32| (mib != NO_MEMORY_VALUE).then(|| bytes(mib));
+    let [total, used, available, free, cached, buffers, compressed, wired, swap_total, swap_used] =
+        sizes;
+    Ok(MemoryStats {
+        total: bytes(total),
+        used: bytes(used),
+        available: bytes(available),
+        free: bytes(free),
+        cached: optional(cached),
+        buffers: optional(buffers),
+        compressed: optional(compressed),
+        wired: optional(wired),
+        swap_total: bytes(swap_total),
+        swap_used: bytes(swap_used),
+        pressure: MemoryPressure::from_u8(r.u8()?),
+    })
+}
+
+/// Sent in a Power message for a percentage when there is no battery
+const NO_BATTERY: u8 = u8::MAX;
+
+/// Encode the power source and battery.
+///
+/// The packed form is:
+///
+///   source u8 | percent u8 | state u8 | minutes u16 | cycles u16 | draw mW u32
+///   | thermal u8
+///
+/// Source is 0 unknown, 1 AC or 2 battery; percent is 0xFF without a battery;
+/// state is 0 unknown, 1 discharging, 2 charging, 3 full or 4 not charging;
+/// thermal pressure is 0 unknown, then nominal, moderate, heavy, trapping and
+/// sleeping from 1 to 5. Unknown minutes, cycles and draw are all ones.
+pub fn encode_power(power: &PowerStats, encoding: Encoding) -> Result<Vec<u8>, serde_json::Error> {
+    match encoding {
+        Encoding::Packed => {
+            let battery = power.battery.unwrap_or_default();
+            let mut w = WireWriter::new();
+            w.u8(power.source.map_or(0, PowerSource::to_u8))
+                .u8(power.battery.map_or(NO_BATTERY, |b| b.percent))
+                .u8(battery.state.map_or(0, ChargeState::to_u8))
+                .u16(
+                    battery
+                        .minutes_remaining
+                        .map_or(u16::MAX, |m| m.min(u16::MAX as u32 - 1) as u16),
+                )
+                .u16(
+                    battery
+                        .cycle_count
+                        .map_or(u16::MAX, |c| c.min(u16::MAX as u32 - 1) as u16),
+                )
+                .u32(
+                    power
+                        .power_draw_mw
+                        .map_or(u32::MAX, |mw| mw.min(u32::MAX - 1)),
+                )
+                .u8(power.thermal_pressure.map_or(0, ThermalPressure::to_u8));
+            Ok(w.into_inner())
+        }
+        Encoding::Json => serde_json::to_vec(&serde_json::json!({ "power": power })),
+    }
+}
+
+/// Decode a packed Power payload
+pub fn decode_power(payload: &[u8]) -> Result<PowerStats, DecodeError> {
+    let mut r = WireReader::new(payload);
+    let source = PowerSource::from_u8(r.u8()?);
+    let percent = r.u8()?;
+    let state = ChargeState::from_u8(r.u8()?);
+    let minutes = r.u16()?;
+    let cycles = r.u16()?;
+    let draw = r.u32()?;
+    let thermal_pressure = ThermalPressure::from_u8(r.u8()?);
+    let battery = (percent != NO_BATTERY).then(|| BatteryStats {
+        percent,
+        state,
+        minutes_remaining: (minutes != u16::MAX).then_some(minutes as u32),
+        cycle_count: (cycles != u16::MAX).then_some(cycles as u32),
+    });
+    Ok(PowerStats {
+        source,
+        battery,
+        power_draw_mw: (draw != u32::MAX).then_some(draw),
+        thermal_pressure,
+    })
+}
+
+/// Longest process name sent to the Flipper, in bytes (Linux's own limit)
+pub const PROCESS_NAME_MAX: usize = 15;
+
+/// Encode the top process lists.
+///
+/// The packed form is both counts, then the CPU list followed by the memory
+/// list, each process as:
+///
+///   pid u32 | cpu u16 | memory MiB u32 | name_len u8 | name
+///
+/// CPU is in tenths of a percent of one core. A process busy and large
+/// appears in both lists.
+pub fn encode_processes(
+    processes: &TopProcesses,
+    encoding: Encoding,
+) -> Result<Vec<u8>, serde_json::Error> {
+    let list = |list: &[ProcessStats]| -> Vec<ProcessStats> {
+        list.iter()
+            .take(u8::MAX as usize)
+            .map(|process| ProcessStats {
+                name: truncate_utf8(&process.name, PROCESS_NAME_MAX).to_owned(),
+                ..process.clone()
+            })
+            .collect()
+    };
+    let processes = TopProcesses {
+        by_cpu: list(&processes.by_cpu),
+        by_memory: list(&processes.by_memory),
+    };
+
+    match encoding {
+        Encoding::Packed => {
+            let mut w = WireWriter::new();
+            w.u8(processes.by_cpu.len() as u8)
+                .u8(processes.by_memory.len() as u8);
+            for process in processes.by_cpu.iter().chain(&processes.by_memory) {
+                w.u32(process.pid)
+                    .u16(process.cpu)
+                    .u32((process.memory >> 20).min(u32::MAX as u64) as u32)
+                    .u8(process.name.len() as u8)
+                    .bytes(process.name.as_bytes());
+            }
+            Ok(w.into_inner())
+        }
+        Encoding::Json => serde_json::to_vec(&serde_json::json!({ "processes": processes })),
+    }
+}
+
+/// Decode a packed Processes payload; memory comes back rounded down to whole MiB
+pub fn decode_processes(payload: &[u8]) -> Result<TopProcesses, DecodeError> {
+    let mut r = WireReader::new(payload);
+    let cpu_count = r.u8()?;
+    let memory_count = r.u8()?;
+    let mut read_list = |count: u8| {
+        (0..count)
+            .map(|_| {
+                let pid = r.u32()?;
+                let cpu = r.u16()?;
+                let memory = (r.u32()? as u64) << 20;
+                let name_len = r.u8()? as usize;
+                let name = std::str::from_utf8(r.bytes(name_len)?)
+                    .map_err(|_| DecodeError::Invalid("process name is not UTF-8"))?;
+                Ok(ProcessStats {
+                    pid,
+                    name: name.to_owned(),
+                    cpu,
+                    memory,
+                })
+            })
+            .collect::<Result<Vec<_>, DecodeError>>()
+    };
+    let by_cpu = read_list(cpu_count)?;
+    let by_memory = read_list(memory_count)?;
+    Ok(TopProcesses { by_cpu, by_memory })
+}
+
+/// Outcome of the handshake: how samples are put on the wire for this connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Session {
+    /// The device never answered; send the bare encoded struct
+    Legacy {
+        encoding: Encoding,
+        fields: MetricFields,
+    },
+    /// The device speaks the framed protocol
+    Framed {
+        version: u8,
+        fields: MetricFields,
+        encoding: Encoding,
+        max_payload: u16,
+    },
+}
+
+impl Session {
+    /// Agree on fields and encoding from both sides' capabilities.
+    ///
+    /// A device that shares no encoding with the host can't decode any frame,
+    /// so it gets the legacy struct instead.
+    pub fn from_capabilities(
+        host: &Capabilities,
+        device: &Capabilities,
+        preferred: Encoding,
+    ) -> Self {
+        let fields = host.fields.intersection(device.fields);
+        let common = Encodings(host.encodings.0 & device.encodings.0);
+        let encoding = if common.supports(preferred) {
+            preferred
+        } else if common.supports(Encoding::Packed) {
+            Encoding::Packed
+        } else if common.supports(Encoding::Json) {
+            Encoding::Json
+        } else {
+            return Session::Legacy {
+                encoding: preferred,
+                fields,
+            };
+        };
+
+        Session::Framed {
+            version: host.version.min(device.version),
+            fields,
+            encoding,
+            max_payload: device.max_payload,
+        }
+    }
+
+    /// Encode one sample for this session
+    pub fn encode_sample(&self, info: &SystemInfo) -> Result<Vec<u8>, EncodeError> {
+        match *self {
+            Session::Legacy { encoding, fields } => {
+                Ok(wire::encode(&mask_fields(info, fields), encoding)?)
+            }
+            Session::Framed {
+                fields, encoding, ..
+            } => {
+                let payload = encode_metrics(info, fields, encoding)?;
+                encode_frame(MessageType::Metrics, encoding, &payload)
+            }
+        }
+    }
+
+    /// Encode everything this session sends per sample, in sending order.
+    ///
+    /// Legacy sessions get the single struct; framed ones a Metrics message
+    /// and, if negotiated, Gpus, Cores, Sensors, Disks, Network, Memory,
+    /// Power and Processes messages.
+    pub fn encode_messages(&self, info: &SystemInfo) -> Result<Vec<Vec<u8>>, EncodeError> {
+        let Session::Framed {
+            fields, encoding, ..
+        } = *self
+        else {
+            return Ok(vec![self.encode_sample(info)?]);
+        };
+
+        let mut messages = Vec::new();
+        if fields.intersection(MetricFields::IN_METRICS) != MetricFields::empty() {
+            messages.push(self.encode_sample(info)?);
+        }
+        if fields.contains(MetricFields::GPUS) {
+            let payload = encode_gpus(&info.gpus, encoding)?;
+            messages.push(encode_frame(MessageType::Gpus, encoding, &payload)?);
+        }
+        if fields.contains(MetricFields::CORES) {
+            let payload = encode_cores(&info.cores, encoding)?;
+            messages.push(encode_frame(MessageType::Cores, encoding, &payload)?);
+        }
+        if fields.contains(MetricFields::SENSORS) {
+            let payload = encode_sensors(&info.sensors, encoding)?;
+            messages.push(encode_frame(MessageType::Sensors, encoding, &payload)?);
+        }
+        if fields.contains(MetricFields::DISKS) {
+            let payload = encode_disks(&info.disks, encoding)?;
+            messages.push(encode_frame(MessageType::Disks, encoding, &payload)?);
+        }
+        if fields.contains(MetricFields::NETWORK) {
+            let payload = encode_network(&info.network, encoding)?;
+            messages.push(encode_frame(MessageType::Network, encoding, &payload)?);
+        }
+        if fields.contains(MetricFields::MEMORY) {
+            let payload = encode_memory(&info.memory, encoding)?;
+            messages.push(encode_frame(MessageType::Memory, encoding, &payload)?);
+        }
+        if fields.contains(MetricFields::POWER) {
+            let payload = encode_power(&info.power, encoding)?;
+            messages.push(encode_frame(MessageType::Power, encoding, &payload)?);
+        }
+        if fields.contains(MetricFields::PROCESSES) {
+            let payload = encode_processes(&info.processes, encoding)?;
+            messages.push(encode_frame(MessageType::Processes, encoding, &payload)?);
+        }
+        Ok(messages)
+    }
+}
+
+/// Send Hello and wait for the device's Capabilities.
+///
+/// Devices that do not reply within `timeout` (or cannot reply at all) are
+/// treated as legacy. Only a lost link is reported as an error.
+pub async fn negotiate<T: Transport + ?Sized>(
+    transport: &mut T,
+    host: &Capabilities,
+    preferred: Encoding,
+    timeout: Duration,
+) -> Result<Session, TransportError> {
+    let legacy = Session::Legacy {
+        encoding: preferred,
+        fields: host.fields,
+    };
+
+    let hello = encode_frame(MessageType::Hello, Encoding::Packed, &host.encode())
+        .expect("capabilities fit in a frame");
+    transport.send_frame(&hello).await?;
+
+    let reply = tokio::time::timeout(timeout, async {
+        loop {
+            let data = transport.recv_frame().await?;
+            if let Ok(frame) = decode_frame(&data) {
+                if frame.msg_type == MessageType::Capabilities {
+                    if let Ok(caps) = Capabilities::decode(&frame.payload) {
+                        return Ok(caps);
+                    }
+                }
+            }
+        }
+    })
+    .await;
+
+    match reply {
+        Ok(Ok(device)) => Ok(Session::from_capabilities(host, &device, preferred)),
+        Ok(Err(TransportError::Disconnected)) => Err(TransportError::Disconnected),
+        // Timed out, or the transport has no receive path
+        _ => Ok(legacy),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::ChannelTransport;
+    use crate::wire::PACKED_SYSTEM_INFO_LEN;
+    use std::net::Ipv4Addr;
+
+    fn sample() -> SystemInfo {
+        SystemInfo {
+            cpu_usage: 12,
+            ram_max: 32,
+            ram_usage: 50,
+            ram_unit: *b"GB\0\0",
+            gpu_usage: 80,
+            vram_max: 16,
+            vram_usage: 25,
+            vram_unit: *b"GB\0\0",
+            gpus: vec![
+                GpuStats::new("AMD Radeon Pro W5700X", 80, 16 << 30, 4 << 30)
+                    .with_power_mw(Some(142_500)),
+                GpuStats::new("AMD Radeon RX 6800 XT (Thunderbolt eGPU)", 3, 16 << 30, 0),
+            ],
+            cores: vec![
+                CoreStats {
+                    usage: 4,
+                    frequency: 2064,
+                    kind: Some(CoreKind::Efficiency),
+                },
+                CoreStats {
+                    usage: 97,
+                    frequency: 3228,
+                    kind: Some(CoreKind::Performance),
+                },
+            ],
+            sensors: vec![
+                Sensor {
+                    label: "k10temp Tctl".to_owned(),
+                    kind: SensorKind::Cpu,
+                    value: 542,
+                    percent: false,
+                },
+                Sensor {
+                    label: "acpitz temp1".to_owned(),
+                    kind: SensorKind::Other,
+                    value: -45,
+                    percent: false,
+                },
+                Sensor {
+                    label: "nct6798 CPU Fan (rear exhaust)".to_owned(),
+                    kind: SensorKind::Fan,
+                    value: 40000,
+                    percent: false,
+                },
+                Sensor {
+                    label: "nvidia RTX 3090 fan".to_owned(),
+                    kind: SensorKind::Fan,
+                    value: 78,
+                    percent: true,
+                },
+            ],
+            disks: vec![
+                DiskStats {
+                    io: Some(DiskIo {
+                        read_bytes_per_sec: 4 << 20,
+                        write_bytes_per_sec: 1536,
+                        read_iops: 150,
+                        write_iops: 70000,
+                    }),
+                    ..DiskStats::new("/", "nvme0n1p2", 500 << 30, 125 << 30)
+                },
+                DiskStats::new("/Volumes/Time Machine", "disk4s2", 2 << 40, 1 << 40),
+            ],
+            network: NetworkStats::new(vec![
+                InterfaceStats {
+                    name: "en0".to_owned(),
+                    rx_bytes_per_sec: 3 << 20,
+                    tx_bytes_per_sec: 200 << 10,
+                    state: LinkState::Up,
+                    ipv4: Some(Ipv4Addr::new(192, 168, 1, 42)),
+                    ipv6: Some("2a02:810d:1234:5600:10c4:4b2e:9f3a:71d0".parse().unwrap()),
+                },
+                InterfaceStats {
+                    name: "enx00e04c680123".to_owned(),
+                    state: LinkState::Down,
+                    ..InterfaceStats::default()
+                },
+            ]),
+            memory: MemoryStats {
+                total: 32 << 30,
+                used: 21 << 30,
+                available: 11 << 30,
+                free: (1 << 30) + 123,
+                cached: Some(10 << 30),
+                compressed: Some(6 << 30),
+                wired: Some(3 << 30),
+                swap_total: 2 << 30,
+                swap_used: 1 << 30,
+                pressure: Some(MemoryPressure::Warning),
+                ..MemoryStats::default()
+            },
+            power: PowerStats {
+                source: Some(PowerSource::Battery),
+                battery: Some(BatteryStats {
+                    percent: 82,
+                    state: Some(ChargeState::Discharging),
+                    minutes_remaining: Some(312),
+                    cycle_count: Some(143),
+                }),
+                power_draw_mw: Some(9120),
+                thermal_pressure: Some(ThermalPressure::Moderate),
+            },
+            processes: TopProcesses {
+                by_cpu: vec![ProcessStats {
+                    pid: 5120,
+                    name: "rustc".to_owned(),
+                    cpu: 3980,
+                    memory: 1 << 30,
+                }],
+                by_memory: vec![
+                    ProcessStats {
+                        pid: 4211,
+                        name: "com.apple.WebKit.WebContent".to_owned(),
+                        cpu: 12,
+                        memory: (3 << 30) + 5,
+                    },
+                    ProcessStats {
+                        pid: 5120,
+                        name: "rustc".to_owned(),
+                        cpu: 3980,
+                        memory: 1 << 30,
+                    },
+                ],
+            },
+        }
+    }
+
+    #[test]
+    fn test_crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn test_frame_round_trip_and_corruption() {
+        let frame = encode_frame(MessageType::Metrics, Encoding::Json, b"{}").unwrap();
+        let decoded = decode_frame(&frame).unwrap();
+        assert_eq!(decoded.msg_type, MessageType::Metrics);
+        assert_eq!(decoded.encoding, Encoding::Json);
+        assert_eq!(decoded.payload, b"{}");
+
+        let mut corrupt = frame.clone();
+        corrupt[HEADER_LEN] ^= 0xFF;
+        assert_eq!(
+            decode_frame(&corrupt),
+            Err(DecodeError::Invalid("crc mismatch"))
+        );
+        assert!(decode_frame(&frame[..4]).is_err());
+
+        let too_big = vec![0; u16::MAX as usize + 1];
+        assert!(matches!(
+            encode_frame(MessageType::Metrics, Encoding::Json, &too_big),
+            Err(EncodeError::PayloadTooLarge(65536))
+        ));
+    }
+
+    #[test]
+    fn test_hello_is_not_mistaken_for_legacy_struct() {
+        let hello = encode_frame(
+            MessageType::Hello,
+            Encoding::Packed,
+            &Capabilities::host().encode(),
+        )
+        .unwrap();
+        assert_ne!(hello.len(), PACKED_SYSTEM_INFO_LEN);
+    }
+
+    #[test]
+    fn test_metrics_subset_round_trip() {
+        let fields = MetricFields::CPU.union(MetricFields::GPU);
+        let payload = encode_metrics(&sample(), fields, Encoding::Packed).unwrap();
+        assert_eq!(payload.len(), 4 + 1 + 1);
+
+        let (decoded_fields, info) = decode_metrics(&payload).unwrap();
+        assert_eq!(decoded_fields, fields);
+        assert_eq!(info.cpu_usage, 12);
+        assert_eq!(info.gpu_usage, 80);
+        assert_eq!(info.ram_max, 0);
+
+        let json = encode_metrics(&sample(), MetricFields::RAM, Encoding::Json).unwrap();
+        let value: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["ram_max"], 32);
+        assert!(value.get("cpu_usage").is_none());
+    }
+
+    #[test]
+    fn test_metric_fields_from_str() {
+        assert_eq!(
+            "cpu+RAM".parse::<MetricFields>().unwrap(),
+            MetricFields::CPU.union(MetricFields::RAM)
+        );
+        assert_eq!("all".parse::<MetricFields>().unwrap(), MetricFields::all());
+        assert!(MetricFields::all().contains("gpus".parse().unwrap()));
+        assert!("cpu+disk".parse::<MetricFields>().is_err());
+        assert!("".parse::<MetricFields>().is_err());
+    }
+
+    #[test]
+    fn test_metrics_leaves_out_gpu_list() {
+        let payload = encode_metrics(&sample(), MetricFields::all(), Encoding::Packed).unwrap();
+        let (fields, _) = decode_metrics(&payload).unwrap();
+        assert_eq!(fields, MetricFields::LEGACY);
+        assert_eq!(payload.len(), 4 + 1 + 7 + 1 + 7);
+    }
+
+    #[test]
+    fn test_gpus_round_trip() {
+        let payload = encode_gpus(&sample().gpus, Encoding::Packed).unwrap();
+        let gpus = decode_gpus(&payload).unwrap();
+        assert_eq!(gpus[0], sample().gpus[0]);
+        assert_eq!(gpus[1].name, "AMD Radeon RX 6800 XT (T");
+        assert_eq!(gpus[1].vram_max, 16);
+        assert_eq!(gpus[1].power_mw, None);
+        assert!(decode_gpus(&payload[..payload.len() - 1]).is_err());
+
+        let json = encode_gpus(&sample().gpus, Encoding::Json).unwrap();
+        let value: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["gpus"][1]["usage"], 3);
+        assert_eq!(value["gpus"][0]["power_mw"], 142_500);
+        assert_eq!(truncate_utf8("Radeon™", 8), "Radeon");
+    }
+
+    #[test]
+    fn test_cores_round_trip() {
+        let payload = encode_cores(&sample().cores, Encoding::Packed).unwrap();
+        assert_eq!(
+            payload,
+            [2, 4 | EFFICIENCY_CORE_FLAG, 97, 0x10, 0x08, 0x9c, 0x0c]
+        );
+        assert_eq!(decode_cores(&payload).unwrap(), sample().cores);
+
+        // Without efficiency cores there is no P/E split
+        let uniform = [CoreStats {
+            usage: 50,
+            frequency: 2400,
+            kind: None,
+        }];
+        let payload = encode_cores(&uniform, Encoding::Packed).unwrap();
+        assert_eq!(decode_cores(&payload).unwrap(), uniform);
+        assert!(decode_cores(&payload[..3]).is_err());
+
+        let json = encode_cores(&sample().cores, Encoding::Json).unwrap();
+        let value: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["cores"][0]["kind"], "efficiency");
+        assert_eq!(value["cores"][1]["frequency"], 3228);
+    }
+
+    #[test]
+    fn test_sensors_round_trip() {
+        let payload = encode_sensors(&sample().sensors, Encoding::Packed).unwrap();
+        let sensors = decode_sensors(&payload).unwrap();
+        assert_eq!(sensors[..2], sample().sensors[..2]);
+        // Fan RPM uses the full u16 range; labels are cut
+        assert_eq!(sensors[2].value, 40000);
+        assert_eq!(sensors[2].label, "nct6798 CPU Fan (rea");
+        assert_eq!(sensors[3], sample().sensors[3]);
+        assert_eq!(payload[payload.len() - 23], 0x80 | SensorKind::Fan.to_u8());
+
+        let mut bad = payload.clone();
+        bad[1] = 9;
+        assert_eq!(
+            decode_sensors(&bad),
+            Err(DecodeError::Invalid("unknown sensor kind"))
+        );
+
+        let json = encode_sensors(&sample().sensors, Encoding::Json).unwrap();
+        let value: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["sensors"][0]["kind"], "cpu");
+        assert_eq!(value["sensors"][1]["value"], -45);
+    }
+
+    #[test]
+    fn test_disks_round_trip() {
+        let payload = encode_disks(&sample().disks, Encoding::Packed).unwrap();
+        let disks = decode_disks(&payload).unwrap();
+        assert_eq!((disks[0].mount.as_str(), disks[0].usage), ("/", 75));
+        assert_eq!(
+            disks[0].io,
+            Some(DiskIo {
+                read_bytes_per_sec: 4 << 20,
+                write_bytes_per_sec: 1024,
+                read_iops: 150,
+                write_iops: u16::MAX as u32,
+            })
+        );
+        assert_eq!(disks[1].mount, "/Volumes/Time Ma");
+        assert_eq!((disks[1].size, &disks[1].unit), (2, b"TB\0\0"));
+        assert_eq!(disks[1].io, None);
+        assert!(decode_disks(&payload[..payload.len() - 1]).is_err());
+
+        let json = encode_disks(&sample().disks, Encoding::Json).unwrap();
+        let value: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["disks"][0]["device"], "nvme0n1p2");
+        assert_eq!(value["disks"][0]["io"]["read_iops"], 150);
+        assert!(value["disks"][1]["io"].is_null());
+    }
+
+    #[test]
+    fn test_network_round_trip() {
+        let payload = encode_network(&sample().network, Encoding::Packed).unwrap();
+        // Totals, count, then en0 with both addresses and the down adapter with none
+        assert_eq!(payload.len(), 9 + (9 + 4 + 16 + 1 + 3) + (9 + 1 + 12));
+        let network = decode_network(&payload).unwrap();
+        assert_eq!(network.rx_bytes_per_sec, 3 << 20);
+        assert_eq!(network.interfaces[0], sample().network.interfaces[0]);
+        assert_eq!(network.interfaces[1].name, "enx00e04c680");
+        assert_eq!(network.interfaces[1].state, LinkState::Down);
+        assert_eq!(network.interfaces[1].ipv4, None);
+        assert!(decode_network(&payload[..payload.len() - 1]).is_err());
+
+        let json = encode_network(&sample().network, Encoding::Json).unwrap();
+        let value: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["network"]["tx_bytes_per_sec"], 200 << 10);
+        assert_eq!(value["network"]["interfaces"][0]["ipv4"], "192.168.1.42");
+        assert_eq!(value["network"]["interfaces"][1]["state"], "down");
+    }
+
+    #[test]
+    fn test_memory_round_trip() {
+        let payload = encode_memory(&sample().memory, Encoding::Packed).unwrap();
+        assert_eq!(payload.len(), 10 * 4 + 1);
+        // Buffers are Linux-only
+        assert_eq!(payload[20..24], [0xFF; 4]);
+        let memory = decode_memory(&payload).unwrap();
+        assert_eq!(memory.free, 1 << 30);
+        assert_eq!(
+            memory,
+            MemoryStats {
+                free: 1 << 30,
+                ..sample().memory
+            }
+        );
+        assert!(decode_memory(&payload[..40]).is_err());
+
+        let json = encode_memory(&sample().memory, Encoding::Json).unwrap();
+        let value: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["memory"]["pressure"], "warning");
+        assert!(value["memory"]["buffers"].is_null());
+    }
+
+    #[test]
+    fn test_power_round_trip() {
+        let payload = encode_power(&sample().power, Encoding::Packed).unwrap();
+        assert_eq!(
+            payload,
+            [2, 82, 1, 0x38, 0x01, 0x8F, 0x00, 0xA0, 0x23, 0x00, 0x00, 2]
+        );
+        assert_eq!(decode_power(&payload).unwrap(), sample().power);
+        assert!(decode_power(&payload[..11]).is_err());
+
+        let desktop = PowerStats {
+            source: Some(PowerSource::Ac),
+            ..PowerStats::default()
+        };
+        let payload = encode_power(&desktop, Encoding::Packed).unwrap();
+        assert_eq!(payload[1], NO_BATTERY);
+        assert_eq!(decode_power(&payload).unwrap(), desktop);
+
+        let json = encode_power(&sample().power, Encoding::Json).unwrap();
+        let value: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["power"]["battery"]["state"], "discharging");
+        assert_eq!(value["power"]["power_draw_mw"], 9120);
+        assert_eq!(value["power"]["thermal_pressure"], "moderate");
+    }
+
+    #[test]
+    fn test_processes_round_trip() {
+        let payload = encode_processes(&sample().processes, Encoding::Packed).unwrap();
+        assert_eq!(payload[..2], [1, 2]);
+        let processes = decode_processes(&payload).unwrap();
+        assert_eq!(processes.by_cpu, sample().processes.by_cpu);
+        assert_eq!(processes.by_memory[0].name, "com.apple.WebKi");
+        assert_eq!(processes.by_memory[0].memory, 3 << 30);
+        assert_eq!(processes.by_memory[1].pid, 5120);
+        assert!(decode_processes(&payload[..payload.len() - 1]).is_err());
+
+        let json = encode_processes(&sample().processes, Encoding::Json).unwrap();
+        let value: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["processes"]["by_cpu"][0]["cpu"], 3980);
+        assert_eq!(
+            value["processes"]["by_memory"][0]["name"],
+            "com.apple.WebKi"
+        );
+    }
+
+    #[test]
+    fn test_session_messages() {
+        let framed = |fields| Session::Framed {
+            version: PROTOCOL_VERSION,
+            fields,
+            encoding: Encoding::Packed,
+            max_payload: u16::MAX,
+        };
+        let types = |session: Session| -> Vec<MessageType> {
+            session
+                .encode_messages(&sample())
+                .unwrap()
+                .iter()
+                .map(|message| decode_frame(message).unwrap().msg_type)
+                .collect()
+        };
+
+        assert_eq!(
+            types(framed(MetricFields::all())),
+            [
+                MessageType::Metrics,
+                MessageType::Gpus,
+                MessageType::Cores,
+                MessageType::Sensors,
+                MessageType::Disks,
+                MessageType::Network,
+                MessageType::Memory,
+                MessageType::Power,
+                MessageType::Processes
+            ]
+        );
+        assert_eq!(types(framed(MetricFields::LEGACY)), [MessageType::Metrics]);
+        assert_eq!(types(framed(MetricFields::GPUS)), [MessageType::Gpus]);
+
+        let legacy = Session::Legacy {
+            encoding: Encoding::Packed,
+            fields: MetricFields::all(),
+        };
+        let messages = legacy.encode_messages(&sample()).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].len(), PACKED_SYSTEM_INFO_LEN);
+    }
+
+    #[test]
+    fn test_legacy_session_masks_unselected_fields() {
+        let session = Session::Legacy {
+            encoding: Encoding::Packed,
+            fields: MetricFields::CPU,
+        };
+        let info = wire::decode_packed(&session.encode_sample(&sample()).unwrap()).unwrap();
+        assert_eq!(info.cpu_usage, 12);
+        assert_eq!(info.ram_max, 0);
+        assert_eq!(info.ram_unit, [0; 4]);
+        assert_eq!(info.gpu_usage, 0);
+        assert_eq!(info.vram_unit, [0; 4]);
+    }
+
+    #[test]
+    fn test_session_picks_common_encoding() {
+        let host = Capabilities::host();
+        let device = Capabilities {
+            version: 1,
+            fields: MetricFields::CPU.union(MetricFields::RAM),
+            encodings: Encodings::PACKED,
+            max_payload: 64,
+        };
+        assert_eq!(
+            Session::from_capabilities(&host, &device, Encoding::Json),
+            Session::Framed {
+                version: 1,
+                fields: MetricFields::CPU.union(MetricFields::RAM),
+                encoding: Encoding::Packed,
+                max_payload: 64,
+            }
+        );
+
+        let no_common_encoding = Capabilities {
+            encodings: Encodings(0),
+            ..device
+        };
+        assert_eq!(
+            Session::from_capabilities(&host, &no_common_encoding, Encoding::Json),
+            Session::Legacy {
+                encoding: Encoding::Json,
+                fields: MetricFields::CPU.union(MetricFields::RAM),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_with_framed_device() {
+        let (mut transport, mut peer) = ChannelTransport::pair(64);
+        transport.connect().await.unwrap();
+
+        let device = tokio::spawn(async move {
+            let hello = decode_frame(&peer.recv().await.unwrap()).unwrap();
+            assert_eq!(hello.msg_type, MessageType::Hello);
+            let caps = Capabilities {
+                version: 1,
+                fields: MetricFields::CPU,
+                encodings: Encodings::PACKED,
+                max_payload: 128,
+            };
+            peer.send(
+                &encode_frame(MessageType::Capabilities, Encoding::Packed, &caps.encode()).unwrap(),
+            );
+            peer
+        });
+
+        let session = negotiate(
+            &mut transport,
+            &Capabilities::host(),
+            Encoding::Packed,
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+        let _peer = device.await.unwrap();
+
+        assert!(matches!(
+            session,
+            Session::Framed {
+                fields: MetricFields::CPU,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_falls_back_to_legacy() {
+        let (mut transport, mut peer) = ChannelTransport::pair(64);
+        transport.connect().await.unwrap();
+
+        let session = negotiate(
+            &mut transport,
+            &Capabilities::host(),
+            Encoding::Packed,
+            Duration::from_millis(50),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            session,
+            Session::Legacy {
+                encoding: Encoding::Packed,
+                fields: MetricFields::all(),
+            }
+        );
+
+        // The legacy app only ever sees the Hello followed by bare structs
+        assert!(peer.recv().await.is_some());
+        let bytes = session.encode_sample(&sample()).unwrap();
+        assert_eq!(bytes.len(), PACKED_SYSTEM_INFO_LEN);
+    }
+}
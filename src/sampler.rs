@@ -0,0 +1,75 @@
+// ======================== sampler.rs ========================
+// One sampling task shared by every connected Flipper
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::system_info::{Collector, SystemInfo};
+
+/// Latest sample; `None` until the first one is taken
+pub type SampleReceiver = watch::Receiver<Option<Arc<SystemInfo>>>;
+
+/// Start sampling every `interval` until all receivers are dropped
+pub fn spawn_sampler(interval: Duration) -> (SampleReceiver, JoinHandle<()>) {
+    spawn_sampler_with(Collector::new(), interval)
+}
+
+/// Like `spawn_sampler`, with a collector set up by the caller
+pub fn spawn_sampler_with(
+    mut collector: Collector,
+    interval: Duration,
+) -> (SampleReceiver, JoinHandle<()>) {
+    let (tx, rx) = watch::channel(None);
+
+    let handle = tokio::spawn(async move {
+        loop {
+            let info = SystemInfo::get_system_info(&mut collector).await;
+            if tx.send(Some(Arc::new(info))).is_err() {
+                break;
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+
+    (rx, handle)
+}
+
+/// Most recent sample, waiting for the first one if needed.
+///
+/// Returns `None` once the sampler has stopped.
+pub async fn latest_sample(samples: &mut SampleReceiver) -> Option<Arc<SystemInfo>> {
+    loop {
+        if let Some(info) = samples.borrow_and_update().clone() {
+            return Some(info);
+        }
+        samples.changed().await.ok()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sampler_publishes_to_all_receivers() {
+        let (mut first, handle) = spawn_sampler(Duration::from_millis(10));
+        let mut second = first.clone();
+
+        let a = latest_sample(&mut first).await.unwrap();
+        let b = latest_sample(&mut second).await.unwrap();
+        assert!(a.ram_max > 0 && b.ram_max > 0);
+
+        drop(first);
+        drop(second);
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_latest_sample_ends_with_sampler() {
+        let (tx, mut rx) = watch::channel(None);
+        drop(tx);
+        assert!(latest_sample(&mut rx).await.is_none());
+    }
+}
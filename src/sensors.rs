@@ -0,0 +1,412 @@
+// ======================== sensors.rs ========================
+// Temperatures and fan speeds from Linux hwmon and sysinfo components
+
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use sysinfo::Components;
+
+use crate::gpu_info_nvidia::NvidiaGpu;
+use crate::helpers::{format_patterns, parse_patterns};
+
+/// What a sensor measures; everything but `Fan` is a temperature
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum SensorKind {
+    Cpu,
+    Gpu,
+    /// NVMe and other drives
+    Storage,
+    /// Motherboard, chipset, battery and anything unrecognised
+    Other,
+    Fan,
+}
+
+impl SensorKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            SensorKind::Cpu => "cpu",
+            SensorKind::Gpu => "gpu",
+            SensorKind::Storage => "storage",
+            SensorKind::Other => "other",
+            SensorKind::Fan => "fan",
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            SensorKind::Cpu => 0,
+            SensorKind::Gpu => 1,
+            SensorKind::Storage => 2,
+            SensorKind::Other => 3,
+            SensorKind::Fan => 4,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(SensorKind::Cpu),
+            1 => Some(SensorKind::Gpu),
+            2 => Some(SensorKind::Storage),
+            3 => Some(SensorKind::Other),
+            4 => Some(SensorKind::Fan),
+            _ => None,
+        }
+    }
+}
+
+/// One labelled reading
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Sensor {
+    /// e.g. "k10temp Tctl", "nvme Composite" or "nct6798 fan2"
+    pub label: String,
+    pub kind: SensorKind,
+    /// Tenths of a degree Celsius for temperatures, RPM for fans
+    pub value: i32,
+    /// Fans only: `value` is percent of full speed, for fans that don't report RPM
+    pub percent: bool,
+}
+
+impl Sensor {
+    /// Whether `pattern` names this sensor's kind or is part of its label, ignoring case
+    pub fn matches(&self, pattern: &str) -> bool {
+        self.kind.name().eq_ignore_ascii_case(pattern)
+            || self.label.to_lowercase().contains(&pattern.to_lowercase())
+    }
+}
+
+impl fmt::Display for Sensor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            SensorKind::Fan if self.percent => write!(f, "{}: {}%", self.label, self.value),
+            SensorKind::Fan => write!(f, "{}: {} RPM", self.label, self.value),
+            _ => write!(f, "{}: {:.1}°C", self.label, self.value as f64 / 10.0),
+        }
+    }
+}
+
+/// Label fragments that identify what a temperature sensor sits on, checked in order
+const CLASSIFIERS: [(&str, SensorKind); 17] = [
+    ("k10temp", SensorKind::Cpu),
+    ("zenpower", SensorKind::Cpu),
+    ("coretemp", SensorKind::Cpu),
+    ("cpu", SensorKind::Cpu),
+    ("tctl", SensorKind::Cpu),
+    ("package id", SensorKind::Cpu),
+    ("peci", SensorKind::Cpu),
+    ("pmu tdie", SensorKind::Cpu),
+    ("amdgpu", SensorKind::Gpu),
+    ("nouveau", SensorKind::Gpu),
+    ("radeon", SensorKind::Gpu),
+    ("gpu", SensorKind::Gpu),
+    ("nvme", SensorKind::Storage),
+    ("drivetemp", SensorKind::Storage),
+    ("ssd", SensorKind::Storage),
+    ("nand", SensorKind::Storage),
+    ("disk", SensorKind::Storage),
+];
+
+/// Guess what a temperature sensor measures from its label
+pub fn classify(label: &str) -> SensorKind {
+    let label = label.to_lowercase();
+    CLASSIFIERS
+        .iter()
+        .find(|(needle, _)| label.contains(needle))
+        .map_or(SensorKind::Other, |(_, kind)| *kind)
+}
+
+/// Which sensors to report: any whose kind or label matches a pattern; empty means all
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SensorFilter(pub Vec<String>);
+
+impl SensorFilter {
+    pub fn allows(&self, sensor: &Sensor) -> bool {
+        self.0.is_empty() || self.0.iter().any(|pattern| sensor.matches(pattern))
+    }
+
+    /// The sensors this filter allows, in their original order
+    pub fn apply(&self, sensors: &[Sensor]) -> Vec<Sensor> {
+        sensors
+            .iter()
+            .filter(|sensor| self.allows(sensor))
+            .cloned()
+            .collect()
+    }
+}
+
+impl FromStr for SensorFilter {
+    type Err = String;
+
+    /// Parse a list like "cpu+fan" or "Tctl,Composite"; "all" selects everything
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_patterns(s, "sensor").map(SensorFilter)
+    }
+}
+
+impl fmt::Display for SensorFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_patterns(&self.0))
+    }
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_owned())
+}
+
+/// `hwmon3` -> 3, so hwmon10 sorts after hwmon9
+fn numeric_suffix(name: &str, prefix: &str) -> Option<u32> {
+    name.strip_prefix(prefix)?.parse().ok()
+}
+
+/// Every temperature and fan channel under `<sysfs_root>/class/hwmon`.
+///
+/// Labels are "<chip> <channel label>", or "<chip> temp<N>" / "<chip> fan<N>"
+/// for unlabelled channels, matching what sysinfo reports.
+pub fn read_hwmon(sysfs_root: &Path) -> Vec<Sensor> {
+    let Ok(entries) = fs::read_dir(sysfs_root.join("class/hwmon")) else {
+        return Vec::new();
+    };
+    let mut chips: Vec<(u32, std::path::PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            Some((numeric_suffix(&name, "hwmon")?, entry.path()))
+        })
+        .collect();
+    chips.sort();
+
+    let mut sensors = Vec::new();
+    for (_, dir) in chips {
+        let chip = read_trimmed(&dir.join("name")).unwrap_or_else(|| "hwmon".to_owned());
+        let Ok(files) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut channels: Vec<(bool, u32)> = files
+            .flatten()
+            .filter_map(|file| {
+                let name = file.file_name().into_string().ok()?;
+                let channel = name.strip_suffix("_input")?;
+                match numeric_suffix(channel, "temp") {
+                    Some(n) => Some((false, n)),
+                    None => Some((true, numeric_suffix(channel, "fan")?)),
+                }
+            })
+            .collect();
+        // Temperatures first, then fans, each by channel number
+        channels.sort();
+
+        for (fan, n) in channels {
+            let class = if fan { "fan" } else { "temp" };
+            let Some(raw) = read_trimmed(&dir.join(format!("{}{}_input", class, n)))
+                .and_then(|text| text.parse::<i64>().ok())
+            else {
+                continue;
+            };
+            let label = match read_trimmed(&dir.join(format!("{}{}_label", class, n))) {
+                Some(label) if !label.is_empty() => format!("{} {}", chip, label),
+                _ => format!("{} {}{}", chip, class, n),
+            };
+            sensors.push(if fan {
+                Sensor {
+                    label,
+                    kind: SensorKind::Fan,
+                    value: raw.clamp(0, i32::MAX as i64) as i32,
+                    percent: false,
+                }
+            } else {
+                Sensor {
+                    kind: classify(&label),
+                    label,
+                    // Millidegrees
+                    value: (raw / 100) as i32,
+                    percent: false,
+                }
+            });
+        }
+    }
+    sensors
+}
+
+/// Temperatures sysinfo knows about (SMC on macOS, hwmon/thermal zones on Linux)
+pub fn from_components(components: &Components) -> Vec<Sensor> {
+    components
+        .iter()
+        .filter(|component| component.temperature().is_finite())
+        .map(|component| Sensor {
+            label: component.label().to_owned(),
+            kind: classify(component.label()),
+            value: (component.temperature() * 10.0).round() as i32,
+            percent: false,
+        })
+        .collect()
+}
+
+/// Temperature and fan of each GPU nvidia-smi reports, which the proprietary
+/// driver doesn't expose through hwmon. nvidia-smi only gives fan speed as a
+/// percentage. Identical GPUs are told apart by index.
+pub fn from_nvidia(gpus: &[NvidiaGpu]) -> Vec<Sensor> {
+    let mut sensors = Vec::new();
+    for gpu in gpus {
+        let label = if gpus.len() > 1 {
+            format!("nvidia {} #{}", gpu.name, gpu.index)
+        } else {
+            format!("nvidia {}", gpu.name)
+        };
+        if let Some(temperature) = gpu.temperature {
+            sensors.push(Sensor {
+                label: label.clone(),
+                kind: SensorKind::Gpu,
+                value: (temperature * 10).min(i32::MAX as u64) as i32,
+                percent: false,
+            });
+        }
+        if let Some(fan) = gpu.fan_speed {
+            sensors.push(Sensor {
+                label: format!("{} fan", label),
+                kind: SensorKind::Fan,
+                value: fan.min(100) as i32,
+                percent: true,
+            });
+        }
+    }
+    sensors
+}
+
+/// `primary` followed by the sensors in `extra` whose label it doesn't already have
+pub fn merge(mut primary: Vec<Sensor>, extra: Vec<Sensor>) -> Vec<Sensor> {
+    for sensor in extra {
+        if !primary.iter().any(|s| s.label == sensor.label) {
+            primary.push(sensor);
+        }
+    }
+    primary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu_info_nvidia::parse_csv;
+
+    fn fixture_sensors() -> Vec<Sensor> {
+        read_hwmon(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/linux/sys"))
+    }
+
+    #[test]
+    fn test_read_hwmon() {
+        let sensors = fixture_sensors();
+        let shown: Vec<String> = sensors.iter().map(Sensor::to_string).collect();
+        assert_eq!(
+            shown,
+            [
+                "k10temp Tctl: 54.2°C",
+                "k10temp Tccd1: 48.0°C",
+                "amdgpu edge: 51.0°C",
+                "amdgpu junction: 58.0°C",
+                "amdgpu fan1: 1200 RPM",
+                "nvme Composite: 38.8°C",
+                "nvme Sensor 1: 38.8°C",
+                "nct6798 SYSTIN: 35.0°C",
+                "nct6798 CPU Fan: 820 RPM",
+                "nct6798 fan2: 0 RPM",
+                "nct6798 fan10: 1430 RPM",
+            ]
+        );
+        let kinds: Vec<SensorKind> = sensors.iter().map(|s| s.kind).collect();
+        assert_eq!(
+            kinds[..8],
+            [
+                SensorKind::Cpu,
+                SensorKind::Cpu,
+                SensorKind::Gpu,
+                SensorKind::Gpu,
+                SensorKind::Fan,
+                SensorKind::Storage,
+                SensorKind::Storage,
+                SensorKind::Other,
+            ]
+        );
+        assert!(read_hwmon(Path::new("/nonexistent")).is_empty());
+    }
+
+    #[test]
+    fn test_filter() {
+        let sensors = fixture_sensors();
+        let labels = |filter: &str| -> Vec<String> {
+            filter
+                .parse::<SensorFilter>()
+                .unwrap()
+                .apply(&sensors)
+                .into_iter()
+                .map(|s| s.label)
+                .collect()
+        };
+        // Labels match too: "CPU Fan" is picked up by "cpu"
+        assert_eq!(
+            labels("cpu+composite"),
+            [
+                "k10temp Tctl",
+                "k10temp Tccd1",
+                "nvme Composite",
+                "nct6798 CPU Fan"
+            ]
+        );
+        assert_eq!(labels("gpu,FAN").len(), 6);
+        assert_eq!(labels("all").len(), sensors.len());
+        assert!("+".parse::<SensorFilter>().is_err());
+        assert_eq!(
+            "cpu+fan".parse::<SensorFilter>().unwrap().to_string(),
+            "cpu+fan"
+        );
+    }
+
+    #[test]
+    fn test_classify_and_merge() {
+        assert_eq!(classify("PECI CPU"), SensorKind::Cpu);
+        assert_eq!(classify("GPU Proximity"), SensorKind::Gpu);
+        assert_eq!(classify("NAND CH0 temp"), SensorKind::Storage);
+        assert_eq!(classify("acpitz temp1"), SensorKind::Other);
+
+        let sensor = |label: &str, value| Sensor {
+            label: label.to_owned(),
+            kind: classify(label),
+            value,
+            percent: false,
+        };
+        let merged = merge(
+            vec![sensor("k10temp Tctl", 542)],
+            vec![sensor("k10temp Tctl", 540), sensor("acpitz temp1", 270)],
+        );
+        assert_eq!(
+            merged,
+            [sensor("k10temp Tctl", 542), sensor("acpitz temp1", 270)]
+        );
+    }
+
+    #[test]
+    fn test_from_nvidia() {
+        let multi = parse_csv(include_str!("../tests/fixtures/nvidia/multi_gpu.csv"));
+        let shown: Vec<String> = from_nvidia(&multi).iter().map(Sensor::to_string).collect();
+        assert_eq!(
+            shown,
+            [
+                "nvidia NVIDIA GeForce RTX 3090 #0: 52.0°C",
+                "nvidia NVIDIA GeForce RTX 3090 #0 fan: 30%",
+                "nvidia NVIDIA GeForce RTX 3090 #1: 71.0°C",
+                "nvidia NVIDIA GeForce RTX 3090 #1 fan: 78%",
+            ]
+        );
+        let filter: SensorFilter = "gpu".parse().unwrap();
+        assert_eq!(filter.apply(&from_nvidia(&multi)).len(), 2);
+
+        // The laptop GPU has no fan reading
+        let na = parse_csv(include_str!("../tests/fixtures/nvidia/na_fields.csv"));
+        let sensors = from_nvidia(&na[..1]);
+        assert_eq!(sensors.len(), 1);
+        assert_eq!(
+            sensors[0].label,
+            "nvidia NVIDIA GeForce RTX 3050 Laptop GPU"
+        );
+    }
+}
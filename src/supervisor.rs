@@ -0,0 +1,207 @@
+// ======================== supervisor.rs ========================
+// Keeps a device connection alive: reconnects with backoff after link loss
+
+use async_trait::async_trait;
+use rand::Rng;
+use std::error::Error;
+use std::time::Duration;
+
+use crate::monitor::{monitor_and_send_loop, MonitorOptions};
+use crate::sampler::SampleReceiver;
+use crate::transport::Transport;
+
+pub type ConnectError = Box<dyn Error + Send + Sync>;
+
+/// Produces a connected transport, rediscovering the device if needed
+#[async_trait]
+pub trait Connector: Send {
+    type Transport: Transport;
+
+    async fn connect(&mut self) -> Result<Self::Transport, ConnectError>;
+}
+
+/// Capped exponential backoff with proportional jitter
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub multiplier: f64,
+    /// Fraction of each delay (0.0 - 1.0) that is randomized away
+    pub jitter: f64,
+    attempt: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(Duration::from_secs(1), Duration::from_secs(60))
+    }
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max,
+            multiplier: 2.0,
+            jitter: 0.25,
+            attempt: 0,
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Delay before the next attempt, growing until `max`
+    pub fn next_delay(&mut self) -> Duration {
+        let exp = self.multiplier.powi(self.attempt.min(32) as i32);
+        let base = (self.initial.as_secs_f64() * exp).min(self.max.as_secs_f64());
+        self.attempt = self.attempt.saturating_add(1);
+
+        let spread = base * self.jitter;
+        let delay = if spread > 0.0 {
+            base - rand::thread_rng().gen_range(0.0..spread)
+        } else {
+            base
+        };
+        Duration::from_secs_f64(delay)
+    }
+
+    /// Start over after a successful connection
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SupervisorOptions {
+    pub backoff: Backoff,
+    /// Give up after this many consecutive failed connection attempts (`None` = never)
+    pub max_attempts: Option<u32>,
+}
+
+/// Connect, stream until the link drops, then reconnect, forever.
+///
+/// Only returns when `max_attempts` consecutive connection attempts have
+/// failed, or when the sampler stops.
+pub async fn supervise<C: Connector>(
+    connector: &mut C,
+    samples: &mut SampleReceiver,
+    monitor: &MonitorOptions,
+    options: &SupervisorOptions,
+) -> Result<(), ConnectError> {
+    let mut backoff = options.backoff.clone();
+    let mut failures = 0;
+
+    loop {
+        match connector.connect().await {
+            Ok(mut transport) => {
+                backoff.reset();
+                failures = 0;
+                println!("✓ Connected!\n");
+
+                let result = monitor_and_send_loop(&mut transport, samples, monitor).await;
+                let _ = transport.disconnect().await;
+                match result {
+                    Ok(()) => return Ok(()),
+                    Err(e) => println!("⚠️  Monitoring stopped: {}", e),
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                println!("⚠️  Connection attempt {} failed: {}", failures, e);
+                if options.max_attempts.is_some_and(|max| failures >= max) {
+                    return Err(e);
+                }
+            }
+        }
+
+        let delay = backoff.next_delay();
+        println!("🔄 Reconnecting in {:.1}s...\n", delay.as_secs_f64());
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::spawn_sampler;
+    use crate::transport::{ChannelPeer, ChannelTransport};
+    use std::collections::VecDeque;
+
+    struct QueueConnector {
+        attempts: VecDeque<Option<ChannelTransport>>,
+    }
+
+    #[async_trait]
+    impl Connector for QueueConnector {
+        type Transport = ChannelTransport;
+
+        async fn connect(&mut self) -> Result<ChannelTransport, ConnectError> {
+            match self.attempts.pop_front().flatten() {
+                Some(mut transport) => {
+                    transport.connect().await?;
+                    Ok(transport)
+                }
+                None => Err("no flipper in range".into()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_backoff_grows_and_caps() {
+        let mut backoff =
+            Backoff::new(Duration::from_millis(100), Duration::from_millis(500)).with_jitter(0.0);
+        let delays: Vec<_> = (0..5).map(|_| backoff.next_delay().as_millis()).collect();
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay().as_millis(), 100);
+    }
+
+    #[test]
+    fn test_backoff_jitter_stays_in_range() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(1));
+        for _ in 0..100 {
+            let delay = backoff.next_delay();
+            assert!(delay <= Duration::from_secs(1));
+            assert!(delay >= Duration::from_millis(750));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_reconnects_and_resumes() {
+        let (first, mut first_peer) = ChannelTransport::pair(64);
+        let (second, mut second_peer) = ChannelTransport::pair(64);
+        let mut connector = QueueConnector {
+            attempts: VecDeque::from([None, Some(first), None, Some(second)]),
+        };
+
+        let monitor = MonitorOptions {
+            interval: Duration::from_millis(10),
+            handshake_timeout: Duration::from_millis(20),
+            ..MonitorOptions::default()
+        };
+        let options = SupervisorOptions {
+            backoff: Backoff::new(Duration::from_millis(1), Duration::from_millis(5)),
+            max_attempts: Some(3),
+        };
+        let host = tokio::spawn(async move {
+            let (mut samples, _) = spawn_sampler(Duration::from_millis(10));
+            supervise(&mut connector, &mut samples, &monitor, &options).await
+        });
+
+        async fn stream_then_drop(peer: &mut ChannelPeer) {
+            // Hello plus one sample, then the Flipper walks out of range
+            assert!(peer.recv().await.is_some());
+            assert!(peer.recv().await.is_some());
+            peer.set_link(false);
+        }
+        stream_then_drop(&mut first_peer).await;
+        stream_then_drop(&mut second_peer).await;
+
+        // Queue exhausted: three consecutive failures end supervision
+        assert!(host.await.unwrap().is_err());
+    }
+}
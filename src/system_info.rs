@@ -2,21 +2,217 @@
 
 use crate::helpers::pop_4u8;
 use serde::Serialize;
-use sysinfo::{System, MemoryRefreshKind};
+use std::fmt;
+use std::str::FromStr;
+use sysinfo::{Components, Disks, MemoryRefreshKind, System};
 
+use crate::command::SystemRunner;
+use crate::cpu_topology::CoreKind;
+#[cfg(target_os = "linux")]
+use crate::disks::DiskIoProbe;
+use crate::disks::{self, DiskFilter, DiskStats};
+#[cfg(target_os = "linux")]
+use crate::gpu_info_linux::LinuxGpuProbe;
+#[cfg(target_os = "linux")]
+use crate::gpu_info_linux::DEFAULT_SYSFS_ROOT;
 #[cfg(target_os = "macos")]
 use crate::gpu_info_macos::GpuInfo;
+#[cfg(not(target_os = "macos"))]
+use crate::gpu_info_nvidia::NvidiaProbe;
+#[cfg(target_os = "linux")]
+use crate::memory::DEFAULT_MEMINFO_PATH;
+use crate::memory::{MemoryStats, RamUsed};
+use crate::network::{InterfaceFilter, NetworkProbe, NetworkStats};
+use crate::power::PowerStats;
+#[cfg(target_os = "macos")]
+use crate::powermetrics::PowerReceiver;
+use crate::processes::{self, TopProcesses, MAX_PROCESSES};
+use crate::sensors::{self, Sensor, SensorFilter};
+
+/// State kept between samples (CPU deltas, GPU busy-time snapshots)
+pub struct Collector {
+    system: System,
+    /// P/E kind of each logical CPU, read on the first sample; empty if not hybrid
+    core_kinds: Option<Vec<CoreKind>>,
+    components: Components,
+    disks: Disks,
+    network: NetworkProbe,
+    #[cfg(target_os = "linux")]
+    disk_io: DiskIoProbe,
+    #[cfg(target_os = "linux")]
+    linux_gpu: LinuxGpuProbe,
+    #[cfg(not(target_os = "macos"))]
+    nvidia: NvidiaProbe,
+    #[cfg(target_os = "macos")]
+    power: Option<PowerReceiver>,
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Collector::new()
+    }
+}
+
+impl Collector {
+    pub fn new() -> Self {
+        Collector {
+            system: System::new_all(),
+            core_kinds: None,
+            components: Components::new_with_refreshed_list(),
+            disks: Disks::new_with_refreshed_list(),
+            network: NetworkProbe::new(),
+            #[cfg(target_os = "linux")]
+            disk_io: DiskIoProbe::default(),
+            #[cfg(target_os = "linux")]
+            linux_gpu: LinuxGpuProbe::default(),
+            #[cfg(not(target_os = "macos"))]
+            nvidia: NvidiaProbe::new(Box::new(SystemRunner)),
+            #[cfg(target_os = "macos")]
+            power: None,
+        }
+    }
+
+    /// Take GPU usage, GPU power and thermal pressure from a running powermetrics
+    #[cfg(target_os = "macos")]
+    pub fn with_power(mut self, power: PowerReceiver) -> Self {
+        self.power = Some(power);
+        self
+    }
+}
+
+/// One GPU, scaled the same way as the legacy gpu/vram fields
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct GpuStats {
+    /// e.g. "Apple M2 Pro", "NVIDIA GeForce RTX 3080" or "amdgpu (card1)"
+    pub name: String,
+    pub usage: u8,
+    pub vram_max: u16,
+    pub vram_usage: u8,
+    pub vram_unit: [u8; 4],
+    /// Board power in milliwatts, where the driver reports it
+    pub power_mw: Option<u32>,
+}
+
+impl GpuStats {
+    /// Scale raw numbers (percent, bytes) into the wire fields
+    pub fn new(name: impl Into<String>, usage: u64, vram_total: u64, vram_used: u64) -> Self {
+        let vram_exp = SystemInfo::get_exp(vram_total, 1024);
+        let vram_divisor = u64::pow(1024, vram_exp);
+
+        let vram_usage = if vram_total > 0 {
+            ((vram_used as f64 / vram_total as f64) * 100.0) as u8
+        } else {
+            0
+        };
+
+        GpuStats {
+            name: name.into(),
+            usage: usage.min(100) as u8,
+            vram_max: (vram_total / vram_divisor) as u16,
+            vram_usage,
+            vram_unit: pop_4u8(SystemInfo::get_unit(vram_exp).as_bytes()),
+            power_mw: None,
+        }
+    }
+
+    pub fn with_power_mw(mut self, power_mw: Option<u32>) -> Self {
+        self.power_mw = power_mw;
+        self
+    }
+}
+
+/// One logical CPU
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoreStats {
+    pub usage: u8,
+    /// Current clock in MHz
+    pub frequency: u16,
+    /// `None` unless the CPU mixes performance and efficiency cores
+    pub kind: Option<CoreKind>,
+}
 
-#[derive(Serialize, Debug, Clone)]
+/// Which GPU fills the single-GPU fields of the legacy layout
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum GpuSelector {
+    /// Highest usage; the first listed wins a tie
+    #[default]
+    Busiest,
+    /// Position in `SystemInfo::gpus`
+    Index(usize),
+    /// First GPU whose name contains this, ignoring case
+    Name(String),
+}
+
+impl FromStr for GpuSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("GPU selector must not be empty".to_owned());
+        }
+        if s.eq_ignore_ascii_case("busiest") {
+            return Ok(GpuSelector::Busiest);
+        }
+        Ok(match s.parse::<usize>() {
+            Ok(index) => GpuSelector::Index(index),
+            Err(_) => GpuSelector::Name(s.to_owned()),
+        })
+    }
+}
+
+impl fmt::Display for GpuSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuSelector::Busiest => write!(f, "busiest"),
+            GpuSelector::Index(index) => write!(f, "index {}", index),
+            GpuSelector::Name(name) => write!(f, "name '{}'", name),
+        }
+    }
+}
+
+impl GpuSelector {
+    /// The selected GPU, if it exists
+    pub fn select<'a>(&self, gpus: &'a [GpuStats]) -> Option<&'a GpuStats> {
+        match self {
+            GpuSelector::Busiest => gpus.iter().rev().max_by_key(|gpu| gpu.usage),
+            GpuSelector::Index(index) => gpus.get(*index),
+            GpuSelector::Name(name) => {
+                let name = name.to_lowercase();
+                gpus.iter()
+                    .find(|gpu| gpu.name.to_lowercase().contains(&name))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
 pub struct SystemInfo {
     pub cpu_usage: u8,
     pub ram_max: u16,
     pub ram_usage: u8,
     pub ram_unit: [u8; 4],
+    /// The selected GPU (see `GpuSelector`), for the single-GPU layouts
     pub gpu_usage: u8,
     pub vram_max: u16,
     pub vram_usage: u8,
     pub vram_unit: [u8; 4],
+    /// Every GPU found, in a stable order
+    pub gpus: Vec<GpuStats>,
+    /// Every logical CPU, in OS numbering
+    pub cores: Vec<CoreStats>,
+    /// Temperatures and fans, in a stable order
+    pub sensors: Vec<Sensor>,
+    /// Every mounted filesystem
+    pub disks: Vec<DiskStats>,
+    /// Every interface sysinfo lists, and totals over them
+    pub network: NetworkStats,
+    /// Breakdown behind the RAM fields, plus swap and pressure
+    pub memory: MemoryStats,
+    /// Power source and battery, on laptops
+    pub power: PowerStats,
+    /// Busiest processes by CPU and by memory
+    pub processes: TopProcesses,
 }
 
 impl SystemInfo {
@@ -42,79 +238,380 @@ impl SystemInfo {
         }
     }
 
-    pub async fn get_system_info(system: &mut System) -> Self {
+    /// Scale a byte count into a size and unit, as for the RAM fields
+    pub fn scale(bytes: u64) -> (u16, [u8; 4]) {
+        let exp = Self::get_exp(bytes, 1024);
+        let size = (bytes / u64::pow(1024, exp)) as u16;
+        (size, pop_4u8(Self::get_unit(exp).as_bytes()))
+    }
+
+    pub async fn get_system_info(collector: &mut Collector) -> Self {
+        let system = &mut collector.system;
+
         // Refresh system information
         system.refresh_cpu();
         system.refresh_memory_specifics(MemoryRefreshKind::everything());
-        
+
         // Give CPU time to calculate usage
         tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
         system.refresh_cpu();
+        // Per-process CPU covers the time since the previous sample
+        system.refresh_processes();
 
         // Get CPU usage
         let cpu_usage = system.global_cpu_info().cpu_usage() as u8;
+        let mut cores: Vec<CoreStats> = system
+            .cpus()
+            .iter()
+            .map(|cpu| CoreStats {
+                usage: cpu.cpu_usage().round().min(100.0) as u8,
+                frequency: cpu.frequency().min(u16::MAX as u64) as u16,
+                kind: None,
+            })
+            .collect();
 
         // Get RAM information
-        let ram_total = system.total_memory();
-        let ram_used = system.used_memory();
-        let ram_exp = Self::get_exp(ram_total, 1024);
-        let ram_divisor = u64::pow(1024, ram_exp);
-        
-        let ram_max = (ram_total / ram_divisor) as u16;
-        let ram_usage = if ram_total > 0 {
-            ((ram_used as f64 / ram_total as f64) * 100.0) as u8
-        } else {
-            0
-        };
-        let ram_unit = pop_4u8(Self::get_unit(ram_exp).as_bytes());
+        let memory = Self::get_memory(collector).await;
+        let (ram_max, ram_unit) = Self::scale(memory.total);
+        let ram_usage = memory.usage();
+
+        if collector.core_kinds.is_none() {
+            let kinds = Self::get_core_kinds(cores.len()).await;
+            collector.core_kinds = Some(kinds.unwrap_or_default());
+        }
+        if let Some(kinds) = &collector.core_kinds {
+            for (core, kind) in cores.iter_mut().zip(kinds) {
+                core.kind = Some(*kind);
+            }
+        }
 
         // Get GPU information (platform-specific)
-        let (gpu_usage, vram_max, vram_usage, vram_unit) = Self::get_gpu_stats().await;
+        let gpus = Self::get_gpus(collector).await;
+        let sensors = Self::get_sensors(collector);
+        let disks = Self::get_disks(collector);
+        let network = Self::get_network(collector).await;
+        let power = Self::get_power(collector).await;
+        let processes =
+            TopProcesses::new(&processes::from_system(&collector.system), MAX_PROCESSES);
 
         SystemInfo {
             cpu_usage,
             ram_max,
             ram_usage,
             ram_unit,
-            gpu_usage,
-            vram_max,
-            vram_usage,
-            vram_unit,
+            gpus,
+            cores,
+            sensors,
+            disks,
+            network,
+            memory,
+            power,
+            processes,
+            ..SystemInfo::default()
+        }
+        .with_gpu(&GpuSelector::Busiest)
+    }
+
+    /// Keep the `count` busiest and largest processes
+    pub fn with_processes(mut self, count: usize) -> Self {
+        self.processes.truncate(count);
+        self
+    }
+
+    /// Keep only the sensors `filter` allows
+    pub fn with_sensors(mut self, filter: &SensorFilter) -> Self {
+        self.sensors = filter.apply(&self.sensors);
+        self
+    }
+
+    /// Keep only the disks `filter` allows
+    pub fn with_disks(mut self, filter: &DiskFilter) -> Self {
+        self.disks = filter.apply(&self.disks);
+        self
+    }
+
+    /// Count RAM as used by `definition` in `ram_usage` and `memory.used`
+    pub fn with_ram_used(mut self, definition: RamUsed) -> Self {
+        self.memory = self.memory.with_used(definition);
+        self.ram_usage = self.memory.usage();
+        self
+    }
+
+    /// Keep only the interfaces `filter` allows, with totals over those
+    pub fn with_network(mut self, filter: &InterfaceFilter) -> Self {
+        self.network = filter.apply(&self.network);
+        self
+    }
+
+    /// Fill the single-GPU fields from the selected GPU, or zeros if there is none
+    pub fn with_gpu(mut self, selector: &GpuSelector) -> Self {
+        let gpu = selector.select(&self.gpus).cloned().unwrap_or(GpuStats {
+            vram_unit: pop_4u8(b"GB"),
+            ..GpuStats::default()
+        });
+        self.gpu_usage = gpu.usage;
+        self.vram_max = gpu.vram_max;
+        self.vram_usage = gpu.vram_usage;
+        self.vram_unit = gpu.vram_unit;
+        self
+    }
+
+    /// hwmon covers fans too, so it comes first on Linux; sysinfo adds the rest.
+    /// NVIDIA GPUs come from the nvidia-smi run `get_gpus` just made.
+    fn get_sensors(collector: &mut Collector) -> Vec<Sensor> {
+        collector.components.refresh();
+        #[allow(unused_mut)]
+        let mut list = sensors::from_components(&collector.components);
+        #[cfg(target_os = "linux")]
+        {
+            list = sensors::merge(sensors::read_hwmon(DEFAULT_SYSFS_ROOT.as_ref()), list);
         }
+        #[cfg(not(target_os = "macos"))]
+        list.extend(sensors::from_nvidia(collector.nvidia.latest()));
+        list
     }
 
+    /// Capacity from sysinfo; I/O rates only where diskstats exists (Linux)
+    fn get_disks(collector: &mut Collector) -> Vec<DiskStats> {
+        // Picks up mounts and unmounts, and refreshes free space
+        collector.disks.refresh_list();
+        #[allow(unused_mut)]
+        let mut list = disks::from_disks(&collector.disks);
+        #[cfg(target_os = "linux")]
+        disks::attach_io(&mut list, &collector.disk_io.sample());
+        list
+    }
+
+    /// /proc/meminfo has buffers, caches and zswap that sysinfo leaves out
+    #[cfg(target_os = "linux")]
+    async fn get_memory(collector: &mut Collector) -> MemoryStats {
+        let meminfo = crate::memory::read_meminfo(DEFAULT_MEMINFO_PATH.as_ref());
+        meminfo.unwrap_or_else(|| MemoryStats::from_system(&collector.system))
+    }
+
+    /// vm_stat splits out cached and compressed pages, which sysinfo counts as used
     #[cfg(target_os = "macos")]
-    async fn get_gpu_stats() -> (u8, u16, u8, [u8; 4]) {
-        if let Some(gpu_info) = GpuInfo::get_gpu_info().await {
-            let vram_exp = Self::get_exp(gpu_info.vram_max, 1024);
-            let vram_divisor = u64::pow(1024, vram_exp);
-            
-            let vram_max = if vram_divisor > 0 {
-                (gpu_info.vram_max / vram_divisor) as u16
-            } else {
-                0
-            };
-            
-            let vram_usage = if gpu_info.vram_max > 0 {
-                ((gpu_info.vram_used as f64 / gpu_info.vram_max as f64) * 100.0) as u8
-            } else {
-                0
-            };
-            
-            let vram_unit = pop_4u8(Self::get_unit(vram_exp).as_bytes());
-            let gpu_usage = gpu_info.gpu_usage as u8;
+    async fn get_memory(collector: &mut Collector) -> MemoryStats {
+        let base = MemoryStats::from_system(&collector.system);
+        crate::memory::macos_memory(&SystemRunner, base).await
+    }
 
-            (gpu_usage, vram_max, vram_usage, vram_unit)
-        } else {
-            // Fallback values if GPU info unavailable
-            (0, 0, 0, pop_4u8(b"GB"))
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    async fn get_memory(collector: &mut Collector) -> MemoryStats {
+        MemoryStats::from_system(&collector.system)
+    }
+
+    #[cfg(target_os = "macos")]
+    async fn get_network(collector: &mut Collector) -> NetworkStats {
+        let details = crate::network::macos_details(&SystemRunner).await;
+        collector.network.sample(&details)
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn get_network(collector: &mut Collector) -> NetworkStats {
+        let details =
+            crate::network::linux_details(&SystemRunner, DEFAULT_SYSFS_ROOT.as_ref()).await;
+        collector.network.sample(&details)
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    async fn get_network(collector: &mut Collector) -> NetworkStats {
+        collector.network.sample(&Default::default())
+    }
+
+    /// The battery gauge measures the whole system where it can; otherwise
+    /// powermetrics' CPU, GPU and ANE power is the best estimate
+    #[cfg(target_os = "macos")]
+    async fn get_power(collector: &Collector) -> PowerStats {
+        let mut power = crate::power::macos_power(&SystemRunner).await;
+        let sample = collector
+            .power
+            .as_ref()
+            .and_then(|power| power.borrow().clone());
+        if let Some(sample) = sample {
+            power.power_draw_mw = power.power_draw_mw.or_else(|| sample.package_power_mw());
+            power.thermal_pressure = sample.thermal_pressure;
         }
+        power
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn get_power(_collector: &Collector) -> PowerStats {
+        crate::power::read_power_supply(DEFAULT_SYSFS_ROOT.as_ref())
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    async fn get_power(_collector: &Collector) -> PowerStats {
+        PowerStats::default()
+    }
+
+    #[cfg(target_os = "macos")]
+    async fn get_core_kinds(_count: usize) -> Option<Vec<CoreKind>> {
+        crate::cpu_topology::macos_core_kinds(&SystemRunner).await
     }
 
+    #[cfg(target_os = "linux")]
+    async fn get_core_kinds(count: usize) -> Option<Vec<CoreKind>> {
+        crate::cpu_topology::linux_core_kinds(DEFAULT_SYSFS_ROOT.as_ref(), count)
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    async fn get_core_kinds(_count: usize) -> Option<Vec<CoreKind>> {
+        None
+    }
+
+    #[cfg(target_os = "macos")]
+    async fn get_gpus(collector: &mut Collector) -> Vec<GpuStats> {
+        let mut gpus = GpuInfo::get_all_gpu_info(&SystemRunner).await;
+        // Discrete GPUs (the most VRAM) first, so they win a tie for busiest
+        gpus.sort_by_key(|gpu| std::cmp::Reverse(gpu.vram_max));
+
+        // powermetrics reports a single GPU, so only trust it for a single GPU
+        let sample = collector
+            .power
+            .as_ref()
+            .and_then(|power| power.borrow().clone())
+            .filter(|_| gpus.len() == 1);
+        if let (Some(residency), [gpu]) = (
+            sample.as_ref().and_then(|sample| sample.gpu_residency),
+            gpus.as_mut_slice(),
+        ) {
+            gpu.gpu_usage = residency.round() as u64;
+        }
+        let gpu_power = sample
+            .and_then(|sample| sample.gpu_power)
+            .map(|mw| mw.round() as u32);
+
+        gpus.into_iter()
+            .map(|gpu| {
+                GpuStats::new(gpu.model, gpu.gpu_usage, gpu.vram_max, gpu.vram_used)
+                    .with_power_mw(gpu_power)
+            })
+            .collect()
+    }
+
+    /// Every GPU nvidia-smi reports, in its index order
     #[cfg(not(target_os = "macos"))]
-    async fn get_gpu_stats() -> (u8, u16, u8, [u8; 4]) {
-        // Placeholder for other platforms (Windows/Linux)
-        // TODO: Implement Windows NVML/nvidia-smi parsing
-        (0, 0, 0, pop_4u8(b"GB"))
+    async fn nvidia_gpus(collector: &mut Collector) -> Vec<GpuStats> {
+        collector
+            .nvidia
+            .sample()
+            .await
+            .into_iter()
+            .map(|gpu| {
+                GpuStats::new(
+                    gpu.name,
+                    gpu.utilization.unwrap_or(0),
+                    gpu.memory_total.unwrap_or(0),
+                    gpu.memory_used.unwrap_or(0),
+                )
+                .with_power_mw(gpu.power_draw.map(|watts| (watts * 1000.0).round() as u32))
+            })
+            .collect()
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn get_gpus(collector: &mut Collector) -> Vec<GpuStats> {
+        let mut gpus = Self::nvidia_gpus(collector).await;
+
+        // The proprietary NVIDIA driver exposes nothing useful in DRM sysfs
+        let mut cards: Vec<_> = collector
+            .linux_gpu
+            .sample()
+            .into_iter()
+            .filter(|gpu| gpu.card.driver.as_deref() != Some("nvidia"))
+            .collect();
+        // Discrete GPUs (the most dedicated memory) first
+        cards.sort_by_key(|gpu| std::cmp::Reverse(gpu.card.vram_total.unwrap_or(0)));
+
+        gpus.extend(cards.into_iter().map(|gpu| {
+            let name = match &gpu.card.driver {
+                Some(driver) => format!("{} ({})", driver, gpu.card.name),
+                None => gpu.card.name.clone(),
+            };
+            GpuStats::new(
+                name,
+                gpu.busy_percent.unwrap_or(0.0).round() as u64,
+                gpu.card.vram_total.unwrap_or(0),
+                gpu.card.vram_used.unwrap_or(0),
+            )
+        }));
+        gpus
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    async fn get_gpus(collector: &mut Collector) -> Vec<GpuStats> {
+        // Other platforms (Windows): NVIDIA only for now
+        Self::nvidia_gpus(collector).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gpus() -> Vec<GpuStats> {
+        vec![
+            GpuStats::new("Intel UHD Graphics 630", 30, 1536 << 20, 0),
+            GpuStats::new("AMD Radeon Pro 5500M", 64, 8 << 30, 1 << 30),
+            GpuStats::new("AMD Radeon RX 6800 XT", 64, 16 << 30, 12 << 30),
+        ]
+    }
+
+    #[test]
+    fn test_gpu_stats_scaling() {
+        let gpu = GpuStats::new("eGPU", 140, 16 << 30, 12 << 30);
+        assert_eq!(gpu.usage, 100);
+        assert_eq!(gpu.vram_max, 16);
+        assert_eq!(gpu.vram_usage, 75);
+        assert_eq!(&gpu.vram_unit, b"GB\0\0");
+        assert_eq!(GpuStats::new("iGPU", 0, 512 << 20, 0).vram_max, 512);
+        assert_eq!(&GpuStats::new("iGPU", 0, 512 << 20, 0).vram_unit, b"MB\0\0");
+    }
+
+    #[test]
+    fn test_gpu_selector_from_str() {
+        assert_eq!("Busiest".parse(), Ok(GpuSelector::Busiest));
+        assert_eq!("1".parse(), Ok(GpuSelector::Index(1)));
+        assert_eq!(
+            " radeon ".parse(),
+            Ok(GpuSelector::Name("radeon".to_owned()))
+        );
+        assert!("".parse::<GpuSelector>().is_err());
+    }
+
+    #[test]
+    fn test_gpu_selector_select() {
+        let gpus = gpus();
+        let name = |selector: GpuSelector| selector.select(&gpus).map(|gpu| gpu.name.as_str());
+
+        // A tie goes to the first listed
+        assert_eq!(name(GpuSelector::Busiest), Some("AMD Radeon Pro 5500M"));
+        assert_eq!(name(GpuSelector::Index(0)), Some("Intel UHD Graphics 630"));
+        assert_eq!(name(GpuSelector::Index(3)), None);
+        assert_eq!(
+            name(GpuSelector::Name("6800".to_owned())),
+            Some("AMD Radeon RX 6800 XT")
+        );
+        assert_eq!(name(GpuSelector::Name("nvidia".to_owned())), None);
+    }
+
+    #[test]
+    fn test_with_gpu_fills_legacy_fields() {
+        let info = SystemInfo {
+            gpus: gpus(),
+            ..SystemInfo::default()
+        };
+
+        let egpu = info.clone().with_gpu(&GpuSelector::Index(2));
+        assert_eq!(
+            (egpu.gpu_usage, egpu.vram_max, egpu.vram_usage),
+            (64, 16, 75)
+        );
+        assert_eq!(egpu.gpus.len(), 3);
+
+        let missing = info.with_gpu(&GpuSelector::Name("nvidia".to_owned()));
+        assert_eq!((missing.gpu_usage, missing.vram_max), (0, 0));
+        assert_eq!(&missing.vram_unit, b"GB\0\0");
     }
 }
@@ -0,0 +1,213 @@
+// ======================== system_profiler.rs ========================
+// GPUs and their displays from `system_profiler -json SPDisplaysDataType`
+
+use serde::Deserialize;
+
+use crate::command::CommandError;
+
+pub const DISPLAYS_ARGS: [&str; 2] = ["-json", "SPDisplaysDataType"];
+
+/// One graphics processor as System Information lists it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GpuRecord {
+    pub model: String,
+    /// e.g. "Intel", "AMD", "Apple", "NVIDIA"
+    pub vendor: Option<String>,
+    /// Dedicated VRAM in bytes, or the dynamic maximum for integrated GPUs
+    pub vram: Option<u64>,
+    /// Whether the VRAM is carved out of system memory
+    pub vram_shared: bool,
+    /// e.g. "Metal 3"
+    pub metal: Option<String>,
+    /// Apple Silicon only
+    pub cores: Option<u32>,
+    pub displays: Vec<AttachedDisplay>,
+}
+
+/// A display connected to a GPU
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AttachedDisplay {
+    pub name: String,
+    /// Native pixels, e.g. "3072 x 1920"
+    pub pixels: Option<String>,
+    pub main: bool,
+    pub internal: bool,
+}
+
+#[derive(Deserialize)]
+struct Report {
+    #[serde(rename = "SPDisplaysDataType", default)]
+    gpus: Vec<RawGpu>,
+}
+
+#[derive(Deserialize)]
+struct RawGpu {
+    #[serde(rename = "_name")]
+    name: String,
+    sppci_model: Option<String>,
+    spdisplays_vendor: Option<String>,
+    spdisplays_vram: Option<String>,
+    spdisplays_vram_shared: Option<String>,
+    #[serde(rename = "spdisplays_mtlgpufamilysupport")]
+    metal_family: Option<String>,
+    spdisplays_metal: Option<String>,
+    sppci_cores: Option<String>,
+    #[serde(rename = "spdisplays_ndrvs", default)]
+    displays: Vec<RawDisplay>,
+}
+
+#[derive(Deserialize)]
+struct RawDisplay {
+    #[serde(rename = "_name")]
+    name: String,
+    #[serde(rename = "_spdisplays_pixels")]
+    pixels: Option<String>,
+    spdisplays_main: Option<String>,
+    spdisplays_connection_type: Option<String>,
+}
+
+/// Parse a size like "8 GB" or "1536 MB" into bytes
+fn parse_size(text: &str) -> Option<u64> {
+    let mut words = text.split_whitespace();
+    let value = words.next()?.parse::<u64>().ok()?;
+    let exp = match words.next()? {
+        "KB" => 1,
+        "MB" => 2,
+        "GB" => 3,
+        "TB" => 4,
+        _ => return None,
+    };
+    Some(value * 1024u64.pow(exp))
+}
+
+/// "sppci_vendor_amd" -> "AMD", "NVIDIA (0x10de)" -> "NVIDIA"
+fn vendor_name(raw: &str) -> String {
+    let name = raw.strip_prefix("sppci_vendor_").unwrap_or(raw);
+    let name = name.split(" (").next().unwrap_or(name);
+    match name.to_ascii_lowercase().as_str() {
+        "amd" | "ati" => "AMD".to_owned(),
+        "nvidia" => "NVIDIA".to_owned(),
+        "intel" => "Intel".to_owned(),
+        "apple" => "Apple".to_owned(),
+        _ => name.to_owned(),
+    }
+}
+
+/// "spdisplays_metal3" -> "Metal 3"
+fn metal_name(raw: &str) -> String {
+    let name = raw.strip_prefix("spdisplays_").unwrap_or(raw);
+    match name.strip_prefix("metal") {
+        Some(version) if !version.is_empty() => format!("Metal {}", version),
+        _ => name.to_owned(),
+    }
+}
+
+impl From<RawGpu> for GpuRecord {
+    fn from(raw: RawGpu) -> Self {
+        let shared = raw.spdisplays_vram_shared.as_deref().and_then(parse_size);
+        GpuRecord {
+            model: raw.sppci_model.unwrap_or(raw.name),
+            vendor: raw.spdisplays_vendor.as_deref().map(vendor_name),
+            vram: raw
+                .spdisplays_vram
+                .as_deref()
+                .and_then(parse_size)
+                .or(shared),
+            vram_shared: raw.spdisplays_vram.is_none() && shared.is_some(),
+            metal: raw
+                .metal_family
+                .or(raw.spdisplays_metal)
+                .as_deref()
+                .map(metal_name),
+            cores: raw.sppci_cores.and_then(|cores| cores.parse().ok()),
+            displays: raw
+                .displays
+                .into_iter()
+                .map(|display| AttachedDisplay {
+                    name: display.name,
+                    pixels: display.pixels,
+                    main: display.spdisplays_main.as_deref() == Some("spdisplays_yes"),
+                    internal: display.spdisplays_connection_type.as_deref()
+                        == Some("spdisplays_internal"),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Parse `system_profiler -json SPDisplaysDataType` output, one record per GPU
+pub fn parse_displays(output: &str) -> Result<Vec<GpuRecord>, CommandError> {
+    let report: Report = serde_json::from_str(output)
+        .map_err(|e| CommandError::unparseable("system_profiler", e.to_string()))?;
+    Ok(report.gpus.into_iter().map(GpuRecord::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    const INTEL: &str = include_str!("../tests/fixtures/macos/intel/system_profiler_displays.json");
+    const MAC_PRO: &str =
+        include_str!("../tests/fixtures/macos/mac_pro/system_profiler_displays.json");
+    const M1: &str = include_str!("../tests/fixtures/macos/m1/system_profiler_displays.json");
+
+    #[test]
+    fn test_parse_integrated_and_discrete() {
+        let gpus = parse_displays(INTEL).unwrap();
+        assert_eq!(gpus.len(), 2);
+
+        assert_eq!(gpus[0].model, "Intel UHD Graphics 630");
+        assert_eq!(gpus[0].vendor.as_deref(), Some("Intel"));
+        assert_eq!(gpus[0].vram, Some(1536 * 1024 * 1024));
+        assert!(gpus[0].vram_shared);
+        assert!(gpus[0].displays.is_empty());
+
+        assert_eq!(gpus[1].model, "AMD Radeon Pro 5500M");
+        assert_eq!(gpus[1].vendor.as_deref(), Some("AMD"));
+        assert_eq!(gpus[1].vram, Some(8 * GIB));
+        assert!(!gpus[1].vram_shared);
+        assert_eq!(gpus[1].metal.as_deref(), Some("Metal 3"));
+        assert_eq!(
+            gpus[1].displays,
+            vec![AttachedDisplay {
+                name: "Color LCD".to_owned(),
+                pixels: Some("3072 x 1920".to_owned()),
+                main: true,
+                internal: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_mac_pro_with_egpu() {
+        let gpus = parse_displays(MAC_PRO).unwrap();
+        let models: Vec<&str> = gpus.iter().map(|gpu| gpu.model.as_str()).collect();
+        assert_eq!(
+            models,
+            [
+                "AMD Radeon Pro W5700X",
+                "AMD Radeon Pro W5700X",
+                "AMD Radeon RX 6800 XT"
+            ]
+        );
+        assert_eq!(gpus[2].vram, Some(16 * GIB));
+        assert_eq!(gpus[0].displays.len(), 2);
+        assert!(gpus[1].displays.is_empty());
+        assert!(!gpus[2].displays[0].main);
+    }
+
+    #[test]
+    fn test_parse_apple_silicon() {
+        let gpus = parse_displays(M1).unwrap();
+        assert_eq!(gpus.len(), 1);
+        assert_eq!(gpus[0].model, "Apple M1");
+        assert_eq!(gpus[0].vendor.as_deref(), Some("Apple"));
+        assert_eq!(gpus[0].vram, None);
+        assert_eq!(gpus[0].cores, Some(8));
+        assert_eq!(gpus[0].metal.as_deref(), Some("Metal 3"));
+
+        assert!(parse_displays("Graphics/Displays:\n").is_err());
+    }
+}
@@ -0,0 +1,363 @@
+// ======================== transport.rs ========================
+// Link abstraction between the host and a Flipper Zero
+
+use async_trait::async_trait;
+use btleplug::api::{CharPropFlags, Characteristic, Peripheral as _, ValueNotification, WriteType};
+use btleplug::platform::Peripheral;
+use futures::stream::{Stream, StreamExt};
+use std::error::Error;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::flipper_manager::FLIPPER_CHARACTERISTIC_UUID;
+
+/// Payload size of a single write at the minimum ATT MTU (23 bytes minus the 3 byte header).
+/// btleplug does not expose the negotiated MTU, so this is the only size that is always safe.
+pub const DEFAULT_BLE_MTU: usize = 20;
+
+#[derive(Debug)]
+pub enum TransportError {
+    /// A frame was sent or received before `connect` succeeded
+    NotConnected,
+    /// The link went away (out of range, device powered off, peer closed)
+    Disconnected,
+    /// The device is connected but does not expose the monitor characteristic
+    CharacteristicNotFound(Uuid),
+    /// Any other Bluetooth stack error
+    Ble(btleplug::Error),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::NotConnected => write!(f, "transport is not connected"),
+            TransportError::Disconnected => write!(f, "device disconnected"),
+            TransportError::CharacteristicNotFound(uuid) => {
+                write!(f, "characteristic {} not found on device", uuid)
+            }
+            TransportError::Ble(e) => write!(f, "bluetooth error: {}", e),
+        }
+    }
+}
+
+impl Error for TransportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TransportError::Ble(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<btleplug::Error> for TransportError {
+    fn from(e: btleplug::Error) -> Self {
+        match e {
+            btleplug::Error::NotConnected => TransportError::Disconnected,
+            e => TransportError::Ble(e),
+        }
+    }
+}
+
+/// A frame-oriented link to a Flipper Zero
+#[async_trait]
+pub trait Transport: Send {
+    /// Establish the link; calling it again after a disconnect re-establishes it
+    async fn connect(&mut self) -> Result<(), TransportError>;
+
+    /// Send a single frame; frames larger than `mtu()` may be truncated by the link
+    async fn send_frame(&mut self, frame: &[u8]) -> Result<(), TransportError>;
+
+    /// Wait for the next frame sent by the device
+    async fn recv_frame(&mut self) -> Result<Vec<u8>, TransportError>;
+
+    /// Tear the link down
+    async fn disconnect(&mut self) -> Result<(), TransportError>;
+
+    /// Largest frame that can be delivered in a single write
+    fn mtu(&self) -> usize;
+}
+
+type NotificationStream = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
+
+/// Transport over the Flipper's BLE monitor characteristic
+pub struct BleTransport {
+    peripheral: Peripheral,
+    characteristic_uuid: Uuid,
+    characteristic: Option<Characteristic>,
+    notifications: Option<NotificationStream>,
+    mtu: usize,
+}
+
+impl BleTransport {
+    pub fn new(peripheral: Peripheral) -> Self {
+        BleTransport {
+            peripheral,
+            characteristic_uuid: FLIPPER_CHARACTERISTIC_UUID,
+            characteristic: None,
+            notifications: None,
+            mtu: DEFAULT_BLE_MTU,
+        }
+    }
+
+    /// Write to a different characteristic than the PC Monitor default
+    pub fn with_characteristic(mut self, uuid: Uuid) -> Self {
+        self.characteristic_uuid = uuid;
+        self
+    }
+
+    /// Override the write size when the platform is known to negotiate a larger MTU
+    pub fn with_mtu(mut self, mtu: usize) -> Self {
+        self.mtu = mtu;
+        self
+    }
+
+    pub fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
+    }
+
+    /// Map a failed operation to `Disconnected` if the link is gone
+    async fn classify(&mut self, e: btleplug::Error) -> TransportError {
+        if !self.peripheral.is_connected().await.unwrap_or(false) {
+            self.characteristic = None;
+            self.notifications = None;
+            return TransportError::Disconnected;
+        }
+        e.into()
+    }
+}
+
+#[async_trait]
+impl Transport for BleTransport {
+    async fn connect(&mut self) -> Result<(), TransportError> {
+        if !self.peripheral.is_connected().await? {
+            self.peripheral.connect().await?;
+        }
+        self.peripheral.discover_services().await?;
+
+        let characteristic = self
+            .peripheral
+            .characteristics()
+            .into_iter()
+            .find(|c| c.uuid == self.characteristic_uuid)
+            .ok_or(TransportError::CharacteristicNotFound(
+                self.characteristic_uuid,
+            ))?;
+
+        // Receiving is optional: older Flipper apps only accept writes
+        self.notifications = None;
+        if characteristic.properties.contains(CharPropFlags::NOTIFY) {
+            self.peripheral.subscribe(&characteristic).await?;
+            self.notifications = Some(self.peripheral.notifications().await?);
+        }
+
+        self.characteristic = Some(characteristic);
+        Ok(())
+    }
+
+    async fn send_frame(&mut self, frame: &[u8]) -> Result<(), TransportError> {
+        let characteristic = self
+            .characteristic
+            .as_ref()
+            .ok_or(TransportError::NotConnected)?;
+
+        match self
+            .peripheral
+            .write(characteristic, frame, WriteType::WithoutResponse)
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(e) => Err(self.classify(e).await),
+        }
+    }
+
+    async fn recv_frame(&mut self) -> Result<Vec<u8>, TransportError> {
+        let uuid = self.characteristic_uuid;
+        let notifications = self
+            .notifications
+            .as_mut()
+            .ok_or(TransportError::NotConnected)?;
+
+        while let Some(notification) = notifications.next().await {
+            if notification.uuid == uuid {
+                return Ok(notification.value);
+            }
+        }
+
+        self.characteristic = None;
+        self.notifications = None;
+        Err(TransportError::Disconnected)
+    }
+
+    async fn disconnect(&mut self) -> Result<(), TransportError> {
+        self.characteristic = None;
+        self.notifications = None;
+        if self.peripheral.is_connected().await? {
+            self.peripheral.disconnect().await?;
+        }
+        Ok(())
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+}
+
+/// In-memory transport, paired with a `ChannelPeer` playing the device side
+pub struct ChannelTransport {
+    to_device: mpsc::UnboundedSender<Vec<u8>>,
+    from_device: mpsc::UnboundedReceiver<Vec<u8>>,
+    link_up: Arc<AtomicBool>,
+    connected: bool,
+    mtu: usize,
+}
+
+/// Device end of a `ChannelTransport`
+pub struct ChannelPeer {
+    to_host: mpsc::UnboundedSender<Vec<u8>>,
+    from_host: mpsc::UnboundedReceiver<Vec<u8>>,
+    link_up: Arc<AtomicBool>,
+}
+
+impl ChannelTransport {
+    /// Create a connected host/device pair with the given MTU
+    pub fn pair(mtu: usize) -> (ChannelTransport, ChannelPeer) {
+        let (to_device, from_host) = mpsc::unbounded_channel();
+        let (to_host, from_device) = mpsc::unbounded_channel();
+        let link_up = Arc::new(AtomicBool::new(true));
+
+        let transport = ChannelTransport {
+            to_device,
+            from_device,
+            link_up: link_up.clone(),
+            connected: false,
+            mtu,
+        };
+        let peer = ChannelPeer {
+            to_host,
+            from_host,
+            link_up,
+        };
+        (transport, peer)
+    }
+
+    fn check_link(&mut self) -> Result<(), TransportError> {
+        if !self.connected {
+            return Err(TransportError::NotConnected);
+        }
+        if !self.link_up.load(Ordering::SeqCst) {
+            self.connected = false;
+            return Err(TransportError::Disconnected);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transport for ChannelTransport {
+    async fn connect(&mut self) -> Result<(), TransportError> {
+        if !self.link_up.load(Ordering::SeqCst) || self.to_device.is_closed() {
+            return Err(TransportError::Disconnected);
+        }
+        self.connected = true;
+        Ok(())
+    }
+
+    async fn send_frame(&mut self, frame: &[u8]) -> Result<(), TransportError> {
+        self.check_link()?;
+        self.to_device.send(frame.to_vec()).map_err(|_| {
+            self.connected = false;
+            TransportError::Disconnected
+        })
+    }
+
+    async fn recv_frame(&mut self) -> Result<Vec<u8>, TransportError> {
+        self.check_link()?;
+        match self.from_device.recv().await {
+            Some(frame) => Ok(frame),
+            None => {
+                self.connected = false;
+                Err(TransportError::Disconnected)
+            }
+        }
+    }
+
+    async fn disconnect(&mut self) -> Result<(), TransportError> {
+        self.connected = false;
+        Ok(())
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+}
+
+impl ChannelPeer {
+    /// Wait for the next frame written by the host; `None` once the host is dropped
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        self.from_host.recv().await
+    }
+
+    /// Deliver a frame to the host's `recv_frame`
+    pub fn send(&self, frame: &[u8]) -> bool {
+        self.to_host.send(frame.to_vec()).is_ok()
+    }
+
+    /// Simulate the device going out of range (`false`) or coming back (`true`)
+    pub fn set_link(&self, up: bool) {
+        self.link_up.store(up, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_channel_round_trip() {
+        let (mut transport, mut peer) = ChannelTransport::pair(DEFAULT_BLE_MTU);
+
+        assert!(matches!(
+            transport.send_frame(b"early").await,
+            Err(TransportError::NotConnected)
+        ));
+
+        transport.connect().await.unwrap();
+        transport.send_frame(b"hello").await.unwrap();
+        assert_eq!(peer.recv().await.unwrap(), b"hello");
+
+        assert!(peer.send(b"caps"));
+        assert_eq!(transport.recv_frame().await.unwrap(), b"caps");
+        assert_eq!(transport.mtu(), DEFAULT_BLE_MTU);
+    }
+
+    #[tokio::test]
+    async fn test_channel_link_loss_and_reconnect() {
+        let (mut transport, mut peer) = ChannelTransport::pair(DEFAULT_BLE_MTU);
+        transport.connect().await.unwrap();
+
+        peer.set_link(false);
+        assert!(matches!(
+            transport.send_frame(b"lost").await,
+            Err(TransportError::Disconnected)
+        ));
+        assert!(matches!(
+            transport.connect().await,
+            Err(TransportError::Disconnected)
+        ));
+
+        peer.set_link(true);
+        transport.connect().await.unwrap();
+        transport.send_frame(b"back").await.unwrap();
+        assert_eq!(peer.recv().await.unwrap(), b"back");
+
+        drop(peer);
+        assert!(matches!(
+            transport.send_frame(b"gone").await,
+            Err(TransportError::Disconnected)
+        ));
+    }
+}
@@ -0,0 +1,279 @@
+// ======================== wire.rs ========================
+// Byte-level encodings of SystemInfo for the Flipper
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::system_info::SystemInfo;
+
+/// Size of the packed struct read by the Flipper "PC Monitor" app:
+///
+/// ```c
+/// typedef struct {
+///     uint8_t cpu_usage;
+///     uint16_t ram_max;
+///     uint8_t ram_usage;
+///     char ram_unit[4];
+///     uint8_t gpu_usage;
+///     uint16_t vram_max;
+///     uint8_t vram_usage;
+///     char vram_unit[4];
+/// } __attribute__((packed)) DataStruct;
+/// ```
+pub const PACKED_SYSTEM_INFO_LEN: usize = 16;
+
+/// How a sample is serialized before it is handed to the transport
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// Little-endian packed struct understood by the stock Flipper app
+    #[default]
+    Packed,
+    /// serde_json object, for custom Flipper apps and debugging
+    Json,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "packed" | "binary" => Ok(Encoding::Packed),
+            "json" => Ok(Encoding::Json),
+            other => Err(format!(
+                "unknown encoding '{}' (expected 'packed' or 'json')",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encoding::Packed => write!(f, "packed"),
+            Encoding::Json => write!(f, "json"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The buffer ended before the value was complete
+    UnexpectedEnd,
+    /// The buffer has the wrong size for a fixed-layout value
+    Length { expected: usize, actual: usize },
+    /// The bytes do not form a valid value
+    Invalid(&'static str),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            DecodeError::Length { expected, actual } => {
+                write!(f, "expected {} bytes, got {}", expected, actual)
+            }
+            DecodeError::Invalid(what) => write!(f, "invalid data: {}", what),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Little-endian byte writer
+#[derive(Default)]
+pub struct WireWriter {
+    buf: Vec<u8>,
+}
+
+impl WireWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u8(&mut self, v: u8) -> &mut Self {
+        self.buf.push(v);
+        self
+    }
+
+    pub fn u16(&mut self, v: u16) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn bytes(&mut self, v: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(v);
+        self
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Little-endian byte reader matching `WireWriter`
+pub struct WireReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> WireReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        WireReader { buf }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.buf.len() < n {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn array4(&mut self) -> Result<[u8; 4], DecodeError> {
+        Ok(self.bytes(4)?.try_into().unwrap())
+    }
+}
+
+/// Encode a sample in the PC Monitor app's packed layout
+pub fn encode_packed(info: &SystemInfo) -> Vec<u8> {
+    let mut w = WireWriter::new();
+    w.u8(info.cpu_usage)
+        .u16(info.ram_max)
+        .u8(info.ram_usage)
+        .bytes(&info.ram_unit)
+        .u8(info.gpu_usage)
+        .u16(info.vram_max)
+        .u8(info.vram_usage)
+        .bytes(&info.vram_unit);
+    w.into_inner()
+}
+
+/// Decode the packed layout back into a sample (mirrors what the Flipper reads)
+pub fn decode_packed(data: &[u8]) -> Result<SystemInfo, DecodeError> {
+    if data.len() != PACKED_SYSTEM_INFO_LEN {
+        return Err(DecodeError::Length {
+            expected: PACKED_SYSTEM_INFO_LEN,
+            actual: data.len(),
+        });
+    }
+
+    let mut r = WireReader::new(data);
+    Ok(SystemInfo {
+        cpu_usage: r.u8()?,
+        ram_max: r.u16()?,
+        ram_usage: r.u8()?,
+        ram_unit: r.array4()?,
+        gpu_usage: r.u8()?,
+        vram_max: r.u16()?,
+        vram_usage: r.u8()?,
+        vram_unit: r.array4()?,
+        ..SystemInfo::default()
+    })
+}
+
+/// Keys of the JSON object matching the packed struct
+pub const JSON_KEYS: [&str; 8] = [
+    "cpu_usage",
+    "ram_max",
+    "ram_usage",
+    "ram_unit",
+    "gpu_usage",
+    "vram_max",
+    "vram_usage",
+    "vram_unit",
+];
+
+/// Serialize a sample with the selected encoding; both carry only the packed struct's fields
+pub fn encode(info: &SystemInfo, encoding: Encoding) -> Result<Vec<u8>, serde_json::Error> {
+    match encoding {
+        Encoding::Packed => Ok(encode_packed(info)),
+        Encoding::Json => {
+            let mut value = serde_json::to_value(info)?;
+            if let Some(map) = value.as_object_mut() {
+                map.retain(|key, _| JSON_KEYS.contains(&key.as_str()));
+            }
+            serde_json::to_vec(&value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> SystemInfo {
+        SystemInfo {
+            cpu_usage: 42,
+            ram_max: 0x1234,
+            ram_usage: 63,
+            ram_unit: *b"GB\0\0",
+            gpu_usage: 7,
+            vram_max: 8,
+            vram_usage: 99,
+            vram_unit: *b"MB\0\0",
+            ..SystemInfo::default()
+        }
+    }
+
+    #[test]
+    fn test_packed_layout() {
+        let bytes = encode_packed(&sample());
+        assert_eq!(
+            bytes,
+            [42, 0x34, 0x12, 63, b'G', b'B', 0, 0, 7, 8, 0, 99, b'M', b'B', 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_packed_round_trip() {
+        let info = sample();
+        assert_eq!(decode_packed(&encode_packed(&info)).unwrap(), info);
+        assert_eq!(
+            decode_packed(&[0; 15]),
+            Err(DecodeError::Length {
+                expected: 16,
+                actual: 15
+            })
+        );
+    }
+
+    #[test]
+    fn test_json_leaves_out_gpu_list() {
+        let mut info = sample();
+        info.gpus = vec![Default::default()];
+        let json: serde_json::Value =
+            serde_json::from_slice(&encode(&info, Encoding::Json).unwrap()).unwrap();
+        assert_eq!(json["cpu_usage"], 42);
+        assert_eq!(json.as_object().unwrap().len(), JSON_KEYS.len());
+    }
+
+    #[test]
+    fn test_encoding_from_str() {
+        assert_eq!("JSON".parse::<Encoding>().unwrap(), Encoding::Json);
+        assert_eq!("packed".parse::<Encoding>().unwrap(), Encoding::Packed);
+        assert!("xml".parse::<Encoding>().is_err());
+    }
+}